@@ -0,0 +1,105 @@
+#![allow(dead_code)]
+
+// The parts of libbcm_host the vc4 backend uses, declared through `dynamic_ffi!` so they go
+// through the loader with `dlopen`. Types come from the `videocore` crate, whose own functions
+// call libbcm_host by name.
+
+use std::os::raw::c_int;
+
+use videocore::bcm_host::GraphicsDisplaySize;
+use videocore::dispmanx::{
+  Clamp, DisplayHandle, ElementHandle, Protection, ResourceHandle, Transform, UpdateHandle, VCAlpha,
+};
+use videocore::image::Rect;
+
+pub mod ffi {
+  use super::*;
+
+  dynamic_ffi! {
+    bcm_host;
+    pub fn bcm_host_init();
+    pub fn bcm_host_deinit();
+    pub fn graphics_get_display_size(
+      display_number: u16,
+      width: *mut u32,
+      height: *mut u32,
+    ) -> i32;
+
+    pub fn vc_dispmanx_display_open(device: u32) -> DisplayHandle;
+    pub fn vc_dispmanx_display_close(display: DisplayHandle) -> c_int;
+    pub fn vc_dispmanx_update_start(priority: i32) -> UpdateHandle;
+    pub fn vc_dispmanx_update_submit_sync(update: UpdateHandle) -> c_int;
+    pub fn vc_dispmanx_element_add(
+      update: UpdateHandle,
+      display: DisplayHandle,
+      layer: i32,
+      dest_rect: *mut Rect,
+      src: ResourceHandle,
+      src_rect: *mut Rect,
+      protection: Protection,
+      alpha: *mut VCAlpha,
+      clamp: *mut Clamp,
+      transform: Transform,
+    ) -> ElementHandle;
+  }
+}
+
+pub fn init() {
+  unsafe { ffi::bcm_host_init() };
+}
+
+pub fn deinit() {
+  unsafe { ffi::bcm_host_deinit() };
+}
+
+pub fn graphics_get_display_size(display_number: u16) -> Option<GraphicsDisplaySize> {
+  let mut width: u32 = 0;
+  let mut height: u32 = 0;
+  if unsafe { ffi::graphics_get_display_size(display_number, &mut width, &mut height) } != 0 {
+    return None;
+  }
+  Some(GraphicsDisplaySize { width, height })
+}
+
+pub mod dispmanx {
+  pub use videocore::dispmanx::*;
+
+  use super::*;
+
+  pub fn display_open(device: u32) -> DisplayHandle {
+    unsafe { ffi::vc_dispmanx_display_open(device) }
+  }
+
+  /// `true` on failure, like the `videocore` crate.
+  pub fn display_close(display: DisplayHandle) -> bool {
+    unsafe { ffi::vc_dispmanx_display_close(display) > 0 }
+  }
+
+  pub fn update_start(priority: i32) -> UpdateHandle {
+    unsafe { ffi::vc_dispmanx_update_start(priority) }
+  }
+
+  pub fn update_submit_sync(update: UpdateHandle) -> bool {
+    unsafe { ffi::vc_dispmanx_update_submit_sync(update) > 0 }
+  }
+
+  #[allow(clippy::too_many_arguments)]
+  pub fn element_add(
+    update: UpdateHandle,
+    display: DisplayHandle,
+    layer: i32,
+    dest_rect: *mut Rect,
+    src: ResourceHandle,
+    src_rect: *mut Rect,
+    protection: Protection,
+    alpha: *mut VCAlpha,
+    clamp: *mut Clamp,
+    transform: Transform,
+  ) -> ElementHandle {
+    unsafe {
+      ffi::vc_dispmanx_element_add(
+        update, display, layer, dest_rect, src, src_rect, protection, alpha, clamp, transform,
+      )
+    }
+  }
+}
@@ -0,0 +1,387 @@
+#![allow(dead_code)]
+
+use embedded_graphics_core::draw_target::DrawTarget;
+use embedded_graphics_core::geometry::{Dimensions, OriginDimensions, Point, Size};
+use embedded_graphics_core::pixelcolor::{Rgb888, RgbColor};
+use embedded_graphics_core::primitives::Rectangle;
+use embedded_graphics_core::Pixel;
+use std::convert::Infallible;
+use std::io;
+use std::os::raw::c_void;
+use std::slice;
+
+use crate::dmabuf::{self, DmabufFrame};
+use crate::gbm::gbm_device::{GbmBo, GbmDevice};
+use crate::gbm::mini_gbm as gbm;
+use crate::gbm::mini_gbm::{gbm_bo_flags, gbm_bo_transfer_flags};
+
+/// Memory layouts a `Canvas` can draw into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+  /// `GBM_FORMAT_RGB565`, 16 bits little endian
+  Rgb565,
+  /// `GBM_FORMAT_XRGB8888`, B, G, R, X in memory
+  Xrgb8888,
+}
+
+impl PixelFormat {
+  pub fn from_fourcc(fourcc: u32) -> Option<PixelFormat> {
+    match fourcc {
+      gbm::GBM_FORMAT_RGB565 => Some(PixelFormat::Rgb565),
+      gbm::GBM_FORMAT_XRGB8888 => Some(PixelFormat::Xrgb8888),
+      _ => None,
+    }
+  }
+
+  pub fn fourcc(&self) -> u32 {
+    match self {
+      PixelFormat::Rgb565 => gbm::GBM_FORMAT_RGB565,
+      PixelFormat::Xrgb8888 => gbm::GBM_FORMAT_XRGB8888,
+    }
+  }
+
+  pub fn bytes_per_pixel(&self) -> usize {
+    match self {
+      PixelFormat::Rgb565 => 2,
+      PixelFormat::Xrgb8888 => 4,
+    }
+  }
+
+  fn write(&self, pixel: &mut [u8], color: Rgb888) {
+    match self {
+      PixelFormat::Rgb565 => {
+        let value = ((color.r() as u16 >> 3) << 11)
+          | ((color.g() as u16 >> 2) << 5)
+          | (color.b() as u16 >> 3);
+        pixel.copy_from_slice(&value.to_le_bytes());
+      }
+      PixelFormat::Xrgb8888 => pixel.copy_from_slice(&[color.b(), color.g(), color.r(), 0xff]),
+    }
+  }
+}
+
+/// `embedded-graphics` draw target over mapped pixels (`SoftwareContext::draw_target`,
+/// `LinearBuffer::canvas`), converting `Rgb888` to the buffer's format. Keeps the bounding box
+/// of everything drawn so only that has to be flushed, see `damage`.
+pub struct Canvas<'a> {
+  pixels: &'a mut [u8],
+  width: u32,
+  height: u32,
+  stride: u32,
+  format: PixelFormat,
+  damage: Option<Rectangle>,
+}
+
+impl<'a> Canvas<'a> {
+  /// Panics if `pixels` is too short for `height` rows of `stride` bytes.
+  pub fn new(
+    pixels: &'a mut [u8],
+    width: u32,
+    height: u32,
+    stride: u32,
+    format: PixelFormat,
+  ) -> Canvas<'a> {
+    assert!(stride as usize >= width as usize * format.bytes_per_pixel());
+    assert!(pixels.len() >= stride as usize * height as usize);
+
+    Canvas {
+      pixels,
+      width,
+      height,
+      stride,
+      format,
+      damage: None,
+    }
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  pub fn stride(&self) -> u32 {
+    self.stride
+  }
+
+  pub fn format(&self) -> PixelFormat {
+    self.format
+  }
+
+  /// Bounding box of the pixels drawn so far, `None` if nothing was.
+  pub fn damage(&self) -> Option<Rectangle> {
+    self.damage
+  }
+
+  /// Same as `damage`, and starts tracking from scratch.
+  pub fn take_damage(&mut self) -> Option<Rectangle> {
+    self.damage.take()
+  }
+
+  fn add_damage(&mut self, area: &Rectangle) {
+    let bottom_right = match area.bottom_right() {
+      Some(bottom_right) => bottom_right,
+      None => return,
+    };
+    self.damage = Some(match self.damage {
+      Some(damage) => {
+        let damage_bottom_right = damage.bottom_right().unwrap();
+        Rectangle::with_corners(
+          Point::new(
+            damage.top_left.x.min(area.top_left.x),
+            damage.top_left.y.min(area.top_left.y),
+          ),
+          Point::new(
+            damage_bottom_right.x.max(bottom_right.x),
+            damage_bottom_right.y.max(bottom_right.y),
+          ),
+        )
+      }
+      None => *area,
+    });
+  }
+
+  fn offset(&self, x: u32, y: u32) -> usize {
+    y as usize * self.stride as usize + x as usize * self.format.bytes_per_pixel()
+  }
+}
+
+impl OriginDimensions for Canvas<'_> {
+  fn size(&self) -> Size {
+    Size::new(self.width, self.height)
+  }
+}
+
+impl DrawTarget for Canvas<'_> {
+  type Color = Rgb888;
+  type Error = Infallible;
+
+  fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+  where
+    I: IntoIterator<Item = Pixel<Self::Color>>,
+  {
+    let bytes_per_pixel = self.format.bytes_per_pixel();
+    for Pixel(point, color) in pixels {
+      if point.x < 0 || point.y < 0 || point.x as u32 >= self.width || point.y as u32 >= self.height
+      {
+        continue;
+      }
+      let offset = self.offset(point.x as u32, point.y as u32);
+      self
+        .format
+        .write(&mut self.pixels[offset..offset + bytes_per_pixel], color);
+      self.add_damage(&Rectangle::new(point, Size::new(1, 1)));
+    }
+    Ok(())
+  }
+
+  fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+    let area = area.intersection(&self.bounding_box());
+    if area.is_zero_sized() {
+      return Ok(());
+    }
+
+    // Convert once, then copy row by row
+    let bytes_per_pixel = self.format.bytes_per_pixel();
+    let mut pixel = [0u8; 4];
+    self.format.write(&mut pixel[..bytes_per_pixel], color);
+
+    for y in area.rows() {
+      let start = self.offset(area.top_left.x as u32, y as u32);
+      let end = start + area.size.width as usize * bytes_per_pixel;
+      for target in self.pixels[start..end].chunks_exact_mut(bytes_per_pixel) {
+        target.copy_from_slice(&pixel[..bytes_per_pixel]);
+      }
+    }
+    self.add_damage(&area);
+    Ok(())
+  }
+}
+
+/// A linear GBM buffer mapped for CPU drawing (`GBM_BO_USE_LINEAR | GBM_BO_USE_WRITE`), handed
+/// to the display or GL through `export`. Borrows the device it was allocated on.
+pub struct LinearBuffer<'dev> {
+  bo: GbmBo<'dev>,
+  pixels: *mut u8,
+  map_data: *mut c_void,
+  stride: u32,
+  width: u32,
+  height: u32,
+  format: PixelFormat,
+}
+
+impl<'dev> LinearBuffer<'dev> {
+  pub fn new(
+    gbm_device: &'dev GbmDevice<'_>,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+  ) -> io::Result<LinearBuffer<'dev>> {
+    let bo = gbm_device.create_bo(
+      width,
+      height,
+      format.fourcc(),
+      gbm_bo_flags::GBM_BO_USE_LINEAR | gbm_bo_flags::GBM_BO_USE_WRITE,
+    )?;
+
+    // Mapped for as long as the buffer lives, linear buffers map in place
+    let (pixels, stride, map_data) = gbm::bo_map(
+      bo.as_raw(),
+      0,
+      0,
+      width,
+      height,
+      gbm_bo_transfer_flags::GBM_BO_TRANSFER_READ_WRITE,
+    )
+    .ok_or_else(|| io::Error::other("Couldn't map linear buffer"))?;
+
+    Ok(LinearBuffer {
+      bo,
+      pixels,
+      map_data,
+      stride,
+      width,
+      height,
+      format,
+    })
+  }
+
+  #[inline(always)]
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  #[inline(always)]
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  pub fn canvas(&mut self) -> Canvas<'_> {
+    let pixels = unsafe {
+      slice::from_raw_parts_mut(self.pixels, self.stride as usize * self.height as usize)
+    };
+    Canvas::new(pixels, self.width, self.height, self.stride, self.format)
+  }
+
+  pub fn export(&self) -> io::Result<DmabufFrame<'_>> {
+    dmabuf::export_bo(&self.bo)
+  }
+}
+
+impl Drop for LinearBuffer<'_> {
+  fn drop(&mut self) {
+    gbm::bo_unmap(self.bo.as_raw(), self.map_data);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn pixel(format: PixelFormat, color: Rgb888) -> Vec<u8> {
+    let mut pixel = vec![0u8; format.bytes_per_pixel()];
+    format.write(&mut pixel, color);
+    pixel
+  }
+
+  #[test]
+  fn converts_to_rgb565() {
+    let rgb565 = |color| {
+      let pixel = pixel(PixelFormat::Rgb565, color);
+      u16::from_le_bytes([pixel[0], pixel[1]])
+    };
+    assert_eq!(rgb565(Rgb888::RED), 0xF800);
+    assert_eq!(rgb565(Rgb888::GREEN), 0x07E0);
+    assert_eq!(rgb565(Rgb888::BLUE), 0x001F);
+    assert_eq!(rgb565(Rgb888::WHITE), 0xFFFF);
+    // Low bits are dropped
+    assert_eq!(rgb565(Rgb888::new(0x07, 0x03, 0x07)), 0);
+  }
+
+  #[test]
+  fn converts_to_xrgb8888() {
+    assert_eq!(
+      pixel(PixelFormat::Xrgb8888, Rgb888::RED),
+      [0x00, 0x00, 0xff, 0xff]
+    );
+    assert_eq!(
+      pixel(PixelFormat::Xrgb8888, Rgb888::new(1, 2, 3)),
+      [3, 2, 1, 0xff]
+    );
+  }
+
+  #[test]
+  fn draws_within_rows_and_bounds() {
+    // 2x2 RGB565 with 2 bytes of padding per row
+    let mut pixels = [0xaau8; 12];
+    let mut canvas = Canvas::new(&mut pixels, 2, 2, 6, PixelFormat::Rgb565);
+    canvas
+      .draw_iter([
+        Pixel(Point::new(1, 1), Rgb888::RED),
+        Pixel(Point::new(2, 0), Rgb888::RED),
+        Pixel(Point::new(-1, 0), Rgb888::RED),
+      ])
+      .unwrap();
+    assert_eq!(
+      canvas.damage(),
+      Some(Rectangle::new(Point::new(1, 1), Size::new(1, 1)))
+    );
+    assert_eq!(
+      pixels,
+      [0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0x00, 0xf8, 0xaa, 0xaa]
+    );
+  }
+
+  #[test]
+  fn fills_the_visible_part() {
+    let mut pixels = [0u8; 3 * 2 * 4];
+    let mut canvas = Canvas::new(&mut pixels, 3, 2, 12, PixelFormat::Xrgb8888);
+    let area = Rectangle::new(Point::new(1, -5), Size::new(10, 6));
+    canvas.fill_solid(&area, Rgb888::BLUE).unwrap();
+    assert_eq!(
+      canvas.damage(),
+      Some(Rectangle::new(Point::new(1, 0), Size::new(2, 1)))
+    );
+
+    let blue = [0xff, 0x00, 0x00, 0xff];
+    let black = [0u8; 4];
+    let expected: Vec<u8> = [black, blue, blue, black, black, black].concat();
+    assert_eq!(pixels[..], expected[..]);
+  }
+
+  #[test]
+  fn damage_is_the_union_of_everything_drawn() {
+    let mut pixels = [0u8; 8 * 8 * 4];
+    let mut canvas = Canvas::new(&mut pixels, 8, 8, 32, PixelFormat::Xrgb8888);
+    assert_eq!(canvas.damage(), None);
+
+    canvas
+      .fill_solid(
+        &Rectangle::new(Point::new(1, 1), Size::new(2, 2)),
+        Rgb888::RED,
+      )
+      .unwrap();
+    canvas
+      .draw_iter([Pixel(Point::new(5, 3), Rgb888::RED)])
+      .unwrap();
+    // Nothing drawn, nothing added
+    canvas
+      .fill_solid(&Rectangle::new(Point::new(7, 7), Size::zero()), Rgb888::RED)
+      .unwrap();
+    assert_eq!(
+      canvas.take_damage(),
+      Some(Rectangle::with_corners(Point::new(1, 1), Point::new(5, 3)))
+    );
+    assert_eq!(canvas.damage(), None);
+
+    canvas
+      .draw_iter([Pixel(Point::new(0, 7), Rgb888::RED)])
+      .unwrap();
+    assert_eq!(
+      canvas.damage(),
+      Some(Rectangle::new(Point::new(0, 7), Size::new(1, 1)))
+    );
+  }
+}
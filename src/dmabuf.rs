@@ -0,0 +1,308 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::marker::PhantomData;
+use std::os::unix::io::FromRawFd;
+
+use crate::egl_ffi as egl;
+use crate::egl_image::{self, ffi as gl, DmabufDesc, GLuint, ImportedImage};
+use crate::gbm::gbm_device::{FrontBuffer, GbmBo, GbmSurface};
+use crate::gbm::mini_gbm as gbm;
+use crate::gbm::mini_gbm::gbm_bo_flags;
+use crate::Context;
+
+/// A rendered buffer exported as dmabuf fds, one per plane. The buffer isn't reused for
+/// rendering while the frame is around, the fds themselves stay valid after it's dropped (but
+/// the content may change from then on).
+#[derive(Debug)]
+pub struct DmabufFrame<'a> {
+  pub width: u32,
+  pub height: u32,
+  /// One of the `GBM_FORMAT_*`/`DRM_FORMAT_*` fourccs
+  pub fourcc: u32,
+  /// `DRM_FORMAT_MOD_INVALID` when the driver picked the layout implicitly
+  pub modifier: u64,
+  pub fds: Vec<File>,
+  pub strides: Vec<u32>,
+  pub offsets: Vec<u32>,
+  // Keeps the buffer of an `OffscreenTarget` locked
+  _front: Option<FrontBuffer<'a>>,
+  _guard: PhantomData<&'a mut ()>,
+}
+
+// Owners of a GBM buffer object, the frames exported from them borrow them
+pub(crate) trait AsRawBo {
+  fn as_raw_bo(&self) -> *mut gbm::RawBO;
+}
+
+impl AsRawBo for GbmBo<'_> {
+  fn as_raw_bo(&self) -> *mut gbm::RawBO {
+    self.as_raw()
+  }
+}
+
+impl AsRawBo for FrontBuffer<'_> {
+  fn as_raw_bo(&self) -> *mut gbm::RawBO {
+    self.as_raw()
+  }
+}
+
+pub(crate) fn export_bo<B: AsRawBo>(bo: &B) -> io::Result<DmabufFrame<'_>> {
+  export_raw(bo.as_raw_bo())
+}
+
+// The caller ties the frame to whatever owns `bo`
+fn export_raw<'a>(bo: *mut gbm::RawBO) -> io::Result<DmabufFrame<'a>> {
+  let planes = gbm::bo_get_plane_count(bo).max(1);
+
+  let mut fds = Vec::with_capacity(planes as usize);
+  let mut strides = Vec::with_capacity(planes as usize);
+  let mut offsets = Vec::with_capacity(planes as usize);
+  for plane in 0..planes {
+    let fd = if planes == 1 {
+      gbm::bo_get_fd(bo)
+    } else {
+      gbm::bo_get_fd_for_plane(bo, plane)
+    };
+    if fd < 0 {
+      return Err(io::Error::other(format!(
+        "Couldn't export plane {} of the buffer",
+        plane
+      )));
+    }
+    fds.push(unsafe { File::from_raw_fd(fd) });
+    strides.push(gbm::bo_get_stride_for_plane(bo, plane));
+    offsets.push(gbm::bo_get_offset(bo, plane));
+  }
+
+  Ok(DmabufFrame {
+    width: gbm::bo_get_width(bo),
+    height: gbm::bo_get_height(bo),
+    fourcc: gbm::bo_get_format(bo),
+    modifier: gbm::bo_get_modifier(bo),
+    fds,
+    strides,
+    offsets,
+    _front: None,
+    _guard: PhantomData,
+  })
+}
+
+fn export_front(front: FrontBuffer<'_>) -> io::Result<DmabufFrame<'_>> {
+  let frame = export_raw(front.as_raw())?;
+  Ok(DmabufFrame {
+    _front: Some(front),
+    ..frame
+  })
+}
+
+/// An off-screen GBM surface sharing the context's GL context, for rendering frames that go to
+/// an encoder or another process instead of the display.
+pub struct OffscreenTarget<'ctx> {
+  width: u32,
+  height: u32,
+  egl_display: egl::EGLDisplay,
+  egl_context: egl::EGLContext,
+  egl_surface: egl::EGLSurface,
+  gbm_surface: GbmSurface<'ctx>,
+}
+
+impl<'ctx> OffscreenTarget<'ctx> {
+  pub(crate) fn new(
+    context: &'ctx Context,
+    width: u32,
+    height: u32,
+    format: u32,
+  ) -> OffscreenTarget<'ctx> {
+    let gbm_surface = context
+      .gbm_device()
+      .create_surface(width, height, format, gbm_bo_flags::GBM_BO_USE_RENDERING)
+      .expect("Couldn't create off-screen surface");
+
+    let egl_surface = egl::create_window_surface(
+      context.egl_display(),
+      context.egl_config(),
+      gbm_surface.as_raw() as egl::EGLNativeWindowType,
+      &[],
+    )
+    .expect("Couldn't create off-screen window surface");
+
+    return OffscreenTarget {
+      width,
+      height,
+      egl_display: context.egl_display(),
+      egl_context: context.egl_context(),
+      egl_surface,
+      gbm_surface,
+    };
+  }
+
+  #[inline(always)]
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  #[inline(always)]
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// Directs GL rendering here, `Context::make_current` goes back to the display.
+  pub fn make_current(&self) {
+    egl::make_current(
+      self.egl_display,
+      self.egl_surface,
+      self.egl_surface,
+      self.egl_context,
+    );
+  }
+
+  /// Finishes the frame rendered since the last call and exports it. The target must be
+  /// current, and the previous frame dropped.
+  pub fn export(&mut self) -> io::Result<DmabufFrame<'_>> {
+    egl::swap_buffers(self.egl_display, self.egl_surface);
+
+    let front = self
+      .gbm_surface
+      .lock_front_buffer()
+      .ok_or_else(|| io::Error::other("Couldn't lock off-screen front buffer"))?;
+
+    export_front(front)
+  }
+}
+
+impl Drop for OffscreenTarget<'_> {
+  fn drop(&mut self) {
+    egl::destroy_surface(self.egl_display, self.egl_surface);
+  }
+}
+
+/// A scanout capable GBM buffer GL can render into (through a framebuffer object), for buffers
+/// that get handed around and recycled explicitly, e.g. with `FrameSender`. Drop it with the GL
+/// context current.
+pub struct RenderBuffer<'ctx> {
+  image: Option<ImportedImage<'ctx>>,
+  bo: GbmBo<'ctx>,
+  framebuffer: GLuint,
+  width: u32,
+  height: u32,
+}
+
+impl<'ctx> RenderBuffer<'ctx> {
+  pub(crate) fn new(
+    context: &'ctx Context,
+    width: u32,
+    height: u32,
+    format: u32,
+  ) -> io::Result<RenderBuffer<'ctx>> {
+    let bo = context.gbm_device().create_bo(
+      width,
+      height,
+      format,
+      gbm_bo_flags::GBM_BO_USE_SCANOUT | gbm_bo_flags::GBM_BO_USE_RENDERING,
+    )?;
+
+    let mut buffer = RenderBuffer {
+      image: None,
+      bo,
+      framebuffer: 0,
+      width,
+      height,
+    };
+
+    let image = {
+      let frame = export_bo(&buffer.bo)?;
+      ImportedImage::import_as(
+        context,
+        &DmabufDesc::from_frame(&frame),
+        egl_image::GL_TEXTURE_2D,
+      )?
+    };
+
+    unsafe {
+      gl::glGenFramebuffers(1, &mut buffer.framebuffer);
+      gl::glBindFramebuffer(egl_image::GL_FRAMEBUFFER, buffer.framebuffer);
+      gl::glFramebufferTexture2D(
+        egl_image::GL_FRAMEBUFFER,
+        egl_image::GL_COLOR_ATTACHMENT0,
+        egl_image::GL_TEXTURE_2D,
+        image.texture(),
+        0,
+      );
+      let status = gl::glCheckFramebufferStatus(egl_image::GL_FRAMEBUFFER);
+      gl::glBindFramebuffer(egl_image::GL_FRAMEBUFFER, 0);
+      buffer.image = Some(image);
+
+      if status != egl_image::GL_FRAMEBUFFER_COMPLETE {
+        return Err(io::Error::other(format!(
+          "Incomplete framebuffer: {:#x}",
+          status
+        )));
+      }
+    }
+
+    Ok(buffer)
+  }
+
+  #[inline(always)]
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  #[inline(always)]
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+
+  /// Directs GL rendering here, `unbind` goes back to the current surface.
+  pub fn bind(&self) {
+    unsafe { gl::glBindFramebuffer(egl_image::GL_FRAMEBUFFER, self.framebuffer) };
+  }
+
+  pub fn unbind(&self) {
+    unsafe { gl::glBindFramebuffer(egl_image::GL_FRAMEBUFFER, 0) };
+  }
+
+  /// Submits the rendering queued so far. Whoever reads the buffer next (display, encoder, ...)
+  /// waits for it to finish through the kernel's implicit fencing.
+  pub fn flush(&self) {
+    unsafe { gl::glFlush() };
+  }
+
+  pub fn export(&self) -> io::Result<DmabufFrame<'_>> {
+    export_bo(&self.bo)
+  }
+}
+
+impl Drop for RenderBuffer<'_> {
+  fn drop(&mut self) {
+    unsafe { gl::glDeleteFramebuffers(1, &self.framebuffer) };
+  }
+}
+
+/// A dmabuf imported as a KMS framebuffer, see `Context::import_scanout_buffer`.
+#[derive(Debug, PartialEq)]
+pub struct ScanoutBuffer {
+  pub(crate) fb: u32,
+  pub(crate) handles: Vec<u32>,
+  pub(crate) width: u32,
+  pub(crate) height: u32,
+}
+
+impl ScanoutBuffer {
+  /// KMS framebuffer id
+  pub fn fb(&self) -> u32 {
+    self.fb
+  }
+
+  #[inline(always)]
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  #[inline(always)]
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+}
@@ -0,0 +1,148 @@
+#![allow(dead_code)]
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::Path;
+
+use super::mini_drm::{
+  self as drm, DRMModeConnector, DRMModeCrtc, DRMModeEncoder, DRMModeModeInfo, DRMModePlane,
+  DRMModeRes,
+};
+
+/// A DRM device (`/dev/dri/cardN`), for looking at (and driving) the display hardware without a
+/// `Context`, e.g. to pick a mode or a connector first. `Context::card` gives access to the one
+/// of a live context.
+#[derive(Debug)]
+pub struct Card {
+  file: File,
+}
+
+impl Card {
+  pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Card> {
+    let file = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .custom_flags(libc::O_CLOEXEC)
+      .open(path)?;
+    Ok(Card { file })
+  }
+
+  /// Wraps a device opened elsewhere, e.g. by a `SessionBackend`.
+  pub fn from_file(file: File) -> Card {
+    Card { file }
+  }
+
+  pub fn file(&self) -> &File {
+    &self.file
+  }
+
+  pub fn into_file(self) -> File {
+    self.file
+  }
+
+  /// Ids of the framebuffers, CRTCs, connectors and encoders, and the framebuffer size limits.
+  pub fn resources(&self) -> io::Result<DRMModeRes> {
+    drm::mode_get_resources(&self.file)
+  }
+
+  /// All connectors, connected or not, with their modes (preferred first). Connectors unplugged
+  /// since `resources` (e.g. DisplayPort MST) are left out.
+  pub fn connectors(&self) -> io::Result<Vec<DRMModeConnector>> {
+    let mut connectors = Vec::new();
+    for id in self.resources()?.connectors.iter() {
+      match self.connector(*id) {
+        Ok(connector) => connectors.push(connector),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+      }
+    }
+    Ok(connectors)
+  }
+
+  /// One connector by id, with its modes (preferred first) and the encoder it's on.
+  pub fn connector(&self, id: u32) -> io::Result<DRMModeConnector> {
+    drm::mode_get_connector(&self.file, id)
+  }
+
+  /// All encoders, with the CRTC each one is on (0 when none).
+  pub fn encoders(&self) -> io::Result<Vec<DRMModeEncoder>> {
+    self
+      .resources()?
+      .encoders
+      .iter()
+      .map(|id| drm::mode_get_encoder(&self.file, *id))
+      .collect()
+  }
+
+  /// One CRTC by id, with the framebuffer and mode it's showing.
+  pub fn crtc(&self, id: u32) -> io::Result<DRMModeCrtc> {
+    drm::mode_get_crtc(&self.file, id)
+  }
+
+  /// Makes `planes` list primary and cursor planes too. It's a setting of the open device, so it
+  /// applies to everyone using it, the context included.
+  pub fn enable_universal_planes(&self) -> io::Result<()> {
+    drm::set_client_cap(&self.file, drm::DRM_CLIENT_CAP_UNIVERSAL_PLANES, 1)
+  }
+
+  /// The overlay planes, and the primary and cursor ones after `enable_universal_planes`.
+  pub fn planes(&self) -> io::Result<Vec<DRMModePlane>> {
+    drm::mode_get_plane_resources(&self.file)?
+      .iter()
+      .map(|id| drm::mode_get_plane(&self.file, *id))
+      .collect()
+  }
+
+  /// Framebuffer for a single plane buffer (a GEM handle), e.g. depth 24 and 32 bpp for
+  /// XRGB8888. Remove it with `rm_fb`.
+  pub fn add_fb(
+    &self,
+    width: u32,
+    height: u32,
+    depth: u8,
+    bpp: u8,
+    pitch: u32,
+    handle: u32,
+  ) -> io::Result<u32> {
+    drm::mode_add_fb(&self.file, width, height, depth, bpp, pitch, handle)
+  }
+
+  pub fn rm_fb(&self, fb: u32) -> io::Result<()> {
+    if drm::mode_rm_fb(&self.file, fb) != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+
+  /// Shows `fb` through `connectors`. `None` for `mode` (with fb 0 and no connectors) turns the
+  /// CRTC off. Needs DRM master, i.e. no other display server running.
+  pub fn set_crtc(
+    &self,
+    crtc_id: u32,
+    fb: u32,
+    x: u32,
+    y: u32,
+    connectors: &[u32],
+    mode: Option<&DRMModeModeInfo>,
+  ) -> io::Result<()> {
+    let mode = mode.map(|mode| &mode.raw);
+    if drm::mode_set_crtc(&self.file, crtc_id, fb, x, y, connectors, mode) != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(())
+  }
+}
+
+impl AsRawFd for Card {
+  fn as_raw_fd(&self) -> RawFd {
+    self.file.as_raw_fd()
+  }
+}
+
+impl AsFd for Card {
+  fn as_fd(&self) -> BorrowedFd<'_> {
+    self.file.as_fd()
+  }
+}
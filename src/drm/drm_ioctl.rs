@@ -0,0 +1,455 @@
+#![allow(dead_code)]
+
+// Kernel side of the mode setting interface, from <drm/drm.h> and <drm/drm_mode.h>.
+// These are used where going through libdrm is not an option (e.g. signal handlers), and by
+// the `pure-ioctl` backend of `mini_drm`.
+
+use std::io;
+use std::mem::size_of;
+use std::os::raw::{c_int, c_uint, c_ulong};
+use std::os::unix::io::RawFd;
+
+use super::mini_drm::{RawDRMModeModeInfo, RawDRMVBlank};
+use crate::ioctl::{iow, iowr};
+
+const DRM_IOCTL_BASE: u8 = b'd';
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug)]
+pub struct drm_mode_crtc {
+  pub set_connectors_ptr: u64,
+  pub count_connectors: u32,
+
+  pub crtc_id: u32,
+  pub fb_id: u32,
+
+  pub x: u32,
+  pub y: u32,
+
+  pub gamma_size: u32,
+  pub mode_valid: u32,
+  pub mode: RawDRMModeModeInfo,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug)]
+pub struct drm_mode_crtc_lut {
+  pub crtc_id: u32,
+  pub gamma_size: u32,
+
+  pub red: u64,
+  pub green: u64,
+  pub blue: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_gem_close {
+  pub handle: u32,
+  pub pad: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_create_dumb {
+  pub height: u32,
+  pub width: u32,
+  pub bpp: u32,
+  pub flags: u32,
+
+  // Filled in by the kernel
+  pub handle: u32,
+  pub pitch: u32,
+  pub size: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_map_dumb {
+  pub handle: u32,
+  pub pad: u32,
+  pub offset: u64, //< Fake offset to mmap the device with
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_destroy_dumb {
+  pub handle: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_set_client_cap {
+  pub capability: u64,
+  pub value: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_prime_handle {
+  pub handle: u32,
+  pub flags: u32, //< Only for handle to fd
+  pub fd: i32,
+}
+
+// The `*_ptr` fields below are user pointers to arrays of `count_*` entries. The kernel always
+// fills in the counts, and the arrays when they are big enough.
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_card_res {
+  pub fb_id_ptr: u64,
+  pub crtc_id_ptr: u64,
+  pub connector_id_ptr: u64,
+  pub encoder_id_ptr: u64,
+  pub count_fbs: u32,
+  pub count_crtcs: u32,
+  pub count_connectors: u32,
+  pub count_encoders: u32,
+  pub min_width: u32,
+  pub max_width: u32,
+  pub min_height: u32,
+  pub max_height: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_get_encoder {
+  pub encoder_id: u32,
+  pub encoder_type: u32,
+  pub crtc_id: u32,
+  pub possible_crtcs: u32,
+  pub possible_clones: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_get_connector {
+  pub encoders_ptr: u64,
+  pub modes_ptr: u64,
+  pub props_ptr: u64,
+  pub prop_values_ptr: u64,
+  pub count_modes: u32,
+  pub count_props: u32,
+  pub count_encoders: u32,
+  pub encoder_id: u32,
+  pub connector_id: u32,
+  pub connector_type: u32,
+  pub connector_type_id: u32,
+  pub connection: u32,
+  pub mm_width: u32,
+  pub mm_height: u32,
+  pub subpixel: u32,
+  pub pad: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_fb_cmd {
+  pub fb_id: u32,
+  pub width: u32,
+  pub height: u32,
+  pub pitch: u32,
+  pub bpp: u32,
+  pub depth: u32,
+  pub handle: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_fb_cmd2 {
+  pub fb_id: u32,
+  pub width: u32,
+  pub height: u32,
+  pub pixel_format: u32,
+  pub flags: u32,
+  pub handles: [u32; 4],
+  pub pitches: [u32; 4],
+  pub offsets: [u32; 4],
+  pub modifier: [u64; 4],
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_crtc_page_flip {
+  pub crtc_id: u32,
+  pub fb_id: u32,
+  pub flags: u32,
+  pub reserved: u32,
+  pub user_data: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_fb_dirty_cmd {
+  pub fb_id: u32,
+  pub flags: u32,
+  pub color: u32,
+  pub num_clips: u32,
+  pub clips_ptr: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_get_plane_res {
+  pub plane_id_ptr: u64,
+  pub count_planes: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_get_plane {
+  pub plane_id: u32,
+  pub crtc_id: u32,
+  pub fb_id: u32,
+  pub possible_crtcs: u32,
+  pub gamma_size: u32,
+  pub count_format_types: u32,
+  pub format_type_ptr: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_get_property {
+  pub values_ptr: u64,
+  pub enum_blob_ptr: u64,
+  pub prop_id: u32,
+  pub flags: u32,
+  pub name: [u8; DRM_PROP_NAME_LEN],
+  pub count_values: u32,
+  pub count_enum_blobs: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_property_enum {
+  pub value: u64,
+  pub name: [u8; DRM_PROP_NAME_LEN],
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_obj_get_properties {
+  pub props_ptr: u64,
+  pub prop_values_ptr: u64,
+  pub count_props: u32,
+  pub obj_id: u32,
+  pub obj_type: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_atomic {
+  pub flags: u32,
+  pub count_objs: u32,
+  pub objs_ptr: u64,
+  pub count_props_ptr: u64,
+  pub props_ptr: u64,
+  pub prop_values_ptr: u64,
+  pub reserved: u64,
+  pub user_data: u64,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_create_blob {
+  pub data: u64,
+  pub length: u32,
+
+  // Filled in by the kernel
+  pub blob_id: u32,
+}
+
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct drm_mode_destroy_blob {
+  pub blob_id: u32,
+}
+
+pub const DRM_PROP_NAME_LEN: usize = 32;
+
+pub const DRM_IOCTL_SET_CLIENT_CAP: c_ulong =
+  iow(DRM_IOCTL_BASE, 0x0D, size_of::<drm_set_client_cap>());
+pub const DRM_IOCTL_PRIME_FD_TO_HANDLE: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0x2E, size_of::<drm_prime_handle>());
+pub const DRM_IOCTL_WAIT_VBLANK: c_ulong = iowr(DRM_IOCTL_BASE, 0x3A, size_of::<RawDRMVBlank>());
+pub const DRM_IOCTL_MODE_GETRESOURCES: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xA0, size_of::<drm_mode_card_res>());
+pub const DRM_IOCTL_MODE_GETCRTC: c_ulong = iowr(DRM_IOCTL_BASE, 0xA1, size_of::<drm_mode_crtc>());
+pub const DRM_IOCTL_MODE_GETENCODER: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xA6, size_of::<drm_mode_get_encoder>());
+pub const DRM_IOCTL_MODE_GETCONNECTOR: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xA7, size_of::<drm_mode_get_connector>());
+pub const DRM_IOCTL_MODE_GETPROPERTY: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xAA, size_of::<drm_mode_get_property>());
+pub const DRM_IOCTL_MODE_ADDFB: c_ulong = iowr(DRM_IOCTL_BASE, 0xAE, size_of::<drm_mode_fb_cmd>());
+pub const DRM_IOCTL_MODE_RMFB: c_ulong = iowr(DRM_IOCTL_BASE, 0xAF, size_of::<c_uint>());
+pub const DRM_IOCTL_MODE_PAGE_FLIP: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB0, size_of::<drm_mode_crtc_page_flip>());
+pub const DRM_IOCTL_MODE_DIRTYFB: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB1, size_of::<drm_mode_fb_dirty_cmd>());
+pub const DRM_IOCTL_MODE_GETPLANERESOURCES: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB5, size_of::<drm_mode_get_plane_res>());
+pub const DRM_IOCTL_MODE_GETPLANE: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB6, size_of::<drm_mode_get_plane>());
+pub const DRM_IOCTL_MODE_ADDFB2: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB8, size_of::<drm_mode_fb_cmd2>());
+pub const DRM_IOCTL_MODE_OBJ_GETPROPERTIES: c_ulong = iowr(
+  DRM_IOCTL_BASE,
+  0xB9,
+  size_of::<drm_mode_obj_get_properties>(),
+);
+pub const DRM_IOCTL_MODE_ATOMIC: c_ulong = iowr(DRM_IOCTL_BASE, 0xBC, size_of::<drm_mode_atomic>());
+pub const DRM_IOCTL_MODE_CREATEPROPBLOB: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xBD, size_of::<drm_mode_create_blob>());
+pub const DRM_IOCTL_MODE_DESTROYPROPBLOB: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xBE, size_of::<drm_mode_destroy_blob>());
+
+pub const DRM_IOCTL_GEM_CLOSE: c_ulong = iow(DRM_IOCTL_BASE, 0x09, size_of::<drm_gem_close>());
+pub const DRM_IOCTL_MODE_SETCRTC: c_ulong = iowr(DRM_IOCTL_BASE, 0xA2, size_of::<drm_mode_crtc>());
+pub const DRM_IOCTL_MODE_GETGAMMA: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xA4, size_of::<drm_mode_crtc_lut>());
+pub const DRM_IOCTL_MODE_SETGAMMA: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xA5, size_of::<drm_mode_crtc_lut>());
+pub const DRM_IOCTL_MODE_CREATE_DUMB: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB2, size_of::<drm_mode_create_dumb>());
+pub const DRM_IOCTL_MODE_MAP_DUMB: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB3, size_of::<drm_mode_map_dumb>());
+pub const DRM_IOCTL_MODE_DESTROY_DUMB: c_ulong =
+  iowr(DRM_IOCTL_BASE, 0xB4, size_of::<drm_mode_destroy_dumb>());
+
+/// Plain `ioctl(2)`, retried on `EINTR`/`EAGAIN` like `drmIoctl` does. Async-signal-safe.
+pub unsafe fn drm_ioctl<T>(fd: RawFd, request: c_ulong, arg: *mut T) -> c_int {
+  loop {
+    let ret = libc::ioctl(fd, request as _, arg);
+    if ret == -1 {
+      let errno = *libc::__errno_location();
+      if errno == libc::EINTR || errno == libc::EAGAIN {
+        continue;
+      }
+    }
+    return ret;
+  }
+}
+
+/// Drops a GEM handle, e.g. one from `prime_fd_to_handle`. Same as `drmCloseBufferHandle`.
+pub fn gem_close(fd: RawFd, handle: u32) -> c_int {
+  let mut close = drm_gem_close { handle, pad: 0 };
+  return unsafe { drm_ioctl(fd, DRM_IOCTL_GEM_CLOSE, &mut close) };
+}
+
+/// Allocates a CPU-mappable buffer any KMS driver can scan out.
+pub fn create_dumb(
+  fd: RawFd,
+  width: u32,
+  height: u32,
+  bpp: u32,
+) -> io::Result<drm_mode_create_dumb> {
+  let mut create = drm_mode_create_dumb {
+    width,
+    height,
+    bpp,
+    ..Default::default()
+  };
+  if unsafe { drm_ioctl(fd, DRM_IOCTL_MODE_CREATE_DUMB, &mut create) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(create)
+}
+
+/// Offset to pass to `mmap` on the device fd to get at the buffer's memory.
+pub fn map_dumb(fd: RawFd, handle: u32) -> io::Result<u64> {
+  let mut map = drm_mode_map_dumb {
+    handle,
+    ..Default::default()
+  };
+  if unsafe { drm_ioctl(fd, DRM_IOCTL_MODE_MAP_DUMB, &mut map) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(map.offset)
+}
+
+pub fn destroy_dumb(fd: RawFd, handle: u32) -> c_int {
+  let mut destroy = drm_mode_destroy_dumb { handle };
+  return unsafe { drm_ioctl(fd, DRM_IOCTL_MODE_DESTROY_DUMB, &mut destroy) };
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::mem::size_of;
+
+  // Sizes from <drm/drm.h> and <drm/drm_mode.h>, they're part of the ioctl numbers
+  #[test]
+  fn structs_match_the_kernel_layout() {
+    assert_eq!(size_of::<RawDRMModeModeInfo>(), 68);
+    assert_eq!(size_of::<drm_mode_crtc>(), 104);
+    assert_eq!(size_of::<drm_mode_crtc_lut>(), 32);
+    assert_eq!(size_of::<drm_gem_close>(), 8);
+    assert_eq!(size_of::<drm_mode_create_dumb>(), 32);
+    assert_eq!(size_of::<drm_mode_map_dumb>(), 16);
+    assert_eq!(size_of::<drm_mode_destroy_dumb>(), 4);
+    assert_eq!(size_of::<drm_set_client_cap>(), 16);
+    assert_eq!(size_of::<drm_prime_handle>(), 12);
+    assert_eq!(size_of::<drm_mode_card_res>(), 64);
+    assert_eq!(size_of::<drm_mode_get_encoder>(), 20);
+    assert_eq!(size_of::<drm_mode_get_connector>(), 80);
+    assert_eq!(size_of::<drm_mode_fb_cmd>(), 28);
+    assert_eq!(size_of::<drm_mode_fb_cmd2>(), 104);
+    assert_eq!(size_of::<drm_mode_crtc_page_flip>(), 24);
+    assert_eq!(size_of::<drm_mode_fb_dirty_cmd>(), 24);
+    assert_eq!(size_of::<drm_mode_get_plane_res>(), 16);
+    assert_eq!(size_of::<drm_mode_get_plane>(), 32);
+    assert_eq!(size_of::<drm_mode_get_property>(), 64);
+    assert_eq!(size_of::<drm_mode_property_enum>(), 40);
+    assert_eq!(size_of::<drm_mode_obj_get_properties>(), 32);
+    assert_eq!(size_of::<drm_mode_atomic>(), 56);
+    assert_eq!(size_of::<drm_mode_create_blob>(), 16);
+    assert_eq!(size_of::<drm_mode_destroy_blob>(), 4);
+  }
+
+  #[cfg(target_pointer_width = "64")]
+  #[test]
+  fn vblank_matches_the_kernel_layout() {
+    assert_eq!(size_of::<RawDRMVBlank>(), 24);
+  }
+
+  // As <drm/drm.h> expands them
+  #[test]
+  fn ioctl_numbers_match_the_kernel() {
+    assert_eq!(DRM_IOCTL_SET_CLIENT_CAP, 0x4010_640D);
+    assert_eq!(DRM_IOCTL_MODE_GETRESOURCES, 0xC040_64A0);
+    assert_eq!(DRM_IOCTL_MODE_GETCRTC, 0xC068_64A1);
+    assert_eq!(DRM_IOCTL_MODE_SETCRTC, 0xC068_64A2);
+    assert_eq!(DRM_IOCTL_MODE_GETENCODER, 0xC014_64A6);
+    assert_eq!(DRM_IOCTL_MODE_GETCONNECTOR, 0xC050_64A7);
+    assert_eq!(DRM_IOCTL_MODE_RMFB, 0xC004_64AF);
+    assert_eq!(DRM_IOCTL_MODE_PAGE_FLIP, 0xC018_64B0);
+    assert_eq!(DRM_IOCTL_MODE_ADDFB2, 0xC068_64B8);
+    assert_eq!(DRM_IOCTL_MODE_OBJ_GETPROPERTIES, 0xC020_64B9);
+    assert_eq!(DRM_IOCTL_MODE_ATOMIC, 0xC038_64BC);
+  }
+}
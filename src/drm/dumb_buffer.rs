@@ -0,0 +1,104 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+
+use super::drm_ioctl;
+use super::mini_drm as drm;
+
+const DEPTH: u8 = 24;
+const BPP: u8 = 32;
+
+// A mapped dumb buffer and the framebuffer wrapping it, XRGB8888. Destroyed explicitly, as that
+// needs the device.
+pub(crate) struct DumbBuffer {
+  pub handle: u32,
+  pub fb: u32,
+  pub pitch: u32,
+  pub size: usize,
+  map: *mut u8,
+}
+
+impl DumbBuffer {
+  pub fn new(device: &File, width: u32, height: u32) -> io::Result<DumbBuffer> {
+    let fd = device.as_raw_fd();
+    let created = drm_ioctl::create_dumb(fd, width, height, BPP as u32)?;
+
+    let mut buffer = DumbBuffer {
+      handle: created.handle,
+      fb: 0,
+      pitch: created.pitch,
+      size: created.size as usize,
+      map: ptr::null_mut(),
+    };
+
+    let offset = match drm_ioctl::map_dumb(fd, buffer.handle) {
+      Ok(offset) => offset,
+      Err(error) => {
+        buffer.destroy(device);
+        return Err(error);
+      }
+    };
+    let map = unsafe {
+      libc::mmap(
+        ptr::null_mut(),
+        buffer.size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_SHARED,
+        fd,
+        offset as libc::off_t,
+      )
+    };
+    if map == libc::MAP_FAILED {
+      let error = io::Error::last_os_error();
+      buffer.destroy(device);
+      return Err(error);
+    }
+    buffer.map = map as *mut u8;
+
+    match drm::mode_add_fb(
+      device,
+      width,
+      height,
+      DEPTH,
+      BPP,
+      buffer.pitch,
+      buffer.handle,
+    ) {
+      Ok(fb) => buffer.fb = fb,
+      Err(error) => {
+        buffer.destroy(device);
+        return Err(error);
+      }
+    }
+
+    // Start out black instead of with whatever the driver had lying around
+    unsafe { ptr::write_bytes(buffer.map, 0, buffer.size) };
+
+    Ok(buffer)
+  }
+
+  pub fn pixels(&mut self) -> &mut [u8] {
+    unsafe { slice::from_raw_parts_mut(self.map, self.size) }
+  }
+
+  pub fn destroy(&mut self, device: &File) {
+    if self.fb != 0 {
+      drm::mode_rm_fb(device, self.fb);
+      self.fb = 0;
+    }
+    if !self.map.is_null() {
+      unsafe { libc::munmap(self.map as *mut libc::c_void, self.size) };
+      self.map = ptr::null_mut();
+    }
+    if drm_ioctl::destroy_dumb(device.as_raw_fd(), self.handle) != 0 {
+      println!(
+        "Error destroying dumb buffer: {}",
+        io::Error::last_os_error()
+      );
+    }
+  }
+}
@@ -0,0 +1,218 @@
+#![allow(dead_code, non_snake_case)]
+
+// A stand-in for the libdrm getters in tests, with the same signatures as `ioctl_ffi`. While a
+// `FakeDrm` is installed on the calling thread the getters hand out its objects (or fail with its
+// errno) and count the frees, everything else goes to the real backend. No syscalls on the fake
+// path, so the tests using it run under Miri.
+
+use std::cell::RefCell;
+use std::os::raw::c_int;
+use std::os::unix::io::RawFd;
+use std::ptr;
+
+use super::mini_drm::libdrm;
+use super::mini_drm::{
+  DRMModeConnection, DRMModeSubPixel, RawDRMModeConnector, RawDRMModeCrtc, RawDRMModeEncoder,
+  RawDRMModeModeInfo, RawDRMModeRes,
+};
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FakeDrm {
+  /// Getters return null with this errno, 0 for a failure that doesn't set one
+  pub error: Option<c_int>,
+  /// Null pointers for empty arrays instead of dangling ones, as libdrm does
+  pub null_arrays: bool,
+
+  pub fbs: Vec<u32>,
+  pub crtcs: Vec<u32>,
+  pub connectors: Vec<u32>,
+  pub encoders: Vec<u32>,
+  pub modes: Vec<RawDRMModeModeInfo>,
+  pub props: Vec<u32>,
+  pub prop_values: Vec<u64>,
+
+  /// Objects handed out and freed so far
+  pub gets: usize,
+  pub frees: usize,
+}
+
+thread_local! {
+  static FAKE: RefCell<Option<FakeDrm>> = const { RefCell::new(None) };
+}
+
+/// Routes the getters of this thread to `fake` until `uninstall`.
+pub(crate) fn install(fake: FakeDrm) {
+  FAKE.with(|current| *current.borrow_mut() = Some(fake));
+}
+
+/// The fake with its counters, the real backend from now on.
+pub(crate) fn uninstall() -> FakeDrm {
+  FAKE
+    .with(|current| current.borrow_mut().take())
+    .expect("No fake libdrm installed")
+}
+
+fn installed() -> bool {
+  FAKE.with(|current| current.borrow().is_some())
+}
+
+// `None` when not installed, a null pointer when failing, `make`'s object otherwise
+fn get<T>(make: impl FnOnce(&FakeDrm) -> *const T) -> Option<*const T> {
+  FAKE.with(|current| {
+    let mut current = current.borrow_mut();
+    let fake = current.as_mut()?;
+    if let Some(errno) = fake.error {
+      unsafe { *libc::__errno_location() = errno };
+      return Some(ptr::null());
+    }
+    fake.gets += 1;
+    Some(make(fake))
+  })
+}
+
+#[repr(C)]
+struct Owned<T, S> {
+  raw: T,
+  storage: S,
+}
+
+fn into_raw<T, S>(raw: T, storage: S) -> *const T {
+  Box::into_raw(Box::new(Owned { raw, storage })) as *const T
+}
+
+// Whether `ptr` was the fake's, in which case it's gone now
+unsafe fn free<T, S>(ptr: *const T) -> bool {
+  if !installed() {
+    return false;
+  }
+  FAKE.with(|current| current.borrow_mut().as_mut().unwrap().frees += 1);
+  if !ptr.is_null() {
+    drop(Box::from_raw(ptr as *mut Owned<T, S>));
+  }
+  true
+}
+
+fn array_ptr<T>(fake: &FakeDrm, array: &[T]) -> *const T {
+  if array.is_empty() && fake.null_arrays {
+    return ptr::null();
+  }
+  array.as_ptr()
+}
+
+type ResourcesStorage = (Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>);
+
+pub unsafe fn drmModeGetResources(fd: RawFd) -> *const RawDRMModeRes {
+  let fake = get(|fake| {
+    let storage: ResourcesStorage = (
+      fake.fbs.clone(),
+      fake.crtcs.clone(),
+      fake.connectors.clone(),
+      fake.encoders.clone(),
+    );
+    let raw = RawDRMModeRes {
+      count_fbs: storage.0.len() as c_int,
+      fbs: array_ptr(fake, &storage.0),
+      count_crtcs: storage.1.len() as c_int,
+      crtcs: array_ptr(fake, &storage.1),
+      count_connectors: storage.2.len() as c_int,
+      connectors: array_ptr(fake, &storage.2),
+      count_encoders: storage.3.len() as c_int,
+      encoders: array_ptr(fake, &storage.3),
+      min_width: 0,
+      max_width: 4096,
+      min_height: 0,
+      max_height: 4096,
+    };
+    into_raw(raw, storage)
+  });
+  fake.unwrap_or_else(|| libdrm::drmModeGetResources(fd))
+}
+
+pub unsafe fn drmModeFreeResources(ptr: *const RawDRMModeRes) {
+  if !free::<RawDRMModeRes, ResourcesStorage>(ptr) {
+    libdrm::drmModeFreeResources(ptr);
+  }
+}
+
+type ConnectorStorage = (Vec<RawDRMModeModeInfo>, Vec<u32>, Vec<u64>, Vec<u32>);
+
+pub unsafe fn drmModeGetConnector(fd: RawFd, connectorId: u32) -> *const RawDRMModeConnector {
+  let fake = get(|fake| {
+    let storage: ConnectorStorage = (
+      fake.modes.clone(),
+      fake.props.clone(),
+      fake.prop_values.clone(),
+      fake.encoders.clone(),
+    );
+    let raw = RawDRMModeConnector {
+      connector_id: connectorId,
+      encoder_id: fake.encoders.first().copied().unwrap_or(0),
+      connector_type: 0,
+      connector_type_id: 1,
+      connection: DRMModeConnection::DRM_MODE_CONNECTED,
+      mmWidth: 0,
+      mmHeight: 0,
+      subpixel: DRMModeSubPixel::DRM_MODE_SUBPIXEL_UNKNOWN,
+      count_modes: storage.0.len() as c_int,
+      modes: array_ptr(fake, &storage.0),
+      count_props: storage.1.len() as c_int,
+      props: array_ptr(fake, &storage.1),
+      prop_values: array_ptr(fake, &storage.2),
+      count_encoders: storage.3.len() as c_int,
+      encoders: array_ptr(fake, &storage.3),
+    };
+    into_raw(raw, storage)
+  });
+  fake.unwrap_or_else(|| libdrm::drmModeGetConnector(fd, connectorId))
+}
+
+pub unsafe fn drmModeFreeConnector(ptr: *const RawDRMModeConnector) {
+  if !free::<RawDRMModeConnector, ConnectorStorage>(ptr) {
+    libdrm::drmModeFreeConnector(ptr);
+  }
+}
+
+pub unsafe fn drmModeGetEncoder(fd: RawFd, encoderId: u32) -> *const RawDRMModeEncoder {
+  let fake = get(|fake| {
+    let raw = RawDRMModeEncoder {
+      encoder_id: encoderId,
+      encoder_type: 0,
+      crtc_id: fake.crtcs.first().copied().unwrap_or(0),
+      possible_crtcs: (1 << fake.crtcs.len()) - 1,
+      possible_clones: 0,
+    };
+    into_raw(raw, ())
+  });
+  fake.unwrap_or_else(|| libdrm::drmModeGetEncoder(fd, encoderId))
+}
+
+pub unsafe fn drmModeFreeEncoder(ptr: *const RawDRMModeEncoder) {
+  if !free::<RawDRMModeEncoder, ()>(ptr) {
+    libdrm::drmModeFreeEncoder(ptr);
+  }
+}
+
+pub unsafe fn drmModeGetCrtc(fd: RawFd, crtcId: u32) -> *const RawDRMModeCrtc {
+  let fake = get(|fake| {
+    let mode = fake.modes.first().copied();
+    let raw = RawDRMModeCrtc {
+      crtc_id: crtcId,
+      buffer_id: fake.fbs.first().copied().unwrap_or(0),
+      x: 0,
+      y: 0,
+      width: mode.map_or(0, |mode| mode.hdisplay as u32),
+      height: mode.map_or(0, |mode| mode.vdisplay as u32),
+      mode_valid: mode.is_some() as c_int,
+      mode: mode.unwrap_or_else(|| unsafe { std::mem::zeroed() }),
+      gamma_size: 256,
+    };
+    into_raw(raw, ())
+  });
+  fake.unwrap_or_else(|| libdrm::drmModeGetCrtc(fd, crtcId))
+}
+
+pub unsafe fn drmModeFreeCrtc(ptr: *const RawDRMModeCrtc) {
+  if !free::<RawDRMModeCrtc, ()>(ptr) {
+    libdrm::drmModeFreeCrtc(ptr);
+  }
+}
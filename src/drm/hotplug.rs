@@ -0,0 +1,114 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+// Kernel uevents multicast group, udev's own (rewritten) events go to group 2
+const UEVENT_KERNEL_GROUP: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HotplugEvent {
+  /// Device node below `/dev`, e.g. `dri/card0`
+  pub devname: String,
+  /// Connector that changed, when the kernel says (newer kernels only)
+  pub connector: Option<u32>,
+}
+
+/// Listens for DRM connector hotplug uevents (monitor plugged, unplugged, or EDID changed)
+/// straight from the kernel, no udev needed. The fd is readable when events are pending.
+pub struct HotplugMonitor {
+  socket: File,
+}
+
+impl HotplugMonitor {
+  pub fn new() -> io::Result<HotplugMonitor> {
+    let socket = unsafe {
+      let fd = libc::socket(
+        libc::AF_NETLINK,
+        libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+        libc::NETLINK_KOBJECT_UEVENT,
+      );
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      File::from_raw_fd(fd)
+    };
+
+    let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    address.nl_groups = UEVENT_KERNEL_GROUP;
+    if unsafe {
+      libc::bind(
+        socket.as_raw_fd(),
+        &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+        mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+      )
+    } < 0
+    {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(HotplugMonitor { socket })
+  }
+
+  /// Reads whatever is pending, never blocks.
+  pub fn dispatch(&mut self) -> io::Result<Vec<HotplugEvent>> {
+    let mut buffer = [0u8; 8192];
+    let mut events = Vec::new();
+
+    loop {
+      let count = match self.socket.read(&mut buffer) {
+        Ok(count) => count,
+        Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+        Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+        Err(error) => return Err(error),
+      };
+
+      if let Some(event) = parse_uevent(&buffer[..count]) {
+        events.push(event);
+      }
+    }
+
+    Ok(events)
+  }
+}
+
+impl AsRawFd for HotplugMonitor {
+  fn as_raw_fd(&self) -> RawFd {
+    self.socket.as_raw_fd()
+  }
+}
+
+// "ACTION@DEVPATH\0KEY=VALUE\0KEY=VALUE\0..."
+fn parse_uevent(message: &[u8]) -> Option<HotplugEvent> {
+  let mut subsystem = None;
+  let mut hotplug = false;
+  let mut devname = None;
+  let mut connector = None;
+
+  for field in message.split(|byte| *byte == 0).skip(1) {
+    let field = match std::str::from_utf8(field) {
+      Ok(field) => field,
+      Err(_) => continue,
+    };
+    let mut key_value = field.splitn(2, '=');
+    match (key_value.next(), key_value.next()) {
+      (Some("SUBSYSTEM"), Some(value)) => subsystem = Some(value),
+      (Some("HOTPLUG"), Some(value)) => hotplug = value == "1",
+      (Some("DEVNAME"), Some(value)) => devname = Some(value.to_string()),
+      (Some("CONNECTOR"), Some(value)) => connector = value.parse().ok(),
+      _ => {}
+    }
+  }
+
+  if subsystem != Some("drm") || !hotplug {
+    return None;
+  }
+
+  Some(HotplugEvent {
+    devname: devname.unwrap_or_default(),
+    connector,
+  })
+}
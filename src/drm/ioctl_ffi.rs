@@ -0,0 +1,624 @@
+#![allow(dead_code, non_snake_case, clippy::too_many_arguments)]
+
+// The `pure-ioctl` backend: the libdrm functions `mini_drm` uses, written on top of the raw
+// ioctls, with the same signatures and behaviour (null or non-zero and `errno` on failure). What
+// the getters return is heap allocated here and only ever released by the matching `drmModeFree*`.
+
+use std::mem;
+use std::os::raw::{c_int, c_uint, c_void};
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::slice;
+
+use super::drm_ioctl::*;
+use super::mini_drm::{
+  DRMModeConnection, DRMModeSubPixel, RawDRMClipRect, RawDRMModeConnector, RawDRMModeCrtc,
+  RawDRMModeEncoder, RawDRMModeModeInfo, RawDRMModePlane, RawDRMModePlaneRes, RawDRMModeRes,
+  RawDRMVBlank,
+};
+
+// A libdrm-style struct and the arrays it points into. `raw` comes first, so a pointer to it is
+// a pointer to the whole allocation.
+#[repr(C)]
+struct Owned<T, S> {
+  raw: T,
+  storage: S,
+}
+
+fn into_raw<T, S>(raw: T, storage: S) -> *const T {
+  Box::into_raw(Box::new(Owned { raw, storage })) as *const T
+}
+
+unsafe fn free<T, S>(ptr: *const T) {
+  if !ptr.is_null() {
+    drop(Box::from_raw(ptr as *mut Owned<T, S>));
+  }
+}
+
+// Room for `count` entries, pointed at by an ioctl argument
+fn array<T: Copy>(count: u32, zero: T) -> Vec<T> {
+  vec![zero; count as usize]
+}
+
+fn user_ptr<T>(array: &mut Vec<T>) -> u64 {
+  array.as_mut_ptr() as usize as u64
+}
+
+// Whether the kernel had no more than `array` can hold, trimming it to what was filled in.
+// Objects can come and go (hotplug) between asking for the counts and for the arrays.
+fn fits<T>(array: &mut Vec<T>, count: u32) -> bool {
+  if count as usize > array.len() {
+    return false;
+  }
+  array.truncate(count as usize);
+  true
+}
+
+unsafe fn ioctl<T>(fd: RawFd, request: libc::c_ulong, arg: &mut T) -> c_int {
+  drm_ioctl(fd, request, arg as *mut T)
+}
+
+fn zeroed_mode() -> RawDRMModeModeInfo {
+  unsafe { mem::zeroed() }
+}
+
+type ResourcesStorage = (Vec<u32>, Vec<u32>, Vec<u32>, Vec<u32>);
+
+pub unsafe fn drmModeGetResources(fd: RawFd) -> *const RawDRMModeRes {
+  let mut res;
+  let (mut fbs, mut crtcs, mut connectors, mut encoders);
+  loop {
+    res = drm_mode_card_res::default();
+    if ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res) != 0 {
+      return ptr::null();
+    }
+
+    fbs = array(res.count_fbs, 0u32);
+    crtcs = array(res.count_crtcs, 0u32);
+    connectors = array(res.count_connectors, 0u32);
+    encoders = array(res.count_encoders, 0u32);
+    res.fb_id_ptr = user_ptr(&mut fbs);
+    res.crtc_id_ptr = user_ptr(&mut crtcs);
+    res.connector_id_ptr = user_ptr(&mut connectors);
+    res.encoder_id_ptr = user_ptr(&mut encoders);
+    if ioctl(fd, DRM_IOCTL_MODE_GETRESOURCES, &mut res) != 0 {
+      return ptr::null();
+    }
+
+    if fits(&mut fbs, res.count_fbs)
+      && fits(&mut crtcs, res.count_crtcs)
+      && fits(&mut connectors, res.count_connectors)
+      && fits(&mut encoders, res.count_encoders)
+    {
+      break;
+    }
+  }
+
+  let raw = RawDRMModeRes {
+    count_fbs: fbs.len() as c_int,
+    fbs: fbs.as_ptr(),
+    count_crtcs: crtcs.len() as c_int,
+    crtcs: crtcs.as_ptr(),
+    count_connectors: connectors.len() as c_int,
+    connectors: connectors.as_ptr(),
+    count_encoders: encoders.len() as c_int,
+    encoders: encoders.as_ptr(),
+    min_width: res.min_width,
+    max_width: res.max_width,
+    min_height: res.min_height,
+    max_height: res.max_height,
+  };
+  return into_raw(raw, (fbs, crtcs, connectors, encoders));
+}
+
+pub unsafe fn drmModeFreeResources(ptr: *const RawDRMModeRes) {
+  free::<RawDRMModeRes, ResourcesStorage>(ptr);
+}
+
+pub unsafe fn drmModeGetCrtc(fd: RawFd, crtcId: u32) -> *const RawDRMModeCrtc {
+  let mut crtc = drm_mode_crtc {
+    set_connectors_ptr: 0,
+    count_connectors: 0,
+    crtc_id: crtcId,
+    fb_id: 0,
+    x: 0,
+    y: 0,
+    gamma_size: 0,
+    mode_valid: 0,
+    mode: zeroed_mode(),
+  };
+  if ioctl(fd, DRM_IOCTL_MODE_GETCRTC, &mut crtc) != 0 {
+    return ptr::null();
+  }
+
+  let (width, height) = if crtc.mode_valid != 0 {
+    (crtc.mode.hdisplay as u32, crtc.mode.vdisplay as u32)
+  } else {
+    (0, 0)
+  };
+  let raw = RawDRMModeCrtc {
+    crtc_id: crtc.crtc_id,
+    buffer_id: crtc.fb_id,
+    x: crtc.x,
+    y: crtc.y,
+    width,
+    height,
+    mode_valid: crtc.mode_valid as c_int,
+    mode: crtc.mode,
+    gamma_size: crtc.gamma_size as c_int,
+  };
+  return into_raw(raw, ());
+}
+
+pub unsafe fn drmModeFreeCrtc(ptr: *const RawDRMModeCrtc) {
+  free::<RawDRMModeCrtc, ()>(ptr);
+}
+
+pub unsafe fn drmModeGetEncoder(fd: RawFd, encoderId: u32) -> *const RawDRMModeEncoder {
+  let mut encoder = drm_mode_get_encoder {
+    encoder_id: encoderId,
+    ..Default::default()
+  };
+  if ioctl(fd, DRM_IOCTL_MODE_GETENCODER, &mut encoder) != 0 {
+    return ptr::null();
+  }
+
+  let raw = RawDRMModeEncoder {
+    encoder_id: encoder.encoder_id,
+    encoder_type: encoder.encoder_type,
+    crtc_id: encoder.crtc_id,
+    possible_crtcs: encoder.possible_crtcs,
+    possible_clones: encoder.possible_clones,
+  };
+  return into_raw(raw, ());
+}
+
+pub unsafe fn drmModeFreeEncoder(ptr: *const RawDRMModeEncoder) {
+  free::<RawDRMModeEncoder, ()>(ptr);
+}
+
+fn connection(value: u32) -> DRMModeConnection {
+  match value {
+    1 => DRMModeConnection::DRM_MODE_CONNECTED,
+    2 => DRMModeConnection::DRM_MODE_DISCONNECTED,
+    _ => DRMModeConnection::DRM_MODE_UNKNOWNCONNECTION,
+  }
+}
+
+fn subpixel(value: u32) -> DRMModeSubPixel {
+  match value {
+    2 => DRMModeSubPixel::DRM_MODE_SUBPIXEL_HORIZONTAL_RGB,
+    3 => DRMModeSubPixel::DRM_MODE_SUBPIXEL_HORIZONTAL_BGR,
+    4 => DRMModeSubPixel::DRM_MODE_SUBPIXEL_VERTICAL_RGB,
+    5 => DRMModeSubPixel::DRM_MODE_SUBPIXEL_VERTICAL_BGR,
+    6 => DRMModeSubPixel::DRM_MODE_SUBPIXEL_NONE,
+    _ => DRMModeSubPixel::DRM_MODE_SUBPIXEL_UNKNOWN,
+  }
+}
+
+type ConnectorStorage = (Vec<RawDRMModeModeInfo>, Vec<u32>, Vec<u64>, Vec<u32>);
+
+/// Probes the connector (asks the display for its modes) like libdrm does, so it can be slow.
+pub unsafe fn drmModeGetConnector(fd: RawFd, connectorId: u32) -> *const RawDRMModeConnector {
+  let mut conn;
+  let (mut modes, mut props, mut prop_values, mut encoders);
+  loop {
+    // No room for modes makes the kernel probe
+    conn = drm_mode_get_connector {
+      connector_id: connectorId,
+      ..Default::default()
+    };
+    if ioctl(fd, DRM_IOCTL_MODE_GETCONNECTOR, &mut conn) != 0 {
+      return ptr::null();
+    }
+
+    modes = array(conn.count_modes, zeroed_mode());
+    props = array(conn.count_props, 0u32);
+    prop_values = array(conn.count_props, 0u64);
+    encoders = array(conn.count_encoders, 0u32);
+    conn.modes_ptr = user_ptr(&mut modes);
+    conn.props_ptr = user_ptr(&mut props);
+    conn.prop_values_ptr = user_ptr(&mut prop_values);
+    conn.encoders_ptr = user_ptr(&mut encoders);
+    if ioctl(fd, DRM_IOCTL_MODE_GETCONNECTOR, &mut conn) != 0 {
+      return ptr::null();
+    }
+
+    if fits(&mut modes, conn.count_modes)
+      && fits(&mut props, conn.count_props)
+      && fits(&mut prop_values, conn.count_props)
+      && fits(&mut encoders, conn.count_encoders)
+    {
+      break;
+    }
+  }
+
+  let raw = RawDRMModeConnector {
+    connector_id: conn.connector_id,
+    encoder_id: conn.encoder_id,
+    connector_type: conn.connector_type,
+    connector_type_id: conn.connector_type_id,
+    connection: connection(conn.connection),
+    mmWidth: conn.mm_width,
+    mmHeight: conn.mm_height,
+    subpixel: subpixel(conn.subpixel),
+    count_modes: modes.len() as c_int,
+    modes: modes.as_ptr(),
+    count_props: props.len() as c_int,
+    props: props.as_ptr(),
+    prop_values: prop_values.as_ptr(),
+    count_encoders: encoders.len() as c_int,
+    encoders: encoders.as_ptr(),
+  };
+  return into_raw(raw, (modes, props, prop_values, encoders));
+}
+
+pub unsafe fn drmModeFreeConnector(ptr: *const RawDRMModeConnector) {
+  free::<RawDRMModeConnector, ConnectorStorage>(ptr);
+}
+
+pub unsafe fn drmModeGetPlaneResources(fd: RawFd) -> *const RawDRMModePlaneRes {
+  let mut res;
+  let mut planes;
+  loop {
+    res = drm_mode_get_plane_res::default();
+    if ioctl(fd, DRM_IOCTL_MODE_GETPLANERESOURCES, &mut res) != 0 {
+      return ptr::null();
+    }
+
+    planes = array(res.count_planes, 0u32);
+    res.plane_id_ptr = user_ptr(&mut planes);
+    if ioctl(fd, DRM_IOCTL_MODE_GETPLANERESOURCES, &mut res) != 0 {
+      return ptr::null();
+    }
+
+    if fits(&mut planes, res.count_planes) {
+      break;
+    }
+  }
+
+  let raw = RawDRMModePlaneRes {
+    count_planes: planes.len() as u32,
+    planes: planes.as_ptr(),
+  };
+  return into_raw(raw, planes);
+}
+
+pub unsafe fn drmModeFreePlaneResources(ptr: *const RawDRMModePlaneRes) {
+  free::<RawDRMModePlaneRes, Vec<u32>>(ptr);
+}
+
+pub unsafe fn drmModeGetPlane(fd: RawFd, plane_id: u32) -> *const RawDRMModePlane {
+  let mut plane;
+  let mut formats;
+  loop {
+    plane = drm_mode_get_plane {
+      plane_id,
+      ..Default::default()
+    };
+    if ioctl(fd, DRM_IOCTL_MODE_GETPLANE, &mut plane) != 0 {
+      return ptr::null();
+    }
+
+    formats = array(plane.count_format_types, 0u32);
+    plane.format_type_ptr = user_ptr(&mut formats);
+    if ioctl(fd, DRM_IOCTL_MODE_GETPLANE, &mut plane) != 0 {
+      return ptr::null();
+    }
+
+    if fits(&mut formats, plane.count_format_types) {
+      break;
+    }
+  }
+
+  // The kernel doesn't report where the plane is, libdrm leaves these at 0 too
+  let raw = RawDRMModePlane {
+    count_formats: formats.len() as u32,
+    formats: formats.as_ptr(),
+    plane_id: plane.plane_id,
+    crtc_id: plane.crtc_id,
+    fb_id: plane.fb_id,
+    crtc_x: 0,
+    crtc_y: 0,
+    x: 0,
+    y: 0,
+    possible_crtcs: plane.possible_crtcs,
+    gamma_size: plane.gamma_size,
+  };
+  return into_raw(raw, formats);
+}
+
+pub unsafe fn drmModeFreePlane(ptr: *const RawDRMModePlane) {
+  free::<RawDRMModePlane, Vec<u32>>(ptr);
+}
+
+pub unsafe fn drmSetClientCap(fd: RawFd, capability: u64, value: u64) -> c_int {
+  let mut cap = drm_set_client_cap { capability, value };
+  return ioctl(fd, DRM_IOCTL_SET_CLIENT_CAP, &mut cap);
+}
+
+pub unsafe fn drmModeAddFB(
+  fd: RawFd,
+  width: u32,
+  height: u32,
+  depth: u8,
+  bpp: u8,
+  pitch: u32,
+  bo_handle: u32,
+  buf_id: *mut u32,
+) -> c_int {
+  let mut cmd = drm_mode_fb_cmd {
+    fb_id: 0,
+    width,
+    height,
+    pitch,
+    bpp: bpp as u32,
+    depth: depth as u32,
+    handle: bo_handle,
+  };
+  let ret = ioctl(fd, DRM_IOCTL_MODE_ADDFB, &mut cmd);
+  if ret == 0 {
+    *buf_id = cmd.fb_id;
+  }
+  return ret;
+}
+
+pub unsafe fn drmModeAddFB2WithModifiers(
+  fd: RawFd,
+  width: u32,
+  height: u32,
+  pixel_format: u32,
+  bo_handles: *const u32,
+  pitches: *const u32,
+  offsets: *const u32,
+  modifier: *const u64,
+  buf_id: *mut u32,
+  flags: u32,
+) -> c_int {
+  let mut cmd = drm_mode_fb_cmd2 {
+    width,
+    height,
+    pixel_format,
+    flags,
+    ..Default::default()
+  };
+  cmd
+    .handles
+    .copy_from_slice(slice::from_raw_parts(bo_handles, 4));
+  cmd
+    .pitches
+    .copy_from_slice(slice::from_raw_parts(pitches, 4));
+  cmd
+    .offsets
+    .copy_from_slice(slice::from_raw_parts(offsets, 4));
+  if !modifier.is_null() {
+    cmd
+      .modifier
+      .copy_from_slice(slice::from_raw_parts(modifier, 4));
+  }
+
+  let ret = ioctl(fd, DRM_IOCTL_MODE_ADDFB2, &mut cmd);
+  if ret == 0 {
+    *buf_id = cmd.fb_id;
+  }
+  return ret;
+}
+
+pub unsafe fn drmModeRmFB(fd: RawFd, bufferId: u32) -> c_int {
+  let mut fb_id: c_uint = bufferId;
+  return ioctl(fd, DRM_IOCTL_MODE_RMFB, &mut fb_id);
+}
+
+pub unsafe fn drmModeSetCrtc(
+  fd: RawFd,
+  crtcId: u32,
+  bufferId: u32,
+  x: u32,
+  y: u32,
+  connectors: *const u32,
+  count: c_int,
+  mode: *const RawDRMModeModeInfo,
+) -> c_int {
+  let mut crtc = drm_mode_crtc {
+    set_connectors_ptr: connectors as usize as u64,
+    count_connectors: count as u32,
+    crtc_id: crtcId,
+    fb_id: bufferId,
+    x,
+    y,
+    gamma_size: 0,
+    mode_valid: !mode.is_null() as u32,
+    mode: if mode.is_null() { zeroed_mode() } else { *mode },
+  };
+  return ioctl(fd, DRM_IOCTL_MODE_SETCRTC, &mut crtc);
+}
+
+pub unsafe fn drmModePageFlip(
+  fd: RawFd,
+  crtc_id: u32,
+  fb_id: u32,
+  flags: u32,
+  user_data: *mut c_void,
+) -> c_int {
+  let mut flip = drm_mode_crtc_page_flip {
+    crtc_id,
+    fb_id,
+    flags,
+    reserved: 0,
+    user_data: user_data as usize as u64,
+  };
+  return ioctl(fd, DRM_IOCTL_MODE_PAGE_FLIP, &mut flip);
+}
+
+pub unsafe fn drmModeDirtyFB(
+  fd: RawFd,
+  buffer_id: u32,
+  clips: *const RawDRMClipRect,
+  num_clips: u32,
+) -> c_int {
+  let mut dirty = drm_mode_fb_dirty_cmd {
+    fb_id: buffer_id,
+    num_clips,
+    clips_ptr: clips as usize as u64,
+    ..Default::default()
+  };
+  return ioctl(fd, DRM_IOCTL_MODE_DIRTYFB, &mut dirty);
+}
+
+pub unsafe fn drmPrimeFDToHandle(fd: RawFd, prime_fd: RawFd, handle: *mut u32) -> c_int {
+  let mut args = drm_prime_handle {
+    fd: prime_fd,
+    ..Default::default()
+  };
+  let ret = ioctl(fd, DRM_IOCTL_PRIME_FD_TO_HANDLE, &mut args);
+  if ret == 0 {
+    *handle = args.handle;
+  }
+  return ret;
+}
+
+pub unsafe fn drmWaitVBlank(fd: RawFd, vbl: *mut RawDRMVBlank) -> c_int {
+  return drm_ioctl(fd, DRM_IOCTL_WAIT_VBLANK, vbl);
+}
+
+#[cfg(test)]
+mod tests {
+  use std::fs::{self, OpenOptions};
+  use std::os::unix::io::AsRawFd;
+  use std::path::{Path, PathBuf};
+
+  use super::super::mini_drm::DRMModeConnector;
+  use super::*;
+
+  // The same getters from libdrm, to check the backend against
+  mod libdrm {
+    use super::super::super::mini_drm::{
+      RawDRMModeConnector, RawDRMModeCrtc, RawDRMModeEncoder, RawDRMModePlaneRes, RawDRMModeRes,
+    };
+    use std::os::unix::io::RawFd;
+
+    #[link(name = "drm")]
+    extern "C" {
+      pub fn drmModeGetResources(fd: RawFd) -> *const RawDRMModeRes;
+      pub fn drmModeFreeResources(ptr: *const RawDRMModeRes);
+      pub fn drmModeGetConnector(fd: RawFd, connectorId: u32) -> *const RawDRMModeConnector;
+      pub fn drmModeFreeConnector(ptr: *const RawDRMModeConnector);
+      pub fn drmModeGetEncoder(fd: RawFd, encoderId: u32) -> *const RawDRMModeEncoder;
+      pub fn drmModeFreeEncoder(ptr: *const RawDRMModeEncoder);
+      pub fn drmModeGetCrtc(fd: RawFd, crtcId: u32) -> *const RawDRMModeCrtc;
+      pub fn drmModeFreeCrtc(ptr: *const RawDRMModeCrtc);
+      pub fn drmModeGetPlaneResources(fd: RawFd) -> *const RawDRMModePlaneRes;
+      pub fn drmModeFreePlaneResources(ptr: *const RawDRMModePlaneRes);
+    }
+  }
+
+  // Everything both backends read from a card, copied out
+  #[derive(Debug, PartialEq)]
+  struct Snapshot {
+    fbs: Vec<u32>,
+    crtcs: Vec<RawDRMModeCrtc>,
+    connectors: Vec<DRMModeConnector>,
+    encoders: Vec<RawDRMModeEncoder>,
+    size_limits: [u32; 4],
+    planes: Vec<u32>,
+  }
+
+  unsafe fn ids(items: *const u32, count: usize) -> Vec<u32> {
+    match count {
+      0 => Vec::new(),
+      _ => slice::from_raw_parts(items, count).to_vec(),
+    }
+  }
+
+  macro_rules! snapshot {
+    ($backend:path, $fd:expr) => {{
+      use $backend as backend;
+      unsafe {
+        let raw = backend::drmModeGetResources($fd);
+        assert!(!raw.is_null());
+        let resources = &*raw;
+        let crtc_ids = ids(resources.crtcs, resources.count_crtcs as usize);
+        let connector_ids = ids(resources.connectors, resources.count_connectors as usize);
+        let encoder_ids = ids(resources.encoders, resources.count_encoders as usize);
+        let snapshot = Snapshot {
+          fbs: ids(resources.fbs, resources.count_fbs as usize),
+          crtcs: crtc_ids
+            .iter()
+            .map(|id| {
+              let raw = backend::drmModeGetCrtc($fd, *id);
+              let crtc = *raw;
+              backend::drmModeFreeCrtc(raw);
+              crtc
+            })
+            .collect(),
+          connectors: connector_ids
+            .iter()
+            .map(|id| {
+              let raw = backend::drmModeGetConnector($fd, *id);
+              let connector = DRMModeConnector::from_raw(&*raw);
+              backend::drmModeFreeConnector(raw);
+              connector
+            })
+            .collect(),
+          encoders: encoder_ids
+            .iter()
+            .map(|id| {
+              let raw = backend::drmModeGetEncoder($fd, *id);
+              let encoder = *raw;
+              backend::drmModeFreeEncoder(raw);
+              encoder
+            })
+            .collect(),
+          size_limits: [
+            resources.min_width,
+            resources.max_width,
+            resources.min_height,
+            resources.max_height,
+          ],
+          planes: {
+            let raw = backend::drmModeGetPlaneResources($fd);
+            let planes = ids((*raw).planes, (*raw).count_planes as usize);
+            backend::drmModeFreePlaneResources(raw);
+            planes
+          },
+        };
+        backend::drmModeFreeResources(raw);
+        snapshot
+      }
+    }};
+  }
+
+  // The card driven by vkms, when the module is loaded
+  fn vkms_card() -> Option<PathBuf> {
+    fs::read_dir("/sys/class/drm")
+      .ok()?
+      .flatten()
+      .find_map(|entry| {
+        let name = entry.file_name().into_string().ok()?;
+        let driver = fs::read_link(entry.path().join("device/driver")).ok()?;
+        if !name.starts_with("card") || name.contains('-') || !driver.ends_with("vkms") {
+          return None;
+        }
+        Some(Path::new("/dev/dri").join(name))
+      })
+  }
+
+  #[test]
+  fn reads_the_same_as_libdrm_on_vkms() {
+    let path = match vkms_card() {
+      Some(path) => path,
+      None => return println!("No vkms card, skipped"),
+    };
+    let card = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .open(path)
+      .unwrap();
+    let fd = card.as_raw_fd();
+
+    let ours = snapshot!(super, fd);
+    let theirs = snapshot!(libdrm, fd);
+    assert!(!ours.connectors.is_empty());
+    assert_eq!(ours, theirs);
+  }
+}
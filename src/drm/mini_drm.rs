@@ -1,10 +1,15 @@
 #![allow(dead_code)]
 
-use std::ffi::CStr;
 use std::fs::File;
-use std::os::raw::{c_char, c_int};
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::raw::{c_char, c_int, c_long, c_ulong, c_void};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
 use std::slice;
+use std::time::Duration;
+
+use super::drm_ioctl;
 
 // // This is how we do -ldrm -lgbm -lEGL -lGL
 // #[link(name = "drm")]
@@ -142,8 +147,6 @@ pub struct DRMModeConnector {
   pub prop_values: Vec<u64>, //< List of property values
 
   pub encoders: Vec<u32>, //< List of encoder ids
-
-  pub raw: *const RawDRMModeConnector,
 }
 
 #[derive(Clone, Debug)]
@@ -157,16 +160,6 @@ pub struct DRMModeRes {
   pub max_width: u32,
   pub min_height: u32,
   pub max_height: u32,
-
-  pub raw: *const RawDRMModeRes,
-}
-
-impl Drop for DRMModeRes {
-  fn drop(&mut self) {
-    unsafe {
-      ffi::drmModeFreeResources(self.raw);
-    }
-  }
 }
 
 #[warn(improper_ctypes)]
@@ -188,16 +181,6 @@ pub struct DRMModeEncoder {
   pub crtc_id: u32,
   pub possible_crtcs: u32,
   pub possible_clones: u32,
-
-  pub raw: *const RawDRMModeEncoder,
-}
-
-impl Drop for DRMModeEncoder {
-  fn drop(&mut self) {
-    unsafe {
-      ffi::drmModeFreeEncoder(self.raw);
-    }
-  }
 }
 
 #[warn(improper_ctypes)]
@@ -231,19 +214,197 @@ pub struct DRMModeCrtc {
   pub mode: DRMModeModeInfo,
 
   pub gamma_size: c_int, //< Number of gamma stops
+}
+
+pub const DRM_MODE_FB_MODIFIERS: u32 = 0x02;
+
+pub const DRM_MODE_PAGE_FLIP_EVENT: u32 = 0x01;
+pub const DRM_MODE_PAGE_FLIP_ASYNC: u32 = 0x02;
+
+pub const DRM_EVENT_VBLANK: u32 = 0x01;
+pub const DRM_EVENT_FLIP_COMPLETE: u32 = 0x02;
+pub const DRM_EVENT_CRTC_SEQUENCE: u32 = 0x03;
 
-  pub raw: *const RawDRMModeCrtc,
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawDRMEvent {
+  pub r#type: u32,
+  pub length: u32,
 }
 
-impl Drop for DRMModeCrtc {
-  fn drop(&mut self) {
-    unsafe {
-      ffi::drmModeFreeCrtc(self.raw);
-    }
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawDRMEventVBlank {
+  pub base: RawDRMEvent,
+  pub user_data: u64,
+  pub tv_sec: u32,
+  pub tv_usec: u32,
+  pub sequence: u32,
+  pub crtc_id: u32, //< 0 on older kernels
+}
+
+pub const DRM_VBLANK_ABSOLUTE: u32 = 0x0;
+pub const DRM_VBLANK_RELATIVE: u32 = 0x1;
+pub const DRM_VBLANK_HIGH_CRTC_MASK: u32 = 0x0000003e;
+pub const DRM_VBLANK_EVENT: u32 = 0x4000000;
+pub const DRM_VBLANK_SECONDARY: u32 = 0x20000000;
+pub const DRM_VBLANK_HIGH_CRTC_SHIFT: u32 = 1;
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawDRMVBlankRequest {
+  pub r#type: u32,
+  pub sequence: u32,
+  pub signal: c_ulong, //< user_data of the event
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawDRMVBlankReply {
+  pub r#type: u32,
+  pub sequence: u32,
+  pub tval_sec: c_long,
+  pub tval_usec: c_long,
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+pub struct RawDRMModePlaneRes {
+  pub count_planes: u32,
+  pub planes: *const u32,
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+pub struct RawDRMModePlane {
+  pub count_formats: u32,
+  pub formats: *const u32,
+  pub plane_id: u32,
+
+  pub crtc_id: u32,
+  pub fb_id: u32,
+
+  pub crtc_x: u32,
+  pub crtc_y: u32,
+
+  pub x: u32,
+  pub y: u32,
+
+  pub possible_crtcs: u32,
+  pub gamma_size: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DRMModePlane {
+  pub plane_id: u32,
+
+  pub crtc_id: u32, //< CRTC showing it, 0 when off
+  pub fb_id: u32,
+
+  pub crtc_x: u32, //< Position on the CRTC
+  pub crtc_y: u32,
+
+  pub x: u32, //< Position in the framebuffer, 16.16 fixed point
+  pub y: u32,
+
+  pub possible_crtcs: u32, //< Bit mask of CRTC indices
+  pub gamma_size: u32,
+
+  pub formats: Vec<u32>, //< Supported fourccs
+}
+
+pub const DRM_CLIENT_CAP_UNIVERSAL_PLANES: u64 = 2;
+pub const DRM_CLIENT_CAP_ATOMIC: u64 = 3;
+
+pub const DRM_MODE_OBJECT_CRTC: u32 = 0xcccccccc;
+pub const DRM_MODE_OBJECT_CONNECTOR: u32 = 0xc0c0c0c0;
+pub const DRM_MODE_OBJECT_ENCODER: u32 = 0xe0e0e0e0;
+pub const DRM_MODE_OBJECT_PLANE: u32 = 0xeeeeeeee;
+
+pub const DRM_MODE_PROP_RANGE: u32 = 1 << 1;
+pub const DRM_MODE_PROP_IMMUTABLE: u32 = 1 << 2;
+pub const DRM_MODE_PROP_ENUM: u32 = 1 << 3;
+pub const DRM_MODE_PROP_BLOB: u32 = 1 << 4;
+pub const DRM_MODE_PROP_BITMASK: u32 = 1 << 5;
+pub const DRM_MODE_PROP_OBJECT: u32 = 1 << 6;
+pub const DRM_MODE_PROP_SIGNED_RANGE: u32 = 2 << 6;
+pub const DRM_MODE_PROP_ATOMIC: u32 = 0x80000000;
+
+pub const DRM_MODE_ATOMIC_TEST_ONLY: u32 = 0x0100;
+pub const DRM_MODE_ATOMIC_NONBLOCK: u32 = 0x0200;
+pub const DRM_MODE_ATOMIC_ALLOW_MODESET: u32 = 0x0400;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DRMModeProperty {
+  pub prop_id: u32,
+  pub flags: u32, //< `DRM_MODE_PROP_*`
+  pub name: String,
+  pub values: Vec<u64>, //< Min and max for ranges, the enum values for enums
+  pub enums: Vec<(u64, String)>,
+}
+
+/// Property changes applied all at once by `mode_atomic_commit`.
+#[derive(Clone, Debug, Default)]
+pub struct DRMAtomicRequest {
+  items: Vec<(u32, u32, u64)>,
+}
+
+impl DRMAtomicRequest {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// `object_id` is a CRTC, connector or plane, `property_id` comes from
+  /// `mode_object_get_properties`.
+  pub fn add_property(&mut self, object_id: u32, property_id: u32, value: u64) {
+    self.items.push((object_id, property_id, value));
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
   }
 }
 
-pub mod ffi {
+/// Framebuffer description for `mode_add_fb2`. Planes past the last one in use keep a 0 handle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DRMFramebufferLayout {
+  pub width: u32,
+  pub height: u32,
+  pub pixel_format: u32,
+  pub handles: [u32; 4],
+  pub pitches: [u32; 4],
+  pub offsets: [u32; 4],
+  pub modifier: Option<u64>, //< `None` for the driver's implicit layout
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawDRMClipRect {
+  pub x1: u16,
+  pub y1: u16,
+  pub x2: u16,
+  pub y2: u16,
+}
+
+#[repr(C)]
+pub union RawDRMVBlank {
+  pub request: RawDRMVBlankRequest,
+  pub reply: RawDRMVBlankReply,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DRMEvent {
+  VBlank(RawDRMEventVBlank),
+  FlipComplete(RawDRMEventVBlank),
+}
+
+#[cfg(not(feature = "pure-ioctl"))]
+pub(crate) mod libdrm {
   use super::*;
 
   extern "C" {
@@ -260,7 +421,7 @@ pub mod ffi {
       bpp: u8,
       pitch: u32,
       bo_handle: u32,
-      buf_id: *const u32,
+      buf_id: *mut u32,
     ) -> c_int;
 
     pub fn drmModeRmFB(fd: RawFd, bufferId: u32) -> c_int;
@@ -279,12 +440,93 @@ pub mod ffi {
     pub fn drmModeGetResources(fd: RawFd) -> *const RawDRMModeRes;
     pub fn drmModeGetCrtc(fd: RawFd, crtcId: u32) -> *const RawDRMModeCrtc;
     pub fn drmModeGetEncoder(fd: RawFd, encoderId: u32) -> *const RawDRMModeEncoder;
+    pub fn drmModeGetPlaneResources(fd: RawFd) -> *const RawDRMModePlaneRes;
+    pub fn drmModeFreePlaneResources(ptr: *const RawDRMModePlaneRes);
+    pub fn drmModeGetPlane(fd: RawFd, plane_id: u32) -> *const RawDRMModePlane;
+    pub fn drmModeFreePlane(ptr: *const RawDRMModePlane);
+    pub fn drmSetClientCap(fd: RawFd, capability: u64, value: u64) -> c_int;
     pub fn drmModeGetConnector(fd: RawFd, connectorId: u32) -> *const RawDRMModeConnector;
 
+    pub fn drmModePageFlip(
+      fd: RawFd,
+      crtc_id: u32,
+      fb_id: u32,
+      flags: u32,
+      user_data: *mut c_void,
+    ) -> c_int;
+
+    pub fn drmModeAddFB2WithModifiers(
+      fd: RawFd,
+      width: u32,
+      height: u32,
+      pixel_format: u32,
+      bo_handles: *const u32,
+      pitches: *const u32,
+      offsets: *const u32,
+      modifier: *const u64,
+      buf_id: *mut u32,
+      flags: u32,
+    ) -> c_int;
+
+    pub fn drmPrimeFDToHandle(fd: RawFd, prime_fd: RawFd, handle: *mut u32) -> c_int;
+
+    pub fn drmWaitVBlank(fd: RawFd, vbl: *mut RawDRMVBlank) -> c_int;
+
+    pub fn drmModeDirtyFB(
+      fd: RawFd,
+      buffer_id: u32,
+      clips: *const RawDRMClipRect,
+      num_clips: u32,
+    ) -> c_int;
   }
 }
 
+/// Same functions without libdrm, see `ioctl_ffi`.
+#[cfg(feature = "pure-ioctl")]
+pub(crate) mod libdrm {
+  pub use super::super::ioctl_ffi::*;
+}
+
+#[cfg(not(test))]
+pub(crate) mod ffi {
+  pub use super::libdrm::*;
+}
+
+/// The getters can be faked in tests, see `fake_drm`.
+#[cfg(test)]
+pub(crate) mod ffi {
+  pub use super::super::fake_drm::{
+    drmModeFreeConnector, drmModeFreeCrtc, drmModeFreeEncoder, drmModeFreeResources,
+    drmModeGetConnector, drmModeGetCrtc, drmModeGetEncoder, drmModeGetResources,
+  };
+  pub use super::libdrm::*;
+}
+
+pub const DRM_MODE_FLAG_INTERLACE: u32 = 1 << 4;
+pub const DRM_MODE_FLAG_DBLSCAN: u32 = 1 << 5;
+
 impl DRMModeModeInfo {
+  /// Time between two vblanks, from the pixel clock and the total (blanking included) size.
+  pub fn refresh_interval(&self) -> Duration {
+    if self.clock == 0 || self.htotal == 0 || self.vtotal == 0 {
+      return Duration::from_secs(0);
+    }
+
+    let mut lines = self.vtotal as u64;
+    if (self.flags & DRM_MODE_FLAG_INTERLACE) != 0 {
+      lines /= 2;
+    }
+    if (self.flags & DRM_MODE_FLAG_DBLSCAN) != 0 {
+      lines *= 2;
+    }
+    if self.vscan > 1 {
+      lines *= self.vscan as u64;
+    }
+
+    // clock is in kHz
+    Duration::from_nanos(self.htotal as u64 * lines * 1_000_000 / self.clock as u64)
+  }
+
   pub fn copy(&self) -> DRMModeModeInfo {
     return DRMModeModeInfo {
       clock: self.clock,
@@ -309,13 +551,14 @@ impl DRMModeModeInfo {
   }
 
   pub fn from_raw(raw_mode_info: &RawDRMModeModeInfo) -> DRMModeModeInfo {
-    let mut name = unsafe { CStr::from_bytes_with_nul_unchecked(&(*raw_mode_info).name) }
-      .to_string_lossy()
-      .into_owned();
-    let idx = name.find('\0');
-    if idx.is_some() {
-      name.truncate(idx.unwrap());
-    }
+    // `c_char` is signed on some targets, and nothing guarantees a terminating nul
+    let name: Vec<u8> = raw_mode_info
+      .name
+      .iter()
+      .take_while(|c| **c != 0)
+      .map(|c| *c as u8)
+      .collect();
+    let name = String::from_utf8_lossy(&name).into_owned();
     return DRMModeModeInfo {
       clock: (*raw_mode_info).clock,
       hdisplay: (*raw_mode_info).hdisplay,
@@ -339,130 +582,185 @@ impl DRMModeModeInfo {
   }
 }
 
-impl Drop for DRMModeConnector {
-  fn drop(&mut self) {
-    if self.raw != std::ptr::null() {
-      unsafe {
-        ffi::drmModeFreeConnector(self.raw);
-      }
-    }
+// Copies a libdrm array, which may be null when empty
+unsafe fn copy_array<T: Copy>(items: *const T, count: c_int) -> Vec<T> {
+  if items.is_null() || count <= 0 {
+    return Vec::new();
+  }
+  return slice::from_raw_parts(items, count as usize).to_vec();
+}
+
+fn last_error(default: io::ErrorKind) -> io::Error {
+  let error = io::Error::last_os_error();
+  match error.raw_os_error() {
+    Some(0) | None => io::Error::from(default),
+    Some(_) => error,
   }
 }
 
 impl DRMModeConnector {
-  pub fn from_raw(raw_connector: &RawDRMModeConnector) -> DRMModeConnector {
-    unsafe {
-      let raw_modes = slice::from_raw_parts(
-        (*raw_connector).modes as *const RawDRMModeModeInfo,
-        (*raw_connector).count_modes as usize,
-      );
+  /// Copies everything out, `raw_connector` can be freed right after.
+  ///
+  /// # Safety
+  ///
+  /// The arrays `raw_connector` points to must hold as many items as it says.
+  pub(crate) unsafe fn from_raw(raw_connector: &RawDRMModeConnector) -> DRMModeConnector {
+    let modes = copy_array(raw_connector.modes, raw_connector.count_modes)
+      .iter()
+      .map(DRMModeModeInfo::from_raw)
+      .collect();
+
+    return DRMModeConnector {
+      connector_id: raw_connector.connector_id,
+      encoder_id: raw_connector.encoder_id,
+      connector_type: raw_connector.connector_type,
+      connector_type_id: raw_connector.connector_type_id,
+      connection: raw_connector.connection,
+      mm_width: raw_connector.mmWidth,
+      mm_height: raw_connector.mmHeight,
+      subpixel: raw_connector.subpixel,
+      modes,
+      props: copy_array(raw_connector.props, raw_connector.count_props),
+      prop_values: copy_array(raw_connector.prop_values, raw_connector.count_props),
+      encoders: copy_array(raw_connector.encoders, raw_connector.count_encoders),
+    };
+  }
+}
 
-      let mut modes = Vec::with_capacity((*raw_connector).count_modes as usize);
-      for raw_mode in raw_modes {
-        modes.push(DRMModeModeInfo::from_raw(raw_mode));
-      }
+// All the getters below copy what libdrm returns and free it right away, so the results are
+// plain data.
 
-      let result = DRMModeConnector {
-        connector_id: (*raw_connector).connector_id,
-        encoder_id: (*raw_connector).encoder_id,
-        connector_type: (*raw_connector).connector_type,
-        connector_type_id: (*raw_connector).connector_type_id,
-        connection: (*raw_connector).connection,
-        mm_width: (*raw_connector).mmWidth,
-        mm_height: (*raw_connector).mmHeight,
-        subpixel: (*raw_connector).subpixel,
-        modes,
-        props: (slice::from_raw_parts(
-          (*raw_connector).props as *const u32,
-          (*raw_connector).count_props as usize,
-        ))
-        .to_vec(),
-
-        prop_values: (slice::from_raw_parts(
-          (*raw_connector).prop_values as *const u64,
-          (*raw_connector).count_props as usize,
-        ))
-        .to_vec(),
-
-        encoders: (slice::from_raw_parts(
-          (*raw_connector).encoders as *const u32,
-          (*raw_connector).count_encoders as usize,
-        ))
-        .to_vec(),
-
-        raw: raw_connector,
-      };
-
-      return result;
+pub fn mode_get_resources(device: &File) -> io::Result<DRMModeRes> {
+  let raw_resources = unsafe { ffi::drmModeGetResources((*device).as_raw_fd()) };
+  if raw_resources.is_null() {
+    // Not a KMS device (e.g. a render node) when there's no errno
+    return Err(last_error(io::ErrorKind::Unsupported));
+  }
+
+  let resources = unsafe {
+    let raw = &*raw_resources;
+    DRMModeRes {
+      fbs: copy_array(raw.fbs, raw.count_fbs),
+      crtcs: copy_array(raw.crtcs, raw.count_crtcs),
+      connectors: copy_array(raw.connectors, raw.count_connectors),
+      encoders: copy_array(raw.encoders, raw.count_encoders),
+      min_width: raw.min_width,
+      max_width: raw.max_width,
+      min_height: raw.min_height,
+      max_height: raw.max_height,
     }
+  };
+  unsafe { ffi::drmModeFreeResources(raw_resources) };
+
+  return Ok(resources);
+}
+
+pub fn mode_get_connector(device: &File, connector_id: u32) -> io::Result<DRMModeConnector> {
+  let raw_connector = unsafe { ffi::drmModeGetConnector((*device).as_raw_fd(), connector_id) };
+  if raw_connector.is_null() {
+    return Err(last_error(io::ErrorKind::NotFound));
   }
+
+  let connector = unsafe { DRMModeConnector::from_raw(&*raw_connector) };
+  unsafe { ffi::drmModeFreeConnector(raw_connector) };
+
+  return Ok(connector);
 }
 
-pub fn mode_get_resources(device: &File) -> Option<DRMModeRes> {
-  let raw_resources = unsafe { ffi::drmModeGetResources((*device).as_raw_fd()) };
-  if raw_resources.is_null() {
-    return None;
+pub fn mode_get_encoder(device: &File, encoder_id: u32) -> io::Result<DRMModeEncoder> {
+  let raw_encoder = unsafe { ffi::drmModeGetEncoder((*device).as_raw_fd(), encoder_id) };
+  if raw_encoder.is_null() {
+    return Err(last_error(io::ErrorKind::NotFound));
   }
 
-  unsafe {
-    let result = DRMModeRes {
-      fbs: (slice::from_raw_parts(
-        (*raw_resources).fbs as *const u32,
-        (*raw_resources).count_fbs as usize,
-      ))
-      .to_vec(),
-      crtcs: (slice::from_raw_parts(
-        (*raw_resources).crtcs as *const u32,
-        (*raw_resources).count_crtcs as usize,
-      ))
-      .to_vec(),
-      connectors: (slice::from_raw_parts(
-        (*raw_resources).connectors as *const u32,
-        (*raw_resources).count_connectors as usize,
-      ))
-      .to_vec(),
-      encoders: (slice::from_raw_parts(
-        (*raw_resources).encoders as *const u32,
-        (*raw_resources).count_encoders as usize,
-      ))
-      .to_vec(),
-      min_width: (*raw_resources).min_width,
-      max_width: (*raw_resources).max_width,
-      min_height: (*raw_resources).min_height,
-      max_height: (*raw_resources).max_height,
-
-      raw: raw_resources,
-    };
+  let encoder = unsafe {
+    let raw = &*raw_encoder;
+    DRMModeEncoder {
+      encoder_id: raw.encoder_id,
+      encoder_type: raw.encoder_type,
+      crtc_id: raw.crtc_id,
+      possible_crtcs: raw.possible_crtcs,
+      possible_clones: raw.possible_clones,
+    }
+  };
+  unsafe { ffi::drmModeFreeEncoder(raw_encoder) };
+
+  return Ok(encoder);
+}
 
-    Some(result)
+pub fn mode_get_crtc(device: &File, crtc_id: u32) -> io::Result<DRMModeCrtc> {
+  let raw_crtc = unsafe { ffi::drmModeGetCrtc((*device).as_raw_fd(), crtc_id) };
+  if raw_crtc.is_null() {
+    return Err(last_error(io::ErrorKind::NotFound));
   }
+
+  let crtc = unsafe {
+    let raw = &*raw_crtc;
+    DRMModeCrtc {
+      crtc_id: raw.crtc_id,
+      buffer_id: raw.buffer_id,
+      x: raw.x,
+      y: raw.y,
+      width: raw.width,
+      height: raw.height,
+      mode_valid: raw.mode_valid,
+      mode: DRMModeModeInfo::from_raw(&raw.mode),
+      gamma_size: raw.gamma_size,
+    }
+  };
+  unsafe { ffi::drmModeFreeCrtc(raw_crtc) };
+
+  return Ok(crtc);
 }
 
-pub fn mode_get_crtc(device: &File, crtc_id: u32) -> DRMModeCrtc {
-  unsafe {
-    let raw_crtc = ffi::drmModeGetCrtc((*device).as_raw_fd(), crtc_id);
-
-    let crtc = DRMModeCrtc {
-      crtc_id: (*raw_crtc).crtc_id,
-      buffer_id: (*raw_crtc).buffer_id,
-      x: (*raw_crtc).x,
-      y: (*raw_crtc).y,
-      width: (*raw_crtc).width,
-      height: (*raw_crtc).height,
-      mode_valid: (*raw_crtc).mode_valid,
-      mode: DRMModeModeInfo::from_raw(&(*raw_crtc).mode),
-      gamma_size: (*raw_crtc).gamma_size,
-
-      raw: raw_crtc,
-    };
+/// Plane ids. Primary and cursor planes are only listed once
+/// `DRM_CLIENT_CAP_UNIVERSAL_PLANES` is set.
+pub fn mode_get_plane_resources(device: &File) -> io::Result<Vec<u32>> {
+  let raw_resources = unsafe { ffi::drmModeGetPlaneResources((*device).as_raw_fd()) };
+  if raw_resources.is_null() {
+    return Err(last_error(io::ErrorKind::Unsupported));
+  }
+
+  let planes = unsafe {
+    let raw = &*raw_resources;
+    copy_array(raw.planes, raw.count_planes as c_int)
+  };
+  unsafe { ffi::drmModeFreePlaneResources(raw_resources) };
+
+  return Ok(planes);
+}
 
-    return crtc;
+pub fn mode_get_plane(device: &File, plane_id: u32) -> io::Result<DRMModePlane> {
+  let raw_plane = unsafe { ffi::drmModeGetPlane((*device).as_raw_fd(), plane_id) };
+  if raw_plane.is_null() {
+    return Err(last_error(io::ErrorKind::NotFound));
   }
+
+  let plane = unsafe {
+    let raw = &*raw_plane;
+    DRMModePlane {
+      plane_id: raw.plane_id,
+      crtc_id: raw.crtc_id,
+      fb_id: raw.fb_id,
+      crtc_x: raw.crtc_x,
+      crtc_y: raw.crtc_y,
+      x: raw.x,
+      y: raw.y,
+      possible_crtcs: raw.possible_crtcs,
+      gamma_size: raw.gamma_size,
+      formats: copy_array(raw.formats, raw.count_formats as c_int),
+    }
+  };
+  unsafe { ffi::drmModeFreePlane(raw_plane) };
+
+  return Ok(plane);
 }
 
-pub fn mode_free_crtc(crtc: &mut DRMModeCrtc) {
-  unsafe { ffi::drmModeFreeCrtc(crtc.raw) };
-  crtc.raw = std::ptr::null();
+pub fn set_client_cap(device: &File, capability: u64, value: u64) -> io::Result<()> {
+  if unsafe { ffi::drmSetClientCap((*device).as_raw_fd(), capability, value) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(());
 }
 
 pub fn mode_add_fb(
@@ -473,10 +771,10 @@ pub fn mode_add_fb(
   bpp: u8,
   pitch: u32,
   bo_handle: u32,
-  buf_id: *const u32,
-) -> c_int {
-  unsafe {
-    return ffi::drmModeAddFB(
+) -> io::Result<u32> {
+  let mut buf_id: u32 = 0;
+  let ret = unsafe {
+    ffi::drmModeAddFB(
       (*device).as_raw_fd(),
       width,
       height,
@@ -484,19 +782,67 @@ pub fn mode_add_fb(
       bpp,
       pitch,
       bo_handle,
-      buf_id,
-    );
+      &mut buf_id,
+    )
+  };
+  if ret != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(buf_id);
+}
+
+/// Framebuffer from up to 4 planes. The modifier only goes on planes with a handle.
+pub fn mode_add_fb2(device: &File, layout: &DRMFramebufferLayout) -> io::Result<u32> {
+  let mut modifiers = [0u64; 4];
+  let mut flags = 0;
+  if let Some(modifier) = layout.modifier {
+    for (i, handle) in layout.handles.iter().enumerate() {
+      if *handle != 0 {
+        modifiers[i] = modifier;
+      }
+    }
+    flags = DRM_MODE_FB_MODIFIERS;
+  }
+  let mut buf_id: u32 = 0;
+  let ret = unsafe {
+    ffi::drmModeAddFB2WithModifiers(
+      (*device).as_raw_fd(),
+      layout.width,
+      layout.height,
+      layout.pixel_format,
+      layout.handles.as_ptr(),
+      layout.pitches.as_ptr(),
+      layout.offsets.as_ptr(),
+      modifiers.as_ptr(),
+      &mut buf_id,
+      flags,
+    )
+  };
+  if ret != 0 {
+    return Err(io::Error::last_os_error());
   }
+  return Ok(buf_id);
 }
 
+/// GEM handle for a dmabuf, to be closed with `drm_ioctl::gem_close`.
+pub fn prime_fd_to_handle(device: &File, prime_fd: RawFd) -> io::Result<u32> {
+  let mut handle: u32 = 0;
+  if unsafe { ffi::drmPrimeFDToHandle((*device).as_raw_fd(), prime_fd, &mut handle) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(handle);
+}
+
+/// Shows `buffer_id` on the CRTC through `connectors` in `mode`. `None` (with no framebuffer
+/// and no connectors) turns the CRTC off.
 pub fn mode_set_crtc(
   device: &File,
   crtc_id: u32,
   buffer_id: u32,
   x: u32,
   y: u32,
-  connectors: Vec<u32>,
-  mode: *const RawDRMModeModeInfo,
+  connectors: &[u32],
+  mode: Option<&RawDRMModeModeInfo>,
 ) -> c_int {
   unsafe {
     return ffi::drmModeSetCrtc(
@@ -507,47 +853,494 @@ pub fn mode_set_crtc(
       y,
       connectors.as_ptr(),
       connectors.len() as c_int,
-      mode,
+      mode.map_or(ptr::null(), |mode| mode as *const RawDRMModeModeInfo),
     );
   }
 }
 
+/// Tells drivers of displays that don't scan out continuously (SPI panels, USB adapters) which
+/// parts of the framebuffer changed. `ENOSYS` from everyone else, which can be ignored.
+pub fn mode_dirty_fb(device: &File, buffer_id: u32, clips: &[RawDRMClipRect]) -> c_int {
+  return unsafe {
+    ffi::drmModeDirtyFB(
+      (*device).as_raw_fd(),
+      buffer_id,
+      clips.as_ptr(),
+      clips.len() as u32,
+    )
+  };
+}
+
 pub fn mode_rm_fb(device: &File, buffer_id: u32) -> c_int {
   return unsafe { ffi::drmModeRmFB((*device).as_raw_fd(), buffer_id) };
 }
 
-pub fn find_connector(device: &File, resources: &DRMModeRes) -> Option<DRMModeConnector> {
-  unsafe {
-    for connector_id in &resources.connectors {
-      let connector = ffi::drmModeGetConnector((*device).as_raw_fd(), *connector_id);
-      if (*connector).connection == DRMModeConnection::DRM_MODE_CONNECTED {
-        return Some(DRMModeConnector::from_raw(&*connector));
+/// Queues `buffer_id` for the next vblank (or right away with `DRM_MODE_PAGE_FLIP_ASYNC`).
+/// With `DRM_MODE_PAGE_FLIP_EVENT` completion is reported through `read_events`, carrying
+/// `user_data`.
+pub fn mode_page_flip(
+  device: &File,
+  crtc_id: u32,
+  buffer_id: u32,
+  flags: u32,
+  user_data: u64,
+) -> c_int {
+  return unsafe {
+    ffi::drmModePageFlip(
+      (*device).as_raw_fd(),
+      crtc_id,
+      buffer_id,
+      flags,
+      user_data as usize as *mut c_void,
+    )
+  };
+}
+
+fn vblank_pipe_flags(pipe: u32) -> u32 {
+  return match pipe {
+    0 => 0,
+    1 => DRM_VBLANK_SECONDARY,
+    _ => (pipe << DRM_VBLANK_HIGH_CRTC_SHIFT) & DRM_VBLANK_HIGH_CRTC_MASK,
+  };
+}
+
+/// Waits for the `count`th vblank from now of the CRTC at index `pipe`, 0 returns the counter and
+/// time of the last one right away.
+pub fn wait_vblank(device: &File, pipe: u32, count: u32) -> io::Result<RawDRMVBlankReply> {
+  let mut vblank = RawDRMVBlank {
+    request: RawDRMVBlankRequest {
+      r#type: DRM_VBLANK_RELATIVE | vblank_pipe_flags(pipe),
+      sequence: count,
+      signal: 0,
+    },
+  };
+
+  if unsafe { ffi::drmWaitVBlank((*device).as_raw_fd(), &mut vblank) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(unsafe { vblank.reply });
+}
+
+/// Asks for a `DRMEvent::VBlank` at the next vblank of the CRTC at index `pipe` (its position
+/// in `DRMModeRes::crtcs`), doesn't wait for it.
+pub fn wait_vblank_event(device: &File, pipe: u32, user_data: u64) -> c_int {
+  let mut vblank = RawDRMVBlank {
+    request: RawDRMVBlankRequest {
+      r#type: DRM_VBLANK_RELATIVE | DRM_VBLANK_EVENT | vblank_pipe_flags(pipe),
+      sequence: 1,
+      signal: user_data as c_ulong,
+    },
+  };
+
+  return unsafe { ffi::drmWaitVBlank((*device).as_raw_fd(), &mut vblank) };
+}
+
+// Properties and atomic mode setting, straight through the ioctls whichever the backend.
+
+/// Property ids and current values of a mode object (`DRM_MODE_OBJECT_*`).
+pub fn mode_object_get_properties(
+  device: &File,
+  object_id: u32,
+  object_type: u32,
+) -> io::Result<Vec<(u32, u64)>> {
+  let fd = (*device).as_raw_fd();
+  loop {
+    let mut args = drm_ioctl::drm_mode_obj_get_properties {
+      obj_id: object_id,
+      obj_type: object_type,
+      ..Default::default()
+    };
+    if unsafe { drm_ioctl::drm_ioctl(fd, drm_ioctl::DRM_IOCTL_MODE_OBJ_GETPROPERTIES, &mut args) }
+      != 0
+    {
+      return Err(io::Error::last_os_error());
+    }
+
+    let count = args.count_props;
+    let mut props = vec![0u32; count as usize];
+    let mut values = vec![0u64; count as usize];
+    args.props_ptr = props.as_mut_ptr() as usize as u64;
+    args.prop_values_ptr = values.as_mut_ptr() as usize as u64;
+    if unsafe { drm_ioctl::drm_ioctl(fd, drm_ioctl::DRM_IOCTL_MODE_OBJ_GETPROPERTIES, &mut args) }
+      != 0
+    {
+      return Err(io::Error::last_os_error());
+    }
+
+    // Properties can only be added before the device is registered, but be safe
+    if args.count_props <= count {
+      props.truncate(args.count_props as usize);
+      return Ok(props.into_iter().zip(values).collect());
+    }
+  }
+}
+
+fn prop_name(name: &[u8]) -> String {
+  let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+  String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+pub fn mode_get_property(device: &File, prop_id: u32) -> io::Result<DRMModeProperty> {
+  let fd = (*device).as_raw_fd();
+  loop {
+    let mut args = drm_ioctl::drm_mode_get_property {
+      prop_id,
+      ..Default::default()
+    };
+    if unsafe { drm_ioctl::drm_ioctl(fd, drm_ioctl::DRM_IOCTL_MODE_GETPROPERTY, &mut args) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    // Blob properties report blob ids here, not enums
+    let has_enums = args.flags & (DRM_MODE_PROP_ENUM | DRM_MODE_PROP_BITMASK) != 0;
+    let count_values = args.count_values;
+    let count_enums = if has_enums { args.count_enum_blobs } else { 0 };
+    let mut values = vec![0u64; count_values as usize];
+    let mut enums = vec![drm_ioctl::drm_mode_property_enum::default(); count_enums as usize];
+    args.values_ptr = values.as_mut_ptr() as usize as u64;
+    args.enum_blob_ptr = enums.as_mut_ptr() as usize as u64;
+    args.count_enum_blobs = count_enums;
+    if unsafe { drm_ioctl::drm_ioctl(fd, drm_ioctl::DRM_IOCTL_MODE_GETPROPERTY, &mut args) } != 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    if args.count_values <= count_values && (!has_enums || args.count_enum_blobs <= count_enums) {
+      values.truncate(args.count_values as usize);
+      enums.truncate(if has_enums {
+        args.count_enum_blobs as usize
+      } else {
+        0
+      });
+      return Ok(DRMModeProperty {
+        prop_id: args.prop_id,
+        flags: args.flags,
+        name: prop_name(&args.name),
+        values,
+        enums: enums
+          .iter()
+          .map(|item| (item.value, prop_name(&item.name)))
+          .collect(),
+      });
+    }
+  }
+}
+
+/// Id of the property named `name` on a mode object, e.g. `"FB_ID"` on a plane.
+pub fn find_property(
+  device: &File,
+  object_id: u32,
+  object_type: u32,
+  name: &str,
+) -> io::Result<Option<u32>> {
+  for (prop_id, _) in mode_object_get_properties(device, object_id, object_type)? {
+    if mode_get_property(device, prop_id)?.name == name {
+      return Ok(Some(prop_id));
+    }
+  }
+  Ok(None)
+}
+
+/// Blob for blob properties (e.g. a `RawDRMModeModeInfo` for a CRTC's `MODE_ID`). Stays
+/// around until `mode_destroy_property_blob` or until the fd is closed.
+pub fn mode_create_property_blob(device: &File, data: &[u8]) -> io::Result<u32> {
+  let mut args = drm_ioctl::drm_mode_create_blob {
+    data: data.as_ptr() as usize as u64,
+    length: data.len() as u32,
+    blob_id: 0,
+  };
+  if unsafe {
+    drm_ioctl::drm_ioctl(
+      (*device).as_raw_fd(),
+      drm_ioctl::DRM_IOCTL_MODE_CREATEPROPBLOB,
+      &mut args,
+    )
+  } != 0
+  {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(args.blob_id);
+}
+
+pub fn mode_destroy_property_blob(device: &File, blob_id: u32) -> io::Result<()> {
+  let mut args = drm_ioctl::drm_mode_destroy_blob { blob_id };
+  if unsafe {
+    drm_ioctl::drm_ioctl(
+      (*device).as_raw_fd(),
+      drm_ioctl::DRM_IOCTL_MODE_DESTROYPROPBLOB,
+      &mut args,
+    )
+  } != 0
+  {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(());
+}
+
+/// Applies the request in one go, or nothing of it. Needs `DRM_CLIENT_CAP_ATOMIC` (see
+/// `set_client_cap`). `flags` are `DRM_MODE_ATOMIC_*` and `DRM_MODE_PAGE_FLIP_EVENT`, the
+/// event carries `user_data` like a page flip's.
+pub fn mode_atomic_commit(
+  device: &File,
+  request: &DRMAtomicRequest,
+  flags: u32,
+  user_data: u64,
+) -> io::Result<()> {
+  // The kernel wants the properties grouped by object
+  let mut items = request.items.clone();
+  items.sort_by_key(|item| item.0);
+
+  let mut objects: Vec<u32> = Vec::new();
+  let mut count_props: Vec<u32> = Vec::new();
+  for (object_id, _, _) in &items {
+    if objects.last() != Some(object_id) {
+      objects.push(*object_id);
+      count_props.push(0);
+    }
+    *count_props.last_mut().unwrap() += 1;
+  }
+  let props: Vec<u32> = items.iter().map(|item| item.1).collect();
+  let values: Vec<u64> = items.iter().map(|item| item.2).collect();
+
+  let mut args = drm_ioctl::drm_mode_atomic {
+    flags,
+    count_objs: objects.len() as u32,
+    objs_ptr: objects.as_ptr() as usize as u64,
+    count_props_ptr: count_props.as_ptr() as usize as u64,
+    props_ptr: props.as_ptr() as usize as u64,
+    prop_values_ptr: values.as_ptr() as usize as u64,
+    reserved: 0,
+    user_data,
+  };
+  if unsafe {
+    drm_ioctl::drm_ioctl(
+      (*device).as_raw_fd(),
+      drm_ioctl::DRM_IOCTL_MODE_ATOMIC,
+      &mut args,
+    )
+  } != 0
+  {
+    return Err(io::Error::last_os_error());
+  }
+  return Ok(());
+}
+
+/// Whether events are waiting to be read, waiting up to `timeout_ms` (-1 waits forever).
+pub fn poll_events(device: &File, timeout_ms: c_int) -> io::Result<bool> {
+  let mut poll_fd = libc::pollfd {
+    fd: (*device).as_raw_fd(),
+    events: libc::POLLIN,
+    revents: 0,
+  };
+
+  loop {
+    let ret = unsafe { libc::poll(&mut poll_fd, 1, timeout_ms) };
+    if ret < 0 {
+      let error = io::Error::last_os_error();
+      if error.kind() == io::ErrorKind::Interrupted {
+        continue;
       }
+      return Err(error);
+    }
+    return Ok(ret > 0 && (poll_fd.revents & libc::POLLIN) != 0);
+  }
+}
 
-      ffi::drmModeFreeConnector(connector);
+/// Reads pending vblank and page flip events, blocks if there are none (see `poll_events`).
+/// Same as `drmHandleEvent`, minus the callbacks.
+pub fn read_events(device: &File) -> io::Result<Vec<DRMEvent>> {
+  let mut buffer = [0u8; 1024];
+  let len = loop {
+    match (&*device).read(&mut buffer) {
+      Ok(len) => break len,
+      Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+      Err(error) => return Err(error),
+    }
+  };
+
+  let mut events = Vec::new();
+  let mut offset = 0;
+  while offset + size_of::<RawDRMEvent>() <= len {
+    let header: RawDRMEvent =
+      unsafe { ptr::read_unaligned(buffer[offset..].as_ptr() as *const RawDRMEvent) };
+    if header.length == 0 || offset + header.length as usize > len {
+      break;
+    }
+
+    if header.length as usize >= size_of::<RawDRMEventVBlank>() {
+      let vblank: RawDRMEventVBlank =
+        unsafe { ptr::read_unaligned(buffer[offset..].as_ptr() as *const RawDRMEventVBlank) };
+      match header.r#type {
+        DRM_EVENT_VBLANK => events.push(DRMEvent::VBlank(vblank)),
+        DRM_EVENT_FLIP_COMPLETE => events.push(DRMEvent::FlipComplete(vblank)),
+        _ => {}
+      }
+    }
+
+    offset += header.length as usize;
+  }
+
+  Ok(events)
+}
+
+/// First connector with something plugged in.
+pub fn find_connector(device: &File, resources: &DRMModeRes) -> Option<DRMModeConnector> {
+  for connector_id in &resources.connectors {
+    match mode_get_connector(device, *connector_id) {
+      Ok(connector) if connector.connection == DRMModeConnection::DRM_MODE_CONNECTED => {
+        return Some(connector)
+      }
+      _ => {}
     }
   }
 
   None
 }
 
-pub fn find_encoder(device: &File, connector: *const DRMModeConnector) -> Option<DRMModeEncoder> {
-  if unsafe { (*connector).encoder_id } == 0 {
+/// Encoder the connector currently goes through, `None` if it isn't driven.
+pub fn find_encoder(device: &File, connector: &DRMModeConnector) -> Option<DRMModeEncoder> {
+  if connector.encoder_id == 0 {
     return None;
   }
-  unsafe {
-    let raw_encoder = ffi::drmModeGetEncoder((*device).as_raw_fd(), (*connector).encoder_id);
+  mode_get_encoder(device, connector.encoder_id).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::fake_drm::{self, FakeDrm};
+  use super::*;
+  use std::mem::ManuallyDrop;
+  use std::os::unix::io::FromRawFd;
+
+  // Never used by the fake, never closed
+  fn fake_device() -> ManuallyDrop<File> {
+    ManuallyDrop::new(unsafe { File::from_raw_fd(1000) })
+  }
+
+  fn with_fake<R>(fake: FakeDrm, call: impl FnOnce(&File) -> R) -> (R, FakeDrm) {
+    fake_drm::install(fake);
+    let result = call(&fake_device());
+    (result, fake_drm::uninstall())
+  }
+
+  fn mode(name: &str, hdisplay: u16, vdisplay: u16) -> RawDRMModeModeInfo {
+    let mut mode: RawDRMModeModeInfo = unsafe { std::mem::zeroed() };
+    mode.hdisplay = hdisplay;
+    mode.vdisplay = vdisplay;
+    for (c, b) in mode.name.iter_mut().zip(name.bytes()) {
+      *c = b as c_char;
+    }
+    mode
+  }
 
-    let encoder = DRMModeEncoder {
-      encoder_id: (*raw_encoder).encoder_id,
-      encoder_type: (*raw_encoder).encoder_type,
-      crtc_id: (*raw_encoder).crtc_id,
-      possible_crtcs: (*raw_encoder).possible_crtcs,
-      possible_clones: (*raw_encoder).possible_clones,
+  #[test]
+  fn copy_array_handles_null_and_empty() {
+    let items = [1u32, 2, 3];
+    unsafe {
+      assert!(copy_array(ptr::null::<u32>(), 3).is_empty());
+      assert!(copy_array(items.as_ptr(), 0).is_empty());
+      assert!(copy_array(items.as_ptr(), -1).is_empty());
+      assert_eq!(copy_array(items.as_ptr(), 2), vec![1, 2]);
+    }
+  }
+
+  #[test]
+  fn getters_report_errno() {
+    let failing = FakeDrm {
+      error: Some(libc::EACCES),
+      ..Default::default()
+    };
+    let (result, fake) = with_fake(failing.clone(), mode_get_resources);
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EACCES));
+    let (result, _) = with_fake(failing.clone(), |device| mode_get_connector(device, 1));
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EACCES));
+    let (result, _) = with_fake(failing.clone(), |device| mode_get_encoder(device, 1));
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EACCES));
+    let (result, _) = with_fake(failing, |device| mode_get_crtc(device, 1));
+    assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EACCES));
+    // Nothing to free after a null
+    assert_eq!((fake.gets, fake.frees), (0, 0));
+  }
+
+  #[test]
+  fn getters_without_errno_fall_back_to_a_kind() {
+    let failing = FakeDrm {
+      error: Some(0),
+      ..Default::default()
+    };
+    let (result, _) = with_fake(failing.clone(), mode_get_resources);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+    let (result, _) = with_fake(failing.clone(), |device| mode_get_connector(device, 1));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    let (result, _) = with_fake(failing.clone(), |device| mode_get_encoder(device, 1));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+    let (result, _) = with_fake(failing, |device| mode_get_crtc(device, 1));
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+  }
+
+  #[test]
+  fn resources_are_copied_and_freed_once() {
+    for null_arrays in [false, true] {
+      let (resources, fake) = with_fake(
+        FakeDrm {
+          null_arrays,
+          crtcs: vec![31, 32],
+          connectors: vec![41],
+          ..Default::default()
+        },
+        |device| mode_get_resources(device).unwrap(),
+      );
+      assert!(resources.fbs.is_empty());
+      assert_eq!(resources.crtcs, vec![31, 32]);
+      assert_eq!(resources.connectors, vec![41]);
+      assert!(resources.encoders.is_empty());
+      assert_eq!((fake.gets, fake.frees), (1, 1));
+    }
+  }
+
+  #[test]
+  fn connector_is_copied_and_freed_once() {
+    for null_arrays in [false, true] {
+      let (connector, fake) = with_fake(
+        FakeDrm {
+          null_arrays,
+          encoders: vec![51],
+          modes: vec![mode("1920x1080", 1920, 1080), mode("", 640, 480)],
+          ..Default::default()
+        },
+        |device| mode_get_connector(device, 41).unwrap(),
+      );
+      assert_eq!(connector.connector_id, 41);
+      assert_eq!(connector.encoder_id, 51);
+      assert_eq!(connector.modes.len(), 2);
+      assert_eq!(connector.modes[0].name, "1920x1080");
+      assert_eq!(connector.modes[1].name, "");
+      assert_eq!(connector.modes[1].hdisplay, 640);
+      assert!(connector.props.is_empty());
+      assert!(connector.prop_values.is_empty());
+      assert_eq!(connector.encoders, vec![51]);
+      assert_eq!((fake.gets, fake.frees), (1, 1));
+    }
+  }
 
-      raw: raw_encoder,
+  #[test]
+  fn encoder_and_crtc_are_freed_once() {
+    let objects = FakeDrm {
+      crtcs: vec![31],
+      fbs: vec![61],
+      modes: vec![mode("800x480", 800, 480)],
+      ..Default::default()
     };
 
-    Some(encoder)
+    let (encoder, fake) = with_fake(objects.clone(), |device| {
+      mode_get_encoder(device, 51).unwrap()
+    });
+    assert_eq!((encoder.encoder_id, encoder.crtc_id), (51, 31));
+    assert_eq!((fake.gets, fake.frees), (1, 1));
+
+    let (crtc, fake) = with_fake(objects, |device| mode_get_crtc(device, 31).unwrap());
+    assert_eq!((crtc.crtc_id, crtc.buffer_id), (31, 61));
+    assert_eq!((crtc.width, crtc.height), (800, 480));
+    assert_eq!(crtc.mode.name, "800x480");
+    assert_eq!((fake.gets, fake.frees), (1, 1));
   }
 }
@@ -0,0 +1,48 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::time::Duration;
+
+use super::mini_drm as drm;
+
+// The connector, mode and CRTC a context drives, picked the same way by all of them.
+pub(crate) struct Output {
+  pub connector_id: u32,
+  pub mode: drm::DRMModeModeInfo,
+  // As found at startup, restored on drop
+  pub crtc: drm::DRMModeCrtc,
+  // Index of the CRTC, as vblank requests want it
+  pub pipe: u32,
+}
+
+impl Output {
+  /// The first connected connector in its preferred mode, on the CRTC it's already using.
+  pub fn probe(device: &File) -> Output {
+    let resources = drm::mode_get_resources(device).expect("Couldn't get DRM Mode Resources");
+
+    let connector = drm::find_connector(device, &resources).expect("No connector found");
+    let encoder = drm::find_encoder(device, &connector).expect("No encoder found");
+    let crtc = drm::mode_get_crtc(device, encoder.crtc_id).expect("Couldn't get CRTC");
+    let pipe = resources
+      .crtcs
+      .iter()
+      .position(|crtc_id| *crtc_id == encoder.crtc_id)
+      .unwrap_or(0) as u32;
+
+    return Output {
+      connector_id: connector.connector_id,
+      mode: connector.modes[0].copy(),
+      crtc,
+      pipe,
+    };
+  }
+}
+
+/// Counter and `CLOCK_MONOTONIC` time of the last vblank of the CRTC at index `pipe`. Modesets
+/// send no event, the frame they put up is on screen by then.
+pub(crate) fn last_vblank(device: &File, pipe: u32) -> io::Result<(u32, Duration)> {
+  let vblank = drm::wait_vblank(device, pipe, 0)?;
+  let timestamp = Duration::new(vblank.tval_sec as u64, vblank.tval_usec as u32 * 1000);
+  return Ok((vblank.sequence, timestamp));
+}
@@ -0,0 +1,191 @@
+#![allow(dead_code)]
+
+// EGL devices (EGL_EXT_device_enumeration, EGL_EXT_device_query) and the DRM nodes behind them
+// (EGL_EXT_device_drm, EGL_EXT_device_drm_render_node). The GPU EGL renders with isn't always
+// the device driving the display, e.g. `v3d` and `vc4-drm` on the Pi 4.
+
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::mem;
+use std::os::raw::{c_char, c_void};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use crate::egl_ffi as egl;
+use crate::egl_image::{has_extension, proc_address};
+
+pub const EGL_DEVICE_EXT: egl::EGLint = 0x322C;
+pub const EGL_DRM_DEVICE_FILE_EXT: egl::EGLint = 0x3233;
+pub const EGL_DRM_RENDER_NODE_FILE_EXT: egl::EGLint = 0x3377;
+
+pub type EGLDeviceEXT = *mut c_void;
+type EGLAttrib = isize;
+
+type QueryDevicesEXT =
+  unsafe extern "C" fn(egl::EGLint, *mut EGLDeviceEXT, *mut egl::EGLint) -> egl::EGLBoolean;
+type QueryDeviceStringEXT = unsafe extern "C" fn(EGLDeviceEXT, egl::EGLint) -> *const c_char;
+type QueryDisplayAttribEXT =
+  unsafe extern "C" fn(egl::EGLDisplay, egl::EGLint, *mut EGLAttrib) -> egl::EGLBoolean;
+
+/// An EGL device and the DRM nodes it renders through, when the driver tells.
+#[derive(Clone, Debug)]
+pub struct EglDevice {
+  raw: EGLDeviceEXT,
+  extensions: Vec<String>,
+  drm_node: Option<PathBuf>,
+  render_node: Option<PathBuf>,
+}
+
+// EGL hands out one handle per device, whichever way it was found
+impl PartialEq for EglDevice {
+  fn eq(&self, other: &EglDevice) -> bool {
+    self.raw == other.raw
+  }
+}
+
+fn query_string(device: EGLDeviceEXT, name: egl::EGLint) -> Option<String> {
+  let query = unsafe {
+    mem::transmute::<*mut c_void, QueryDeviceStringEXT>(
+      proc_address("eglQueryDeviceStringEXT").ok()?,
+    )
+  };
+  let value = unsafe { query(device, name) };
+  if value.is_null() {
+    return None;
+  }
+  Some(
+    unsafe { CStr::from_ptr(value) }
+      .to_string_lossy()
+      .into_owned(),
+  )
+}
+
+// Device number of a device node, which is what identifies it: the same card shows up under
+// several paths (`/dev/dri/card0`, `/dev/dri/by-path/...`)
+fn device_number(path: &Path) -> Option<u64> {
+  fs::metadata(path).ok().map(|metadata| metadata.rdev())
+}
+
+impl EglDevice {
+  fn from_raw(raw: EGLDeviceEXT) -> EglDevice {
+    let extensions: Vec<String> = query_string(raw, egl::EGL_EXTENSIONS)
+      .map(|extensions| {
+        extensions
+          .split_whitespace()
+          .map(|name| name.to_string())
+          .collect()
+      })
+      .unwrap_or_default();
+
+    let mut device = EglDevice {
+      raw,
+      extensions,
+      drm_node: None,
+      render_node: None,
+    };
+    if device.has_extension("EGL_EXT_device_drm") {
+      device.drm_node = query_string(raw, EGL_DRM_DEVICE_FILE_EXT).map(PathBuf::from);
+    }
+    if device.has_extension("EGL_EXT_device_drm_render_node") {
+      device.render_node = query_string(raw, EGL_DRM_RENDER_NODE_FILE_EXT).map(PathBuf::from);
+    }
+    device
+  }
+
+  /// All devices EGL knows about, none without EGL_EXT_device_enumeration.
+  pub fn enumerate() -> Vec<EglDevice> {
+    if !has_extension(egl::EGL_NO_DISPLAY, "EGL_EXT_device_enumeration") {
+      return Vec::new();
+    }
+    let query_devices = match proc_address("eglQueryDevicesEXT") {
+      Ok(address) => unsafe { mem::transmute::<*mut c_void, QueryDevicesEXT>(address) },
+      Err(_) => return Vec::new(),
+    };
+
+    let mut count: egl::EGLint = 0;
+    if unsafe { query_devices(0, ptr::null_mut(), &mut count) } != egl::EGL_TRUE || count <= 0 {
+      return Vec::new();
+    }
+    let mut devices: Vec<EGLDeviceEXT> = vec![ptr::null_mut(); count as usize];
+    if unsafe { query_devices(count, devices.as_mut_ptr(), &mut count) } != egl::EGL_TRUE {
+      return Vec::new();
+    }
+    devices.truncate(count as usize);
+
+    devices.into_iter().map(EglDevice::from_raw).collect()
+  }
+
+  /// The device behind a DRM node, primary (`/dev/dri/cardN`) or render (`/dev/dri/renderDN`),
+  /// under whatever path.
+  pub fn for_node<P: AsRef<Path>>(path: P) -> Option<EglDevice> {
+    let rdev = device_number(path.as_ref())?;
+    EglDevice::enumerate()
+      .into_iter()
+      .find(|device| device.has_device_number(rdev))
+  }
+
+  /// The device behind an open DRM node, e.g. `Card::file`.
+  pub fn for_file(file: &File) -> Option<EglDevice> {
+    let rdev = file.metadata().ok()?.rdev();
+    EglDevice::enumerate()
+      .into_iter()
+      .find(|device| device.has_device_number(rdev))
+  }
+
+  /// The device an initialized display renders on (EGL_EXT_device_query).
+  pub(crate) fn for_display(display: egl::EGLDisplay) -> Option<EglDevice> {
+    if !has_extension(egl::EGL_NO_DISPLAY, "EGL_EXT_device_query") {
+      return None;
+    }
+    let query = unsafe {
+      mem::transmute::<*mut c_void, QueryDisplayAttribEXT>(
+        proc_address("eglQueryDisplayAttribEXT").ok()?,
+      )
+    };
+
+    let mut device: EGLAttrib = 0;
+    if unsafe { query(display, EGL_DEVICE_EXT, &mut device) } != egl::EGL_TRUE || device == 0 {
+      return None;
+    }
+    Some(EglDevice::from_raw(device as EGLDeviceEXT))
+  }
+
+  fn has_device_number(&self, rdev: u64) -> bool {
+    let nodes = [self.drm_node.as_ref(), self.render_node.as_ref()];
+    nodes
+      .iter()
+      .flatten()
+      .any(|node| device_number(node) == Some(rdev))
+  }
+
+  pub fn as_raw(&self) -> EGLDeviceEXT {
+    self.raw
+  }
+
+  pub fn extensions(&self) -> &[String] {
+    &self.extensions
+  }
+
+  pub fn has_extension(&self, extension: &str) -> bool {
+    self.extensions.iter().any(|name| name == extension)
+  }
+
+  /// Primary node, e.g. `/dev/dri/card1`. `None` for devices without DRM (software rendering).
+  pub fn drm_node(&self) -> Option<&Path> {
+    self.drm_node.as_deref()
+  }
+
+  /// Render node, e.g. `/dev/dri/renderD128`, to render on without DRM master.
+  pub fn render_node(&self) -> Option<&Path> {
+    self.render_node.as_deref()
+  }
+
+  /// Whether this is the device behind the DRM node at `path`.
+  pub fn is_node<P: AsRef<Path>>(&self, path: P) -> bool {
+    match device_number(path.as_ref()) {
+      Some(rdev) => self.has_device_number(rdev),
+      None => false,
+    }
+  }
+}
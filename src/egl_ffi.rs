@@ -0,0 +1,182 @@
+#![allow(dead_code)]
+
+// The `egl` crate's types and constants, with the functions this crate calls declared through
+// `dynamic_ffi!` so they go through the loader with `dlopen`. The crate's own wrappers call
+// libEGL by name, the ones below shadow them. Used as `use crate::egl_ffi as egl;`.
+
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+
+pub use egl::*;
+
+pub mod ffi {
+  use super::*;
+
+  dynamic_ffi! {
+    egl;
+    pub fn eglGetDisplay(display_id: EGLNativeDisplayType) -> EGLDisplay;
+    pub fn eglInitialize(dpy: EGLDisplay, major: *mut EGLint, minor: *mut EGLint) -> EGLBoolean;
+    pub fn eglTerminate(dpy: EGLDisplay) -> EGLBoolean;
+    pub fn eglGetError() -> EGLint;
+    pub fn eglBindAPI(api: EGLenum) -> EGLBoolean;
+    pub fn eglReleaseThread() -> EGLBoolean;
+    pub fn eglGetProcAddress(procname: *const c_char) -> *mut c_void;
+    pub fn eglQueryString(dpy: EGLDisplay, name: EGLint) -> *const c_char;
+    pub fn eglGetConfigs(
+      dpy: EGLDisplay,
+      configs: *mut EGLConfig,
+      config_size: EGLint,
+      num_config: *mut EGLint,
+    ) -> EGLBoolean;
+    pub fn eglChooseConfig(
+      dpy: EGLDisplay,
+      attrib_list: *const EGLint,
+      configs: *mut EGLConfig,
+      config_size: EGLint,
+      num_config: *mut EGLint,
+    ) -> EGLBoolean;
+    pub fn eglGetConfigAttrib(
+      dpy: EGLDisplay,
+      config: EGLConfig,
+      attribute: EGLint,
+      value: *mut EGLint,
+    ) -> EGLBoolean;
+    pub fn eglCreateContext(
+      dpy: EGLDisplay,
+      config: EGLConfig,
+      share_context: EGLContext,
+      attrib_list: *const EGLint,
+    ) -> EGLContext;
+    pub fn eglDestroyContext(dpy: EGLDisplay, ctx: EGLContext) -> EGLBoolean;
+    pub fn eglCreateWindowSurface(
+      dpy: EGLDisplay,
+      config: EGLConfig,
+      win: EGLNativeWindowType,
+      attrib_list: *const EGLint,
+    ) -> EGLSurface;
+    pub fn eglDestroySurface(dpy: EGLDisplay, surface: EGLSurface) -> EGLBoolean;
+    pub fn eglMakeCurrent(
+      dpy: EGLDisplay,
+      draw: EGLSurface,
+      read: EGLSurface,
+      ctx: EGLContext,
+    ) -> EGLBoolean;
+    pub fn eglSwapBuffers(dpy: EGLDisplay, surface: EGLSurface) -> EGLBoolean;
+    pub fn eglSwapInterval(dpy: EGLDisplay, interval: EGLint) -> EGLBoolean;
+  }
+}
+
+fn attributes(attrib_list: &[EGLint]) -> *const EGLint {
+  if attrib_list.is_empty() {
+    return ptr::null();
+  }
+  attrib_list.as_ptr()
+}
+
+fn non_null(handle: *mut c_void) -> Option<*mut c_void> {
+  if handle.is_null() {
+    return None;
+  }
+  Some(handle)
+}
+
+pub fn get_display(display_id: EGLNativeDisplayType) -> Option<EGLDisplay> {
+  non_null(unsafe { ffi::eglGetDisplay(display_id) })
+}
+
+pub fn initialize(display: EGLDisplay, major: &mut EGLint, minor: &mut EGLint) -> bool {
+  unsafe { ffi::eglInitialize(display, major, minor) == EGL_TRUE }
+}
+
+pub fn terminate(display: EGLDisplay) -> bool {
+  unsafe { ffi::eglTerminate(display) == EGL_TRUE }
+}
+
+pub fn get_error() -> EGLint {
+  unsafe { ffi::eglGetError() }
+}
+
+pub fn bind_api(api: EGLenum) -> bool {
+  unsafe { ffi::eglBindAPI(api) == EGL_TRUE }
+}
+
+pub fn release_thread() -> bool {
+  unsafe { ffi::eglReleaseThread() == EGL_TRUE }
+}
+
+/// The first of at most `config_size` matching configs.
+pub fn choose_config(
+  display: EGLDisplay,
+  attrib_list: &[EGLint],
+  config_size: EGLint,
+) -> Option<EGLConfig> {
+  let mut config: EGLConfig = ptr::null_mut();
+  let mut count: EGLint = 0;
+  let success = unsafe {
+    ffi::eglChooseConfig(
+      display,
+      attributes(attrib_list),
+      &mut config,
+      config_size,
+      &mut count,
+    )
+  };
+  if success != EGL_TRUE || count == 0 {
+    return None;
+  }
+  Some(config)
+}
+
+pub fn get_config_attrib(
+  display: EGLDisplay,
+  config: EGLConfig,
+  attribute: EGLint,
+  value: &mut EGLint,
+) -> bool {
+  unsafe { ffi::eglGetConfigAttrib(display, config, attribute, value) == EGL_TRUE }
+}
+
+pub fn create_context(
+  display: EGLDisplay,
+  config: EGLConfig,
+  share_context: EGLContext,
+  attrib_list: &[EGLint],
+) -> Option<EGLContext> {
+  non_null(unsafe {
+    ffi::eglCreateContext(display, config, share_context, attributes(attrib_list))
+  })
+}
+
+pub fn destroy_context(display: EGLDisplay, ctx: EGLContext) -> bool {
+  unsafe { ffi::eglDestroyContext(display, ctx) == EGL_TRUE }
+}
+
+pub fn create_window_surface(
+  display: EGLDisplay,
+  config: EGLConfig,
+  window: EGLNativeWindowType,
+  attrib_list: &[EGLint],
+) -> Option<EGLSurface> {
+  non_null(unsafe { ffi::eglCreateWindowSurface(display, config, window, attributes(attrib_list)) })
+}
+
+pub fn destroy_surface(display: EGLDisplay, surface: EGLSurface) -> bool {
+  unsafe { ffi::eglDestroySurface(display, surface) == EGL_TRUE }
+}
+
+pub fn make_current(
+  display: EGLDisplay,
+  draw: EGLSurface,
+  read: EGLSurface,
+  ctx: EGLContext,
+) -> bool {
+  unsafe { ffi::eglMakeCurrent(display, draw, read, ctx) == EGL_TRUE }
+}
+
+pub fn swap_buffers(display: EGLDisplay, surface: EGLSurface) -> bool {
+  unsafe { ffi::eglSwapBuffers(display, surface) == EGL_TRUE }
+}
+
+pub fn swap_interval(display: EGLDisplay, interval: EGLint) -> bool {
+  unsafe { ffi::eglSwapInterval(display, interval) == EGL_TRUE }
+}
@@ -0,0 +1,299 @@
+#![allow(dead_code)]
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::raw::c_void;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use crate::dmabuf::DmabufFrame;
+use crate::egl_ffi as egl;
+use crate::gbm::mini_gbm::DRM_FORMAT_MOD_INVALID;
+use crate::Context;
+
+pub type EGLImageKHR = *mut c_void;
+pub type GLenum = u32;
+pub type GLuint = u32;
+pub type GLint = i32;
+
+const EGL_LINUX_DMA_BUF_EXT: egl::EGLenum = 0x3270;
+const EGL_LINUX_DRM_FOURCC_EXT: egl::EGLint = 0x3271;
+
+// FD, OFFSET, PITCH, MODIFIER_LO, MODIFIER_HI for each of the 4 planes
+#[rustfmt::skip]
+const PLANE_ATTRIBUTES: [[egl::EGLint; 5]; 4] = [
+  [0x3272, 0x3273, 0x3274, 0x3443, 0x3444],
+  [0x3275, 0x3276, 0x3277, 0x3445, 0x3446],
+  [0x3278, 0x3279, 0x327A, 0x3447, 0x3448],
+  [0x3440, 0x3441, 0x3442, 0x3449, 0x344A],
+];
+
+pub const GL_TEXTURE_EXTERNAL_OES: GLenum = 0x8D65;
+pub const GL_TEXTURE_2D: GLenum = 0x0DE1;
+pub const GL_FRAMEBUFFER: GLenum = 0x8D40;
+pub const GL_COLOR_ATTACHMENT0: GLenum = 0x8CE0;
+pub const GL_FRAMEBUFFER_COMPLETE: GLenum = 0x8CD5;
+const GL_TEXTURE_MAG_FILTER: GLenum = 0x2800;
+const GL_TEXTURE_MIN_FILTER: GLenum = 0x2801;
+const GL_TEXTURE_WRAP_S: GLenum = 0x2802;
+const GL_TEXTURE_WRAP_T: GLenum = 0x2803;
+const GL_LINEAR: GLint = 0x2601;
+const GL_CLAMP_TO_EDGE: GLint = 0x812F;
+
+type CreateImageKHR = unsafe extern "C" fn(
+  egl::EGLDisplay,
+  egl::EGLContext,
+  egl::EGLenum,
+  egl::EGLClientBuffer,
+  *const egl::EGLint,
+) -> EGLImageKHR;
+type DestroyImageKHR = unsafe extern "C" fn(egl::EGLDisplay, EGLImageKHR) -> egl::EGLBoolean;
+type EGLImageTargetTexture2DOES = unsafe extern "C" fn(GLenum, EGLImageKHR);
+
+pub(crate) mod ffi {
+  use super::*;
+
+  dynamic_ffi! {
+    gles;
+    pub fn glGenTextures(n: i32, textures: *mut GLuint);
+    pub fn glDeleteTextures(n: i32, textures: *const GLuint);
+    pub fn glBindTexture(target: GLenum, texture: GLuint);
+    pub fn glTexParameteri(target: GLenum, pname: GLenum, param: GLint);
+
+    pub fn glGenFramebuffers(n: i32, framebuffers: *mut GLuint);
+    pub fn glDeleteFramebuffers(n: i32, framebuffers: *const GLuint);
+    pub fn glBindFramebuffer(target: GLenum, framebuffer: GLuint);
+    pub fn glFramebufferTexture2D(
+      target: GLenum,
+      attachment: GLenum,
+      textarget: GLenum,
+      texture: GLuint,
+      level: GLint,
+    );
+    pub fn glCheckFramebufferStatus(target: GLenum) -> GLenum;
+    pub fn glFlush();
+  }
+}
+
+/// One plane of a dmabuf. The fd is only borrowed, EGL takes its own reference on import.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DmabufPlane {
+  pub fd: RawFd,
+  pub offset: u32,
+  pub stride: u32,
+}
+
+/// What it takes to import a dmabuf, e.g. NV12 (2 planes) or YUV420 (3 planes) frames from a
+/// camera or a video decoder.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DmabufDesc {
+  pub width: u32,
+  pub height: u32,
+  /// One of the `GBM_FORMAT_*`/`DRM_FORMAT_*` fourccs
+  pub fourcc: u32,
+  /// `DRM_FORMAT_MOD_INVALID` to let the driver assume its implicit layout
+  pub modifier: u64,
+  pub planes: Vec<DmabufPlane>,
+}
+
+impl DmabufDesc {
+  pub fn from_frame(frame: &DmabufFrame) -> DmabufDesc {
+    DmabufDesc {
+      width: frame.width,
+      height: frame.height,
+      fourcc: frame.fourcc,
+      modifier: frame.modifier,
+      planes: frame
+        .fds
+        .iter()
+        .zip(frame.strides.iter().zip(frame.offsets.iter()))
+        .map(|(fd, (stride, offset))| DmabufPlane {
+          fd: fd.as_raw_fd(),
+          offset: *offset,
+          stride: *stride,
+        })
+        .collect(),
+    }
+  }
+}
+
+fn error(message: &str) -> io::Error {
+  io::Error::other(format!("{} (EGL error {:#x})", message, egl::get_error()))
+}
+
+pub(crate) fn proc_address(name: &str) -> io::Result<*mut c_void> {
+  let c_name = CString::new(name).unwrap();
+  let address = unsafe { egl::ffi::eglGetProcAddress(c_name.as_ptr()) };
+  if address.is_null() {
+    return Err(io::Error::new(
+      io::ErrorKind::Unsupported,
+      format!("{} is not available", name),
+    ));
+  }
+  Ok(address)
+}
+
+pub fn has_extension(display: egl::EGLDisplay, extension: &str) -> bool {
+  let extensions = unsafe { egl::ffi::eglQueryString(display, egl::EGL_EXTENSIONS) };
+  if extensions.is_null() {
+    return false;
+  }
+  let extensions = unsafe { CStr::from_ptr(extensions) }.to_string_lossy();
+
+  extensions.split_whitespace().any(|name| name == extension)
+}
+
+/// A dmabuf imported as an `EGLImageKHR` and bound to a `GL_TEXTURE_EXTERNAL_OES` texture,
+/// sampled with `samplerExternalOES` (YUV is converted by the sampler). The GL context must be
+/// current when it's created and dropped. Borrows the context whose EGL display it lives on.
+pub struct ImportedImage<'ctx> {
+  egl_display: egl::EGLDisplay,
+  image: EGLImageKHR,
+  texture: GLuint,
+  width: u32,
+  height: u32,
+  destroy_image: DestroyImageKHR,
+  _context: PhantomData<&'ctx Context>,
+}
+
+impl<'ctx> ImportedImage<'ctx> {
+  pub(crate) fn import(
+    context: &'ctx Context,
+    desc: &DmabufDesc,
+  ) -> io::Result<ImportedImage<'ctx>> {
+    ImportedImage::import_as(context, desc, GL_TEXTURE_EXTERNAL_OES)
+  }
+
+  // `GL_TEXTURE_2D` only works for RGB formats, but can be rendered to
+  pub(crate) fn import_as(
+    context: &'ctx Context,
+    desc: &DmabufDesc,
+    target: GLenum,
+  ) -> io::Result<ImportedImage<'ctx>> {
+    let egl_display = context.egl_display();
+    if !has_extension(egl_display, "EGL_EXT_image_dma_buf_import") {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "EGL_EXT_image_dma_buf_import is not supported",
+      ));
+    }
+    if desc.planes.is_empty() || desc.planes.len() > PLANE_ATTRIBUTES.len() {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "A dmabuf has 1 to 4 planes",
+      ));
+    }
+
+    // Without the modifiers extension only the implicit layout (or plain linear) works
+    let with_modifier = desc.modifier != DRM_FORMAT_MOD_INVALID
+      && has_extension(egl_display, "EGL_EXT_image_dma_buf_import_modifiers");
+    if desc.modifier != DRM_FORMAT_MOD_INVALID && desc.modifier != 0 && !with_modifier {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "EGL_EXT_image_dma_buf_import_modifiers is not supported",
+      ));
+    }
+
+    let mut attributes: Vec<egl::EGLint> = vec![
+      egl::EGL_WIDTH,
+      desc.width as egl::EGLint,
+      egl::EGL_HEIGHT,
+      desc.height as egl::EGLint,
+      EGL_LINUX_DRM_FOURCC_EXT,
+      desc.fourcc as egl::EGLint,
+    ];
+    for (plane, names) in desc.planes.iter().zip(PLANE_ATTRIBUTES.iter()) {
+      attributes.extend_from_slice(&[
+        names[0],
+        plane.fd,
+        names[1],
+        plane.offset as egl::EGLint,
+        names[2],
+        plane.stride as egl::EGLint,
+      ]);
+      if with_modifier {
+        attributes.extend_from_slice(&[
+          names[3],
+          (desc.modifier & 0xffffffff) as egl::EGLint,
+          names[4],
+          (desc.modifier >> 32) as egl::EGLint,
+        ]);
+      }
+    }
+    attributes.push(egl::EGL_NONE);
+
+    let (create_image, destroy_image, target_texture) = unsafe {
+      (
+        mem::transmute::<*mut c_void, CreateImageKHR>(proc_address("eglCreateImageKHR")?),
+        mem::transmute::<*mut c_void, DestroyImageKHR>(proc_address("eglDestroyImageKHR")?),
+        mem::transmute::<*mut c_void, EGLImageTargetTexture2DOES>(proc_address(
+          "glEGLImageTargetTexture2DOES",
+        )?),
+      )
+    };
+
+    let image = unsafe {
+      create_image(
+        egl_display,
+        egl::EGL_NO_CONTEXT,
+        EGL_LINUX_DMA_BUF_EXT,
+        std::ptr::null_mut(),
+        attributes.as_ptr(),
+      )
+    };
+    if image.is_null() {
+      return Err(error("Couldn't create EGL image from dmabuf"));
+    }
+
+    let mut texture: GLuint = 0;
+    unsafe {
+      ffi::glGenTextures(1, &mut texture);
+      ffi::glBindTexture(target, texture);
+      ffi::glTexParameteri(target, GL_TEXTURE_MIN_FILTER, GL_LINEAR);
+      ffi::glTexParameteri(target, GL_TEXTURE_MAG_FILTER, GL_LINEAR);
+      ffi::glTexParameteri(target, GL_TEXTURE_WRAP_S, GL_CLAMP_TO_EDGE);
+      ffi::glTexParameteri(target, GL_TEXTURE_WRAP_T, GL_CLAMP_TO_EDGE);
+      target_texture(target, image);
+      ffi::glBindTexture(target, 0);
+    }
+
+    Ok(ImportedImage {
+      egl_display,
+      image,
+      texture,
+      width: desc.width,
+      height: desc.height,
+      destroy_image,
+      _context: PhantomData,
+    })
+  }
+
+  /// Bind to `GL_TEXTURE_EXTERNAL_OES` (or what it was imported as)
+  pub fn texture(&self) -> GLuint {
+    self.texture
+  }
+
+  pub fn image(&self) -> EGLImageKHR {
+    self.image
+  }
+
+  pub fn width(&self) -> u32 {
+    self.width
+  }
+
+  pub fn height(&self) -> u32 {
+    self.height
+  }
+}
+
+impl Drop for ImportedImage<'_> {
+  fn drop(&mut self) {
+    unsafe {
+      ffi::glDeleteTextures(1, &self.texture);
+      if (self.destroy_image)(self.egl_display, self.image) != egl::EGL_TRUE {
+        println!("Error destroying EGL image: {:#x}", egl::get_error());
+      }
+    }
+  }
+}
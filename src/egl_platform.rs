@@ -0,0 +1,122 @@
+#![allow(dead_code)]
+
+// Getting an EGL display for a platform explicitly (EGL_EXT_platform_base) instead of having
+// `eglGetDisplay` guess what kind of native display it was handed.
+
+use std::mem;
+use std::os::raw::c_void;
+
+use crate::egl_ffi as egl;
+use crate::egl_image::{has_extension, proc_address};
+
+pub const EGL_PLATFORM_GBM_KHR: egl::EGLenum = 0x31D7;
+
+type GetPlatformDisplayEXT =
+  unsafe extern "C" fn(egl::EGLenum, *mut c_void, *const egl::EGLint) -> egl::EGLDisplay;
+type CreatePlatformWindowSurfaceEXT = unsafe extern "C" fn(
+  egl::EGLDisplay,
+  egl::EGLConfig,
+  *mut c_void,
+  *const egl::EGLint,
+) -> egl::EGLSurface;
+
+/// How the context got its EGL display, in order of preference. Both can make window surfaces
+/// on a GBM surface, which the surfaceless and device platforms can't.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayPlatform {
+  /// `eglGetPlatformDisplayEXT(EGL_PLATFORM_GBM_KHR, gbm_device)`
+  Gbm,
+  /// `eglGetDisplay(gbm_device)`, Mesa guesses the platform
+  Legacy,
+}
+
+/// An initialized display.
+#[derive(Debug)]
+pub struct PlatformDisplay {
+  pub platform: DisplayPlatform,
+  pub display: egl::EGLDisplay,
+  pub major: i32,
+  pub minor: i32,
+}
+
+impl PlatformDisplay {
+  pub fn terminate(self) {
+    egl::terminate(self.display);
+  }
+}
+
+// Client extensions are queried on no display
+fn has_client_extension(extension: &str) -> bool {
+  has_extension(egl::EGL_NO_DISPLAY, extension)
+}
+
+fn get_platform_display(platform: egl::EGLenum, native_display: *mut c_void) -> egl::EGLDisplay {
+  let get_display = match proc_address("eglGetPlatformDisplayEXT") {
+    Ok(address) => unsafe { mem::transmute::<*mut c_void, GetPlatformDisplayEXT>(address) },
+    Err(_) => return egl::EGL_NO_DISPLAY,
+  };
+  let attributes = [egl::EGL_NONE];
+  unsafe { get_display(platform, native_display, attributes.as_ptr()) }
+}
+
+/// Displays to try for `gbm_device`, best first. Only the legacy one is there without
+/// EGL_EXT_platform_base.
+pub fn candidates(gbm_device: *mut c_void) -> Vec<(DisplayPlatform, egl::EGLDisplay)> {
+  let mut candidates = Vec::new();
+
+  let has_gbm_platform =
+    has_client_extension("EGL_KHR_platform_gbm") || has_client_extension("EGL_MESA_platform_gbm");
+  if has_client_extension("EGL_EXT_platform_base") && has_gbm_platform {
+    candidates.push((
+      DisplayPlatform::Gbm,
+      get_platform_display(EGL_PLATFORM_GBM_KHR, gbm_device),
+    ));
+  }
+
+  if let Some(display) = egl::get_display(gbm_device) {
+    candidates.push((DisplayPlatform::Legacy, display));
+  }
+
+  candidates.retain(|(_, display)| !display.is_null());
+  candidates
+}
+
+pub fn initialize(platform: DisplayPlatform, display: egl::EGLDisplay) -> Option<PlatformDisplay> {
+  let mut major = 0i32;
+  let mut minor = 0i32;
+  if !egl::initialize(display, &mut major, &mut minor) {
+    return None;
+  }
+
+  Some(PlatformDisplay {
+    platform,
+    display,
+    major,
+    minor,
+  })
+}
+
+/// Window surface on a `gbm_surface`, through `eglCreatePlatformWindowSurfaceEXT` for platform
+/// displays.
+pub fn create_window_surface(
+  display: &PlatformDisplay,
+  config: egl::EGLConfig,
+  gbm_surface: *mut c_void,
+) -> Option<egl::EGLSurface> {
+  if display.platform == DisplayPlatform::Legacy {
+    return egl::create_window_surface(display.display, config, gbm_surface, &[]);
+  }
+
+  let create_surface = unsafe {
+    mem::transmute::<*mut c_void, CreatePlatformWindowSurfaceEXT>(
+      proc_address("eglCreatePlatformWindowSurfaceEXT").ok()?,
+    )
+  };
+  let attributes = [egl::EGL_NONE];
+  let surface =
+    unsafe { create_surface(display.display, config, gbm_surface, attributes.as_ptr()) };
+  if surface == egl::EGL_NO_SURFACE {
+    return None;
+  }
+  Some(surface)
+}
@@ -2,27 +2,11 @@
 
 use std::ptr;
 
-use egl;
+use crate::egl_ffi as egl;
+use crate::egl_ffi::ffi;
 
-mod ffi {
-  use super::*;
-
-  extern "C" {
-    pub fn eglChooseConfig(
-      dpy: egl::EGLDisplay,
-      attrib_list: *const egl::EGLint,
-      configs: *mut egl::EGLConfig,
-      config_size: egl::EGLint,
-      num_config: *mut egl::EGLint,
-    ) -> egl::EGLBoolean;
-
-    pub fn eglGetConfigs(
-      dpy: egl::EGLDisplay,
-      configs: egl::EGLConfig,
-      config_size: egl::EGLint,
-      num_config: *mut egl::EGLint,
-    ) -> egl::EGLBoolean;
-  }
+pub fn swap_interval(display: egl::EGLDisplay, interval: egl::EGLint) -> bool {
+  unsafe { ffi::eglSwapInterval(display, interval) == egl::EGL_TRUE }
 }
 
 pub fn get_config_count(display: egl::EGLDisplay) -> egl::EGLint {
@@ -87,94 +71,238 @@ pub fn match_config_to_visual(
   return None;
 }
 
-pub fn print_config_info(display: egl::EGLDisplay, config: egl::EGLConfig) {
-  let mut config_id: egl::EGLint = 0;
-  let mut config_caveat: egl::EGLint = 0;
-  let mut visual_id: egl::EGLint = 0;
-  let mut visual_type: egl::EGLint = 0;
-  let mut native_renderable: egl::EGLint = 0;
-  let mut renderable_type: egl::EGLint = 0;
-  let mut red_size: egl::EGLint = 0;
-  let mut green_size: egl::EGLint = 0;
-  let mut blue_size: egl::EGLint = 0;
-  let mut alpha_size: egl::EGLint = 0;
-  let mut depth_size: egl::EGLint = 0;
-  let mut buffer_size: egl::EGLint = 0;
-  let mut surface_type: egl::EGLint = 0;
-  let mut color_buffer_type: egl::EGLint = 0;
-  let mut rgb_bind: egl::EGLint = 0;
-  let mut rgba_bind: egl::EGLint = 0;
-
-  egl::get_config_attrib(display, config, egl::EGL_CONFIG_ID, &mut config_id);
-  egl::get_config_attrib(display, config, egl::EGL_CONFIG_CAVEAT, &mut config_caveat);
-  egl::get_config_attrib(display, config, egl::EGL_NATIVE_VISUAL_ID, &mut visual_id);
-  egl::get_config_attrib(
-    display,
-    config,
-    egl::EGL_NATIVE_VISUAL_TYPE,
-    &mut visual_type,
-  );
+/// Every config of the display, not just the ones matching some attributes.
+pub fn get_configs(display: egl::EGLDisplay) -> Vec<egl::EGLConfig> {
+  let config_count = get_config_count(display);
+  let mut configs: Vec<egl::EGLConfig> = Vec::with_capacity(config_count as usize);
 
-  egl::get_config_attrib(
-    display,
-    config,
-    egl::EGL_NATIVE_RENDERABLE,
-    &mut native_renderable,
-  );
-  egl::get_config_attrib(
-    display,
-    config,
-    egl::EGL_RENDERABLE_TYPE,
-    &mut renderable_type,
-  );
-  egl::get_config_attrib(display, config, egl::EGL_BUFFER_SIZE, &mut buffer_size);
-  egl::get_config_attrib(display, config, egl::EGL_RED_SIZE, &mut red_size);
-  egl::get_config_attrib(display, config, egl::EGL_BLUE_SIZE, &mut green_size);
-  egl::get_config_attrib(display, config, egl::EGL_GREEN_SIZE, &mut blue_size);
-  egl::get_config_attrib(display, config, egl::EGL_ALPHA_SIZE, &mut alpha_size);
-  egl::get_config_attrib(display, config, egl::EGL_DEPTH_SIZE, &mut depth_size);
-  egl::get_config_attrib(display, config, egl::EGL_SURFACE_TYPE, &mut surface_type);
-  egl::get_config_attrib(
-    display,
-    config,
-    egl::EGL_COLOR_BUFFER_TYPE,
-    &mut color_buffer_type,
-  );
-  egl::get_config_attrib(display, config, egl::EGL_BIND_TO_TEXTURE_RGB, &mut rgb_bind);
-  egl::get_config_attrib(
-    display,
-    config,
-    egl::EGL_BIND_TO_TEXTURE_RGBA,
-    &mut rgba_bind,
-  );
+  unsafe {
+    let mut returned_configs: egl::EGLint = 0;
+    let success = ffi::eglGetConfigs(
+      display,
+      configs.as_mut_ptr(),
+      config_count,
+      &mut returned_configs,
+    ) == egl::EGL_TRUE;
+
+    if success {
+      configs.set_len(returned_configs as usize);
+    }
+  }
+
+  configs
+}
+
+/// `EGL_CONFIG_CAVEAT`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConfigCaveat {
+  None,
+  Slow,
+  NonConformant,
+}
+
+/// The attributes of an EGL config.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EglConfigInfo {
+  pub config_id: i32,
+  pub caveat: ConfigCaveat,
+  /// The DRM fourcc on GBM (e.g. `GBM_FORMAT_XRGB8888`)
+  pub native_visual_id: i32,
+  pub native_visual_type: i32,
+  pub native_renderable: bool,
+  /// `EGL_OPENGL_ES2_BIT` and friends
+  pub renderable_type: i32,
+  /// `EGL_WINDOW_BIT` and friends
+  pub surface_type: i32,
+  pub luminance: bool,
+  pub buffer_size: i32,
+  pub red_size: i32,
+  pub green_size: i32,
+  pub blue_size: i32,
+  pub alpha_size: i32,
+  pub depth_size: i32,
+  pub stencil_size: i32,
+  pub samples: i32,
+  pub sample_buffers: i32,
+  pub bind_to_texture_rgb: bool,
+  pub bind_to_texture_rgba: bool,
+}
+
+impl EglConfigInfo {
+  pub fn query(display: egl::EGLDisplay, config: egl::EGLConfig) -> EglConfigInfo {
+    let attrib = |attribute: egl::EGLint| {
+      let mut value: egl::EGLint = 0;
+      egl::get_config_attrib(display, config, attribute, &mut value);
+      value
+    };
+
+    let caveat = match attrib(egl::EGL_CONFIG_CAVEAT) {
+      egl::EGL_SLOW_CONFIG => ConfigCaveat::Slow,
+      egl::EGL_NON_CONFORMANT_CONFIG => ConfigCaveat::NonConformant,
+      _ => ConfigCaveat::None,
+    };
+
+    EglConfigInfo {
+      config_id: attrib(egl::EGL_CONFIG_ID),
+      caveat,
+      native_visual_id: attrib(egl::EGL_NATIVE_VISUAL_ID),
+      native_visual_type: attrib(egl::EGL_NATIVE_VISUAL_TYPE),
+      native_renderable: attrib(egl::EGL_NATIVE_RENDERABLE) == egl::EGL_TRUE as i32,
+      renderable_type: attrib(egl::EGL_RENDERABLE_TYPE),
+      surface_type: attrib(egl::EGL_SURFACE_TYPE),
+      luminance: attrib(egl::EGL_COLOR_BUFFER_TYPE) == egl::EGL_LUMINANCE_BUFFER,
+      buffer_size: attrib(egl::EGL_BUFFER_SIZE),
+      red_size: attrib(egl::EGL_RED_SIZE),
+      green_size: attrib(egl::EGL_GREEN_SIZE),
+      blue_size: attrib(egl::EGL_BLUE_SIZE),
+      alpha_size: attrib(egl::EGL_ALPHA_SIZE),
+      depth_size: attrib(egl::EGL_DEPTH_SIZE),
+      stencil_size: attrib(egl::EGL_STENCIL_SIZE),
+      samples: attrib(egl::EGL_SAMPLES),
+      sample_buffers: attrib(egl::EGL_SAMPLE_BUFFERS),
+      bind_to_texture_rgb: attrib(egl::EGL_BIND_TO_TEXTURE_RGB) == egl::EGL_TRUE as i32,
+      bind_to_texture_rgba: attrib(egl::EGL_BIND_TO_TEXTURE_RGBA) == egl::EGL_TRUE as i32,
+    }
+  }
+}
+
+/// What a config should have, for `ConfigRequest::best`. Sizes are in bits.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConfigRequest {
+  pub red_size: i32,
+  pub green_size: i32,
+  pub blue_size: i32,
+  pub alpha_size: i32,
+  pub depth_size: i32,
+  pub stencil_size: i32,
+  pub samples: i32,
+  /// Native visual the config must have to be used at all, the GBM format for a GBM surface
+  pub native_visual_id: Option<i32>,
+}
+
+impl Default for ConfigRequest {
+  fn default() -> Self {
+    ConfigRequest {
+      red_size: 8,
+      green_size: 8,
+      blue_size: 8,
+      alpha_size: 0,
+      depth_size: 0,
+      stencil_size: 0,
+      samples: 0,
+      native_visual_id: None,
+    }
+  }
+}
+
+// Missing bits cost a lot more than extra ones
+fn size_penalty(requested: i32, actual: i32) -> i64 {
+  if actual < requested {
+    (requested - actual) as i64 * 100
+  } else {
+    (actual - requested) as i64
+  }
+}
+
+impl ConfigRequest {
+  /// Lower is better, 0 for a config that's exactly what was asked for.
+  pub fn score(&self, config: &EglConfigInfo) -> i64 {
+    let mut score = 0;
+
+    if let Some(visual_id) = self.native_visual_id {
+      if config.native_visual_id != visual_id {
+        score += 1_000_000;
+      }
+    }
+    score += match config.caveat {
+      ConfigCaveat::None => 0,
+      ConfigCaveat::NonConformant => 10_000,
+      ConfigCaveat::Slow => 100_000,
+    };
+
+    score += size_penalty(self.red_size, config.red_size);
+    score += size_penalty(self.green_size, config.green_size);
+    score += size_penalty(self.blue_size, config.blue_size);
+    score += size_penalty(self.alpha_size, config.alpha_size);
+    score += size_penalty(self.depth_size, config.depth_size);
+    score += size_penalty(self.stencil_size, config.stencil_size);
+    score += size_penalty(self.samples, config.samples);
+
+    score
+  }
+
+  /// Best scoring config, the first one of EGL's order on a tie.
+  pub fn best<'a>(&self, configs: &'a [EglConfigInfo]) -> Option<&'a EglConfigInfo> {
+    self.best_index(configs).map(|i| &configs[i])
+  }
+
+  fn best_index(&self, configs: &[EglConfigInfo]) -> Option<usize> {
+    configs
+      .iter()
+      .enumerate()
+      .min_by_key(|(i, config)| (self.score(config), *i))
+      .map(|(i, _)| i)
+  }
+}
+
+/// Best of `configs` for `request`. Without the requested visual there's none.
+pub fn select_config(
+  display: egl::EGLDisplay,
+  configs: Vec<egl::EGLConfig>,
+  request: &ConfigRequest,
+) -> Option<egl::EGLConfig> {
+  let infos: Vec<EglConfigInfo> = configs
+    .iter()
+    .map(|config| EglConfigInfo::query(display, *config))
+    .collect();
+
+  let index = request.best_index(&infos)?;
+  if let Some(visual_id) = request.native_visual_id {
+    if infos[index].native_visual_id != visual_id {
+      return None;
+    }
+  }
+
+  Some(configs[index])
+}
+
+pub fn print_config_info(display: egl::EGLDisplay, config: egl::EGLConfig) {
+  let info = EglConfigInfo::query(display, config);
 
   println!(
     "  Renderable : {:#02x} {:#02x}",
-    native_renderable, renderable_type
+    info.native_renderable as i32, info.renderable_type
+  );
+  println!(
+    "  Visual ID  : {:#02x} {:#02x}",
+    info.native_visual_id, info.native_visual_type
   );
-  println!("  Visual ID  : {:#02x} {:#02x}", visual_id, visual_type);
-  println!("  Config ID  : {:#02x} {:#02x}", config_id, config_caveat);
+  println!("  Config ID  : {:#02x} {:?}", info.config_id, info.caveat);
   println!(
     "  Sizes      : {}, {}, {}, {}, {}, {}",
-    buffer_size, red_size, green_size, blue_size, alpha_size, depth_size
+    info.buffer_size,
+    info.red_size,
+    info.green_size,
+    info.blue_size,
+    info.alpha_size,
+    info.depth_size
   );
-  println!("  Surface    : {:#02x}", surface_type);
+  println!("  Surface    : {:#02x}", info.surface_type);
   println!(
     "  Color Buff : {}",
-    if color_buffer_type == egl::EGL_RGB_BUFFER {
-      "RGB"
-    } else {
-      "LUMINANCE"
-    }
+    if info.luminance { "LUMINANCE" } else { "RGB" }
   );
   println!(
     "  Textures   : {} {}",
-    if rgb_bind == egl::EGL_TRUE as i32 {
+    if info.bind_to_texture_rgb {
       "RGB"
     } else {
       "   "
     },
-    if rgba_bind == egl::EGL_TRUE as i32 {
+    if info.bind_to_texture_rgba {
       "RGBA"
     } else {
       ""
@@ -188,3 +316,94 @@ pub fn print_configs(egl_display: egl::EGLDisplay, egl_configs: &Vec<egl::EGLCon
     print_config_info(egl_display, *egl_config);
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const XRGB8888: i32 = 0x3432_5258;
+  const RGB565: i32 = 0x3631_4752;
+
+  fn config(config_id: i32, visual: i32, red: i32, green: i32, blue: i32) -> EglConfigInfo {
+    EglConfigInfo {
+      config_id,
+      caveat: ConfigCaveat::None,
+      native_visual_id: visual,
+      native_visual_type: 0,
+      native_renderable: true,
+      renderable_type: egl::EGL_OPENGL_ES2_BIT,
+      surface_type: egl::EGL_WINDOW_BIT,
+      luminance: false,
+      buffer_size: red + green + blue,
+      red_size: red,
+      green_size: green,
+      blue_size: blue,
+      alpha_size: 0,
+      depth_size: 0,
+      stencil_size: 0,
+      samples: 0,
+      sample_buffers: 0,
+      bind_to_texture_rgb: false,
+      bind_to_texture_rgba: false,
+    }
+  }
+
+  #[test]
+  fn exact_match_scores_zero() {
+    let request = ConfigRequest::default();
+    assert_eq!(request.score(&config(1, XRGB8888, 8, 8, 8)), 0);
+  }
+
+  #[test]
+  fn ties_go_to_the_first_in_egl_order() {
+    let request = ConfigRequest::default();
+    let configs = [
+      config(1, RGB565, 5, 6, 5),
+      config(2, XRGB8888, 8, 8, 8),
+      config(3, XRGB8888, 8, 8, 8),
+    ];
+    assert_eq!(request.best_index(&configs), Some(1));
+    assert_eq!(request.best(&configs).map(|c| c.config_id), Some(2));
+  }
+
+  #[test]
+  fn missing_bits_cost_more_than_extra_ones() {
+    let request = ConfigRequest::default();
+    let configs = [config(1, RGB565, 5, 6, 5), config(2, 0, 10, 10, 10)];
+    assert_eq!(request.best_index(&configs), Some(1));
+  }
+
+  #[test]
+  fn other_visuals_lose_to_any_matching_one() {
+    let request = ConfigRequest {
+      native_visual_id: Some(RGB565),
+      ..Default::default()
+    };
+    let mut slow = config(2, RGB565, 5, 6, 5);
+    slow.caveat = ConfigCaveat::Slow;
+    let configs = [config(1, XRGB8888, 8, 8, 8), slow];
+    assert!(request.score(&configs[0]) > request.score(&configs[1]));
+    assert_eq!(request.best_index(&configs), Some(1));
+  }
+
+  #[test]
+  fn slow_ranks_below_non_conformant() {
+    let request = ConfigRequest::default();
+    let mut slow = config(1, XRGB8888, 8, 8, 8);
+    slow.caveat = ConfigCaveat::Slow;
+    let mut non_conformant = config(2, XRGB8888, 8, 8, 8);
+    non_conformant.caveat = ConfigCaveat::NonConformant;
+    let plain = config(3, RGB565, 5, 6, 5);
+
+    let configs = [slow.clone(), non_conformant.clone()];
+    assert_eq!(request.best_index(&configs), Some(1));
+    // A caveat weighs more than a smaller color buffer
+    let configs = [slow, non_conformant, plain];
+    assert_eq!(request.best_index(&configs), Some(2));
+  }
+
+  #[test]
+  fn nothing_to_pick_from() {
+    assert_eq!(ConfigRequest::default().best(&[]), None);
+  }
+}
@@ -0,0 +1,113 @@
+#![allow(dead_code)]
+
+use calloop::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+use std::io;
+use std::os::unix::io::BorrowedFd;
+
+use crate::presentation::PresentationFeedback;
+use crate::Context;
+
+/// Owns the context and feeds `dispatch_events` results to the callback, along with the context
+/// itself so the next frame can be rendered and queued from there. Session notifications (see
+/// `Context::session_fd`) are picked up too.
+pub struct ContextSource {
+  context: Context,
+  token: Option<Token>,
+  session_token: Option<Token>,
+}
+
+impl ContextSource {
+  pub fn new(context: Context) -> ContextSource {
+    ContextSource {
+      context,
+      token: None,
+      session_token: None,
+    }
+  }
+
+  // Owned by the session inside the context, open for as long as we own it
+  fn session_fd(&self) -> Option<BorrowedFd<'_>> {
+    self
+      .context
+      .session_fd()
+      .map(|fd| unsafe { BorrowedFd::borrow_raw(fd) })
+  }
+
+  pub fn context(&self) -> &Context {
+    &self.context
+  }
+
+  pub fn context_mut(&mut self) -> &mut Context {
+    &mut self.context
+  }
+
+  pub fn into_inner(self) -> Context {
+    self.context
+  }
+}
+
+impl EventSource for ContextSource {
+  type Event = PresentationFeedback;
+  type Metadata = Context;
+  type Ret = ();
+  type Error = io::Error;
+
+  fn process_events<F>(
+    &mut self,
+    _readiness: Readiness,
+    token: Token,
+    mut callback: F,
+  ) -> Result<PostAction, Self::Error>
+  where
+    F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+  {
+    if self.token != Some(token) && self.session_token != Some(token) {
+      return Ok(PostAction::Continue);
+    }
+
+    for feedback in self.context.dispatch_events() {
+      callback(feedback, &mut self.context);
+    }
+
+    Ok(PostAction::Continue)
+  }
+
+  fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> calloop::Result<()> {
+    let token = token_factory.token();
+    // The fds stay open for as long as we own the context, and we unregister them before dropping
+    unsafe { poll.register(&self.context, Interest::READ, Mode::Level, token)? };
+    self.token = Some(token);
+    if let Some(fd) = self.session_fd() {
+      let token = token_factory.token();
+      unsafe { poll.register(fd, Interest::READ, Mode::Level, token)? };
+      self.session_token = Some(token);
+    }
+    Ok(())
+  }
+
+  fn reregister(
+    &mut self,
+    poll: &mut Poll,
+    token_factory: &mut TokenFactory,
+  ) -> calloop::Result<()> {
+    let token = token_factory.token();
+    poll.reregister(&self.context, Interest::READ, Mode::Level, token)?;
+    self.token = Some(token);
+    if let Some(fd) = self.session_fd() {
+      let token = token_factory.token();
+      poll.reregister(fd, Interest::READ, Mode::Level, token)?;
+      self.session_token = Some(token);
+    }
+    Ok(())
+  }
+
+  fn unregister(&mut self, poll: &mut Poll) -> calloop::Result<()> {
+    poll.unregister(&self.context)?;
+    self.token = None;
+    if let Some(fd) = self.session_fd() {
+      poll.unregister(fd)?;
+    }
+    self.session_token = None;
+    Ok(())
+  }
+}
@@ -0,0 +1,40 @@
+use mio::event::Source;
+use mio::unix::SourceFd;
+use mio::{Interest, Registry, Token};
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+use crate::Context;
+
+/// Readable when page flips complete or the session has news, call `dispatch_events` then. Both
+/// fds are registered with the same token.
+impl Source for Context {
+  fn register(&mut self, registry: &Registry, token: Token, interests: Interest) -> io::Result<()> {
+    SourceFd(&self.as_raw_fd()).register(registry, token, interests)?;
+    if let Some(fd) = self.session_fd() {
+      SourceFd(&fd).register(registry, token, interests)?;
+    }
+    Ok(())
+  }
+
+  fn reregister(
+    &mut self,
+    registry: &Registry,
+    token: Token,
+    interests: Interest,
+  ) -> io::Result<()> {
+    SourceFd(&self.as_raw_fd()).reregister(registry, token, interests)?;
+    if let Some(fd) = self.session_fd() {
+      SourceFd(&fd).reregister(registry, token, interests)?;
+    }
+    Ok(())
+  }
+
+  fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+    SourceFd(&self.as_raw_fd()).deregister(registry)?;
+    if let Some(fd) = self.session_fd() {
+      SourceFd(&fd).deregister(registry)?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,127 @@
+#![allow(dead_code)]
+
+use futures_core::Stream;
+use std::future;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use crate::presentation::{PresentationFeedback, VblankInfo};
+use crate::Context;
+
+/// The context registered with the tokio reactor. Needs a runtime with IO enabled.
+pub struct AsyncContext {
+  // Deregistered before the context (and the session owning the fd) goes
+  session: Option<AsyncFd<RawFd>>,
+  inner: AsyncFd<Context>,
+}
+
+impl AsyncContext {
+  pub fn new(context: Context) -> io::Result<AsyncContext> {
+    // SAFETY: the session fd stays open as long as the context, which outlives its
+    // registration (see `session`)
+    let session = match context.session_fd() {
+      Some(fd) => Some(unsafe { AsyncFd::register_with_interest(fd, Interest::READABLE)? }),
+      None => None,
+    };
+    // SAFETY: the context owns the card fd and always reports the same one
+    let inner = unsafe { AsyncFd::register_with_interest(context, Interest::READABLE)? };
+    Ok(AsyncContext { session, inner })
+  }
+
+  pub fn context(&self) -> &Context {
+    self.inner.get_ref()
+  }
+
+  pub fn context_mut(&mut self) -> &mut Context {
+    self.inner.get_mut()
+  }
+
+  pub fn into_inner(self) -> Context {
+    let AsyncContext { session, inner } = self;
+    drop(session);
+    inner.into_inner()
+  }
+
+  /// Waits for page flips to complete and returns their feedback, never empty.
+  pub async fn presented(&mut self) -> io::Result<Vec<PresentationFeedback>> {
+    loop {
+      let feedback = self.inner.get_mut().take_feedback();
+      if !feedback.is_empty() {
+        return Ok(feedback);
+      }
+
+      future::poll_fn(|cx| self.poll_events(cx)).await?;
+    }
+  }
+
+  // Waits for the device or the session fd and processes what came in
+  fn poll_events(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+    if let Some(session) = self.session.as_ref() {
+      match session.poll_read_ready(cx) {
+        Poll::Ready(Ok(mut ready)) => {
+          // Cleared first, so whatever arrives while dispatching wakes us again
+          ready.clear_ready();
+          self.inner.get_mut().process_events();
+          return Poll::Ready(Ok(()));
+        }
+        Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+        Poll::Pending => {}
+      }
+    }
+
+    let mut ready = match self.inner.poll_read_ready_mut(cx) {
+      Poll::Ready(Ok(ready)) => ready,
+      Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+      Poll::Pending => return Poll::Pending,
+    };
+    if !ready.get_inner_mut().process_events() {
+      // Drained, wait for the next wakeup
+      ready.clear_ready();
+    }
+    Poll::Ready(Ok(()))
+  }
+
+  /// Resolves at the next vblank. Vblanks that went by while nobody was waiting aren't reported,
+  /// compare `sequence`s to spot them.
+  pub async fn next_vblank(&mut self) -> io::Result<VblankInfo> {
+    future::poll_fn(|cx| self.poll_vblank(cx)).await
+  }
+
+  /// `next_vblank`, over and over.
+  pub fn vblanks(&mut self) -> VblankStream<'_> {
+    VblankStream { context: self }
+  }
+
+  pub fn poll_vblank(&mut self, cx: &mut TaskContext<'_>) -> Poll<io::Result<VblankInfo>> {
+    loop {
+      if let Some(vblank) = self.inner.get_mut().take_vblanks().pop() {
+        return Poll::Ready(Ok(vblank));
+      }
+      if let Err(error) = self.inner.get_mut().request_vblank() {
+        return Poll::Ready(Err(error));
+      }
+
+      match self.poll_events(cx) {
+        Poll::Ready(Ok(())) => {}
+        Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+        Poll::Pending => return Poll::Pending,
+      }
+    }
+  }
+}
+
+pub struct VblankStream<'a> {
+  context: &'a mut AsyncContext,
+}
+
+impl<'a> Stream for VblankStream<'a> {
+  type Item = io::Result<VblankInfo>;
+
+  fn poll_next(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+    self.get_mut().context.poll_vblank(cx).map(Some)
+  }
+}
@@ -0,0 +1,334 @@
+#![allow(dead_code)]
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::presentation::{PresentMode, PresentationFeedback};
+use crate::Context;
+
+// Frames kept around for the rolling statistics, a few seconds worth at 60Hz
+const STATS_WINDOW: usize = 240;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FramePacing {
+  /// Let `swap_buffers` block on vblank, i.e. run at the display refresh rate (Fifo mode).
+  Vblank,
+  /// Sleep until the next frame is due. Works with any present mode.
+  TargetFps(f64),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FrameInfo<'a> {
+  /// Starts at 0
+  pub index: u64,
+  /// Time since the previous frame started, zero on the first one
+  pub delta: Duration,
+  /// Time since the loop started
+  pub elapsed: Duration,
+  /// Presentation of the previous frame, when known
+  pub feedback: Option<PresentationFeedback>,
+  pub stats: &'a FrameStats,
+}
+
+/// Frame time statistics over the last few seconds, plus a running count of dropped frames.
+#[derive(Clone, Debug, Default)]
+pub struct FrameStats {
+  frame_times: VecDeque<Duration>,
+  frames: u64,
+  dropped_frames: u64,
+  // Vblank of the last presented frame, once the backend reported one
+  last_sequence: Option<u32>,
+}
+
+impl FrameStats {
+  pub fn new() -> FrameStats {
+    FrameStats {
+      frame_times: VecDeque::with_capacity(STATS_WINDOW),
+      frames: 0,
+      dropped_frames: 0,
+      last_sequence: None,
+    }
+  }
+
+  pub fn record(&mut self, frame_time: Duration) {
+    if self.frame_times.len() == STATS_WINDOW {
+      self.frame_times.pop_front();
+    }
+    self.frame_times.push_back(frame_time);
+    self.frames += 1;
+  }
+
+  pub fn record_dropped(&mut self) {
+    self.dropped_frames += 1;
+  }
+
+  /// Counts the frames dropped before `feedback`'s from the vblanks since the previous one, each
+  /// frame being due `period` after the last (every refresh when `None`). Ignored when the
+  /// backend can't tell the refresh interval.
+  pub fn record_presented(&mut self, feedback: &PresentationFeedback, period: Option<Duration>) {
+    let refresh = match feedback.refresh {
+      Some(refresh) if !refresh.is_zero() => refresh,
+      _ => return,
+    };
+    if let Some(last_sequence) = self.last_sequence {
+      let refreshes = feedback.sequence.wrapping_sub(last_sequence) as f64;
+      let refreshes_per_frame = period.map_or(1.0, |period| {
+        (period.as_secs_f64() / refresh.as_secs_f64())
+          .round()
+          .max(1.0)
+      });
+      let frames = (refreshes / refreshes_per_frame).round() as u64;
+      self.dropped_frames += frames.saturating_sub(1);
+    }
+    self.last_sequence = Some(feedback.sequence);
+  }
+
+  // Drops are counted from presentation feedback once there is some, from the pacing otherwise
+  fn tracks_presentation(&self) -> bool {
+    self.last_sequence.is_some()
+  }
+
+  /// Frames recorded since the loop started
+  pub fn frames(&self) -> u64 {
+    self.frames
+  }
+
+  /// Frames dropped since the loop started
+  pub fn dropped_frames(&self) -> u64 {
+    self.dropped_frames
+  }
+
+  pub fn min(&self) -> Duration {
+    self.frame_times.iter().min().copied().unwrap_or_default()
+  }
+
+  pub fn max(&self) -> Duration {
+    self.frame_times.iter().max().copied().unwrap_or_default()
+  }
+
+  pub fn avg(&self) -> Duration {
+    if self.frame_times.is_empty() {
+      return Duration::default();
+    }
+    self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+  }
+
+  pub fn percentile(&self, percentile: f64) -> Duration {
+    if self.frame_times.is_empty() {
+      return Duration::default();
+    }
+    let mut sorted: Vec<Duration> = self.frame_times.iter().copied().collect();
+    sorted.sort();
+
+    let rank = (percentile / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank.min(sorted.len() - 1)]
+  }
+
+  pub fn p99(&self) -> Duration {
+    self.percentile(99.0)
+  }
+}
+
+impl fmt::Display for FrameStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    write!(
+      f,
+      "frame time min {:.2}ms avg {:.2}ms max {:.2}ms p99 {:.2}ms, {} dropped of {}",
+      ms(self.min()),
+      ms(self.avg()),
+      ms(self.max()),
+      ms(self.p99()),
+      self.dropped_frames,
+      self.frames,
+    )
+  }
+}
+
+impl Context {
+  /// Renders frames paced to vblank until `draw` returns `false`, in `PresentMode::Fifo`. `draw`
+  /// shouldn't call `swap_buffers`, the loop does.
+  pub fn run<F>(&mut self, draw: F) -> FrameStats
+  where
+    F: FnMut(&mut Context, FrameInfo) -> bool,
+  {
+    self.run_with(FramePacing::Vblank, draw)
+  }
+
+  /// `FramePacing::Vblank` switches to `PresentMode::Fifo` until the loop ends.
+  pub fn run_with<F>(&mut self, pacing: FramePacing, mut draw: F) -> FrameStats
+  where
+    F: FnMut(&mut Context, FrameInfo) -> bool,
+  {
+    let present_mode = self.present_mode();
+    if pacing == FramePacing::Vblank {
+      self.set_present_mode(PresentMode::Fifo);
+    }
+
+    let period = match pacing {
+      FramePacing::TargetFps(fps) if fps > 0.0 => Some(Duration::from_secs_f64(1.0 / fps)),
+      _ => None,
+    };
+
+    let mut stats = FrameStats::new();
+    let mut feedback = None;
+
+    let start = Instant::now();
+    let mut previous = start;
+    let mut deadline = start;
+    let mut index = 0u64;
+
+    loop {
+      let now = Instant::now();
+      let delta = now - previous;
+      previous = now;
+      if index > 0 {
+        stats.record(delta);
+      }
+
+      let info = FrameInfo {
+        index,
+        delta,
+        elapsed: now - start,
+        feedback,
+        stats: &stats,
+      };
+      if !draw(self, info) {
+        break;
+      }
+
+      // Earlier frames that reached the screen since, then this one if it already did
+      let swapped = self.swap_buffers();
+      let mut presented = self.take_feedback();
+      presented.extend(swapped);
+      for presented in &presented {
+        stats.record_presented(presented, period);
+      }
+      feedback = presented.last().copied();
+
+      if let Some(period) = period {
+        // Scheduled from the loop start so sleep jitter doesn't add up
+        deadline += period;
+        let now = Instant::now();
+        if deadline > now {
+          thread::sleep(deadline - now);
+        } else if now - deadline > period {
+          // A whole frame late, skip ahead instead of rushing to catch up
+          while deadline + period < now {
+            deadline += period;
+            if !stats.tracks_presentation() {
+              stats.record_dropped();
+            }
+          }
+        }
+      }
+
+      index += 1;
+    }
+
+    if self.present_mode() != present_mode {
+      self.set_present_mode(present_mode);
+    }
+
+    stats
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const REFRESH: Duration = Duration::from_micros(16_667);
+
+  fn presented(sequence: u32) -> PresentationFeedback {
+    PresentationFeedback {
+      frame: sequence as u64,
+      sequence,
+      timestamp: REFRESH * sequence,
+      refresh: Some(REFRESH),
+      missed_deadline: false,
+    }
+  }
+
+  fn dropped(sequences: &[u32], period: Option<Duration>) -> u64 {
+    let mut stats = FrameStats::new();
+    for &sequence in sequences {
+      stats.record_presented(&presented(sequence), period);
+    }
+    stats.dropped_frames()
+  }
+
+  #[test]
+  fn summarizes_frame_times() {
+    let mut stats = FrameStats::new();
+    assert_eq!(stats.avg(), Duration::default());
+    assert_eq!(stats.p99(), Duration::default());
+
+    for ms in 1..=100 {
+      stats.record(Duration::from_millis(ms));
+    }
+    assert_eq!(stats.frames(), 100);
+    assert_eq!(stats.min(), Duration::from_millis(1));
+    assert_eq!(stats.max(), Duration::from_millis(100));
+    assert_eq!(stats.avg(), Duration::from_micros(50_500));
+    assert_eq!(stats.percentile(50.0), Duration::from_millis(51));
+    assert_eq!(stats.p99(), Duration::from_millis(99));
+  }
+
+  #[test]
+  fn keeps_a_window_of_frame_times() {
+    let mut stats = FrameStats::new();
+    stats.record(Duration::from_secs(1));
+    for _ in 0..STATS_WINDOW {
+      stats.record(Duration::from_millis(16));
+    }
+    assert_eq!(stats.max(), Duration::from_millis(16));
+    assert_eq!(stats.frames(), STATS_WINDOW as u64 + 1);
+  }
+
+  #[test]
+  fn counts_skipped_vblanks_as_drops() {
+    assert_eq!(dropped(&[10, 11, 12, 13], None), 0);
+    assert_eq!(dropped(&[10, 12], None), 1);
+    assert_eq!(dropped(&[10, 11, 15], None), 3);
+    // Several frames within one vblank (Mailbox, Immediate) drop nothing
+    assert_eq!(dropped(&[10, 10, 11], None), 0);
+  }
+
+  #[test]
+  fn judges_drops_against_the_target_period() {
+    let fps30 = Some(Duration::from_secs_f64(1.0 / 30.0));
+    assert_eq!(dropped(&[10, 12, 14], fps30), 0);
+    assert_eq!(dropped(&[10, 14], fps30), 1);
+    // Faster than the display still expects a vblank per frame
+    let fps120 = Some(Duration::from_secs_f64(1.0 / 120.0));
+    assert_eq!(dropped(&[10, 11, 13], fps120), 1);
+  }
+
+  #[test]
+  fn ignores_feedback_without_vblanks() {
+    let mut stats = FrameStats::new();
+    for frame in [0, 5] {
+      let feedback = PresentationFeedback {
+        refresh: None,
+        ..presented(frame)
+      };
+      stats.record_presented(&feedback, None);
+    }
+    assert_eq!(stats.dropped_frames(), 0);
+    assert!(!stats.tracks_presentation());
+  }
+
+  #[test]
+  fn displays_a_summary() {
+    let mut stats = FrameStats::new();
+    stats.record(Duration::from_millis(10));
+    stats.record_dropped();
+    assert_eq!(
+      stats.to_string(),
+      "frame time min 10.00ms avg 10.00ms max 10.00ms p99 10.00ms, 1 dropped of 1"
+    );
+  }
+}
@@ -0,0 +1,587 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::{self, size_of};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::ptr;
+
+use crate::dmabuf::{RenderBuffer, ScanoutBuffer};
+use crate::egl_image::{DmabufDesc, DmabufPlane};
+use crate::presentation::PresentationFeedback;
+use crate::Context;
+
+const MAX_PLANES: usize = 4;
+
+const MESSAGE_ADD_BUFFER: u32 = 1;
+const MESSAGE_REMOVE_BUFFER: u32 = 2;
+const MESSAGE_PRESENT: u32 = 3;
+const MESSAGE_RELEASE: u32 = 4;
+
+// What goes over the socket, fixed size, with the plane fds of `MESSAGE_ADD_BUFFER` attached as
+// SCM_RIGHTS. Both ends run on the same machine, so native endianness is fine.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawShareMessage {
+  pub kind: u32,
+  pub id: u32,
+  pub width: u32,
+  pub height: u32,
+  pub fourcc: u32,
+  pub plane_count: u32,
+  pub modifier: u64,
+  pub frame: u64,
+  pub offsets: [u32; MAX_PLANES],
+  pub strides: [u32; MAX_PLANES],
+}
+
+/// Renderer to presenter: `AddBuffer`, `RemoveBuffer`, `Present`.
+/// Presenter to renderer: `Release`.
+#[derive(Debug)]
+pub enum ShareMessage {
+  /// A buffer the renderer will present by `id` from now on. One fd per plane.
+  AddBuffer {
+    id: u32,
+    width: u32,
+    height: u32,
+    fourcc: u32,
+    modifier: u64,
+    fds: Vec<File>,
+    offsets: Vec<u32>,
+    strides: Vec<u32>,
+  },
+  RemoveBuffer {
+    id: u32,
+  },
+  Present {
+    id: u32,
+    frame: u64,
+  },
+  /// The buffer is off screen, the renderer can draw into it again.
+  Release {
+    id: u32,
+  },
+}
+
+/// One end of a Unix stream socket carrying `ShareMessage`s.
+pub struct FrameChannel {
+  stream: UnixStream,
+}
+
+impl FrameChannel {
+  pub fn new(stream: UnixStream) -> FrameChannel {
+    FrameChannel { stream }
+  }
+
+  /// Both ends, e.g. to hand one to a child process.
+  pub fn pair() -> io::Result<(FrameChannel, FrameChannel)> {
+    let (a, b) = UnixStream::pair()?;
+    Ok((FrameChannel::new(a), FrameChannel::new(b)))
+  }
+
+  pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+    self.stream.set_nonblocking(nonblocking)
+  }
+
+  pub fn send(&self, message: &ShareMessage) -> io::Result<()> {
+    let mut raw = RawShareMessage::default();
+    let mut fds: Vec<RawFd> = Vec::new();
+
+    match message {
+      ShareMessage::AddBuffer {
+        id,
+        width,
+        height,
+        fourcc,
+        modifier,
+        fds: plane_fds,
+        offsets,
+        strides,
+      } => {
+        if plane_fds.is_empty()
+          || plane_fds.len() > MAX_PLANES
+          || offsets.len() != plane_fds.len()
+          || strides.len() != plane_fds.len()
+        {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "A buffer has 1 to 4 planes, each with an fd, offset and stride",
+          ));
+        }
+        raw.kind = MESSAGE_ADD_BUFFER;
+        raw.id = *id;
+        raw.width = *width;
+        raw.height = *height;
+        raw.fourcc = *fourcc;
+        raw.modifier = *modifier;
+        raw.plane_count = plane_fds.len() as u32;
+        raw.offsets[..offsets.len()].copy_from_slice(offsets);
+        raw.strides[..strides.len()].copy_from_slice(strides);
+        fds = plane_fds.iter().map(|fd| fd.as_raw_fd()).collect();
+      }
+      ShareMessage::RemoveBuffer { id } => {
+        raw.kind = MESSAGE_REMOVE_BUFFER;
+        raw.id = *id;
+      }
+      ShareMessage::Present { id, frame } => {
+        raw.kind = MESSAGE_PRESENT;
+        raw.id = *id;
+        raw.frame = *frame;
+      }
+      ShareMessage::Release { id } => {
+        raw.kind = MESSAGE_RELEASE;
+        raw.id = *id;
+      }
+    }
+
+    send_raw(self.stream.as_raw_fd(), &raw, &fds)
+  }
+
+  /// `None` when the socket is non-blocking and nothing is pending.
+  pub fn recv(&self) -> io::Result<Option<ShareMessage>> {
+    let (raw, fds) = match recv_raw(&self.stream)? {
+      Some(received) => received,
+      None => return Ok(None),
+    };
+
+    let message = match raw.kind {
+      MESSAGE_ADD_BUFFER => {
+        let planes = raw.plane_count as usize;
+        if planes == 0 || planes > MAX_PLANES || fds.len() != planes {
+          return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Buffer planes and fds don't match",
+          ));
+        }
+        ShareMessage::AddBuffer {
+          id: raw.id,
+          width: raw.width,
+          height: raw.height,
+          fourcc: raw.fourcc,
+          modifier: raw.modifier,
+          fds,
+          offsets: raw.offsets[..planes].to_vec(),
+          strides: raw.strides[..planes].to_vec(),
+        }
+      }
+      MESSAGE_REMOVE_BUFFER => ShareMessage::RemoveBuffer { id: raw.id },
+      MESSAGE_PRESENT => ShareMessage::Present {
+        id: raw.id,
+        frame: raw.frame,
+      },
+      MESSAGE_RELEASE => ShareMessage::Release { id: raw.id },
+      kind => {
+        return Err(io::Error::new(
+          io::ErrorKind::InvalidData,
+          format!("Unknown message {}", kind),
+        ))
+      }
+    };
+
+    Ok(Some(message))
+  }
+}
+
+impl AsRawFd for FrameChannel {
+  fn as_raw_fd(&self) -> RawFd {
+    self.stream.as_raw_fd()
+  }
+}
+
+fn send_raw(socket: RawFd, raw: &RawShareMessage, fds: &[RawFd]) -> io::Result<()> {
+  let mut iov = libc::iovec {
+    iov_base: raw as *const RawShareMessage as *mut libc::c_void,
+    iov_len: size_of::<RawShareMessage>(),
+  };
+
+  let fds_size = mem::size_of_val(fds) as u32;
+  let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size) } as usize];
+
+  let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+  msg.msg_iov = &mut iov;
+  msg.msg_iovlen = 1;
+  if !fds.is_empty() {
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = control.len() as _;
+    unsafe {
+      let cmsg = libc::CMSG_FIRSTHDR(&msg);
+      (*cmsg).cmsg_level = libc::SOL_SOCKET;
+      (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+      (*cmsg).cmsg_len = libc::CMSG_LEN(fds_size) as _;
+      ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+    }
+  }
+
+  loop {
+    let sent = unsafe { libc::sendmsg(socket, &msg, libc::MSG_NOSIGNAL) };
+    if sent < 0 {
+      let error = io::Error::last_os_error();
+      if error.kind() == io::ErrorKind::Interrupted {
+        continue;
+      }
+      return Err(error);
+    }
+    if (sent as usize) < size_of::<RawShareMessage>() {
+      return Err(io::Error::new(
+        io::ErrorKind::WriteZero,
+        "Short write on the frame channel",
+      ));
+    }
+    return Ok(());
+  }
+}
+
+fn recv_raw(stream: &UnixStream) -> io::Result<Option<(RawShareMessage, Vec<File>)>> {
+  let mut raw = RawShareMessage::default();
+  let mut iov = libc::iovec {
+    iov_base: &mut raw as *mut RawShareMessage as *mut libc::c_void,
+    iov_len: size_of::<RawShareMessage>(),
+  };
+
+  let fds_size = (MAX_PLANES * size_of::<RawFd>()) as u32;
+  let mut control = vec![0u8; unsafe { libc::CMSG_SPACE(fds_size) } as usize];
+
+  let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+  msg.msg_iov = &mut iov;
+  msg.msg_iovlen = 1;
+  msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+  msg.msg_controllen = control.len() as _;
+
+  let received = loop {
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if received < 0 {
+      let error = io::Error::last_os_error();
+      match error.kind() {
+        io::ErrorKind::Interrupted => continue,
+        io::ErrorKind::WouldBlock => return Ok(None),
+        _ => return Err(error),
+      }
+    }
+    break received as usize;
+  };
+  if received == 0 {
+    return Err(io::Error::new(
+      io::ErrorKind::UnexpectedEof,
+      "Frame channel closed",
+    ));
+  }
+
+  // Take ownership of whatever fds came along first, so they're closed on errors
+  let mut fds = Vec::new();
+  unsafe {
+    let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+    while !cmsg.is_null() {
+      if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+        let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+        let count = ((*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize) / size_of::<RawFd>();
+        for i in 0..count {
+          fds.push(File::from_raw_fd(ptr::read_unaligned(data.add(i))));
+        }
+      }
+      cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+    }
+  }
+  if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+    return Err(io::Error::new(
+      io::ErrorKind::InvalidData,
+      "Too many fds in one message",
+    ));
+  }
+
+  if received < size_of::<RawShareMessage>() {
+    // Stream socket, the rest of the message is on its way
+    let bytes = unsafe {
+      std::slice::from_raw_parts_mut(
+        &mut raw as *mut RawShareMessage as *mut u8,
+        size_of::<RawShareMessage>(),
+      )
+    };
+    read_rest(stream, &mut bytes[received..])?;
+  }
+
+  Ok(Some((raw, fds)))
+}
+
+// Finishes a message whose start was already received. The sender writes it in one go, so on a
+// non-blocking socket this only waits for the tail to arrive instead of dropping the head.
+fn read_rest(mut stream: &UnixStream, mut bytes: &mut [u8]) -> io::Result<()> {
+  while !bytes.is_empty() {
+    match stream.read(bytes) {
+      Ok(0) => {
+        return Err(io::Error::new(
+          io::ErrorKind::UnexpectedEof,
+          "Frame channel closed mid-message",
+        ))
+      }
+      Ok(read) => bytes = &mut bytes[read..],
+      Err(error) => match error.kind() {
+        io::ErrorKind::Interrupted => {}
+        io::ErrorKind::WouldBlock => {
+          let mut poll_fd = libc::pollfd {
+            fd: stream.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+          };
+          if unsafe { libc::poll(&mut poll_fd, 1, -1) } < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+              return Err(error);
+            }
+          }
+        }
+        _ => return Err(error),
+      },
+    }
+  }
+  Ok(())
+}
+
+/// Renderer side: shares `RenderBuffer`s with a presenter and keeps track of which ones it is
+/// done with.
+pub struct FrameSender {
+  channel: FrameChannel,
+  busy: Vec<bool>,
+  frame: u64,
+}
+
+impl FrameSender {
+  /// Puts the channel in non-blocking mode, releases are picked up by `dispatch`.
+  pub fn new(channel: FrameChannel) -> io::Result<FrameSender> {
+    channel.set_nonblocking(true)?;
+    Ok(FrameSender {
+      channel,
+      busy: Vec::new(),
+      frame: 0,
+    })
+  }
+
+  /// Sends the buffer over, returns the id to `present` it with.
+  pub fn add_buffer(&mut self, buffer: &RenderBuffer) -> io::Result<u32> {
+    let id = self.busy.len() as u32;
+    let frame = buffer.export()?;
+    self.channel.send(&ShareMessage::AddBuffer {
+      id,
+      width: frame.width,
+      height: frame.height,
+      fourcc: frame.fourcc,
+      modifier: frame.modifier,
+      fds: frame.fds,
+      offsets: frame.offsets,
+      strides: frame.strides,
+    })?;
+    self.busy.push(false);
+    Ok(id)
+  }
+
+  /// Tells the presenter to forget the buffer, it may still be on screen for a while.
+  pub fn remove_buffer(&mut self, id: u32) -> io::Result<()> {
+    if let Some(busy) = self.busy.get_mut(id as usize) {
+      // Never handed out again
+      *busy = true;
+    }
+    self.channel.send(&ShareMessage::RemoveBuffer { id })
+  }
+
+  /// Flush GL rendering into the buffer first (`RenderBuffer::flush`). The buffer is busy until
+  /// the presenter releases it.
+  pub fn present(&mut self, id: u32) -> io::Result<()> {
+    let busy = self
+      .busy
+      .get_mut(id as usize)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown buffer"))?;
+    *busy = true;
+
+    let frame = self.frame;
+    self.frame += 1;
+    self.channel.send(&ShareMessage::Present { id, frame })
+  }
+
+  /// Processes releases from the presenter, never blocks.
+  pub fn dispatch(&mut self) -> io::Result<()> {
+    while let Some(message) = self.channel.recv()? {
+      if let ShareMessage::Release { id } = message {
+        if let Some(busy) = self.busy.get_mut(id as usize) {
+          *busy = false;
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// A buffer that's free to render into, if any.
+  pub fn acquire(&mut self) -> io::Result<Option<u32>> {
+    self.dispatch()?;
+    Ok(self.busy.iter().position(|busy| !busy).map(|id| id as u32))
+  }
+}
+
+/// Readable when the presenter released buffers.
+impl AsRawFd for FrameSender {
+  fn as_raw_fd(&self) -> RawFd {
+    self.channel.as_raw_fd()
+  }
+}
+
+/// Presenter side: imports the renderer's buffers as framebuffers and scans them out through the
+/// context, sending them back once they're off screen.
+pub struct FramePresenter {
+  channel: FrameChannel,
+  buffers: HashMap<u32, ScanoutBuffer>,
+  // Removed (or replaced) by the renderer while still on screen, by framebuffer: ids get reused
+  removed: HashMap<u32, ScanoutBuffer>,
+}
+
+impl FramePresenter {
+  /// Puts the channel in non-blocking mode.
+  pub fn new(channel: FrameChannel) -> io::Result<FramePresenter> {
+    channel.set_nonblocking(true)?;
+    Ok(FramePresenter {
+      channel,
+      buffers: HashMap::new(),
+      removed: HashMap::new(),
+    })
+  }
+
+  /// Call when either the channel or the context fd is readable. Presents what the renderer asked
+  /// for (newest wins when several frames are waiting), releases what went off screen and returns
+  /// the presentation feedback gathered along the way.
+  pub fn dispatch(&mut self, context: &mut Context) -> io::Result<Vec<PresentationFeedback>> {
+    let mut feedback = context.dispatch_events();
+
+    while let Some(message) = self.channel.recv()? {
+      match message {
+        ShareMessage::AddBuffer {
+          id,
+          width,
+          height,
+          fourcc,
+          modifier,
+          fds,
+          offsets,
+          strides,
+        } => {
+          let desc = DmabufDesc {
+            width,
+            height,
+            fourcc,
+            modifier,
+            planes: fds
+              .iter()
+              .zip(offsets.iter().zip(strides.iter()))
+              .map(|(fd, (offset, stride))| DmabufPlane {
+                fd: fd.as_raw_fd(),
+                offset: *offset,
+                stride: *stride,
+              })
+              .collect(),
+          };
+          // The framebuffer keeps its own reference, the fds can go
+          let buffer = context.import_scanout_buffer(&desc)?;
+          if let Some(replaced) = self.buffers.insert(id, buffer) {
+            self.removed.insert(replaced.fb(), replaced);
+          }
+        }
+        ShareMessage::RemoveBuffer { id } => {
+          if let Some(buffer) = self.buffers.remove(&id) {
+            self.removed.insert(buffer.fb(), buffer);
+          }
+        }
+        ShareMessage::Present { id, .. } => match self.buffers.get(&id) {
+          Some(buffer) => feedback.extend(context.present_buffer(buffer)),
+          None => self.channel.send(&ShareMessage::Release { id })?,
+        },
+        ShareMessage::Release { .. } => {}
+      }
+    }
+
+    for fb in context.take_released_buffers() {
+      if let Some((id, _)) = self.buffers.iter().find(|(_, buffer)| buffer.fb() == fb) {
+        self.channel.send(&ShareMessage::Release { id: *id })?;
+      }
+    }
+
+    // Removed buffers go once they're off screen
+    let idle: Vec<u32> = self
+      .removed
+      .iter()
+      .filter(|(_, buffer)| !context.is_buffer_in_use(buffer))
+      .map(|(fb, _)| *fb)
+      .collect();
+    for fb in idle {
+      let buffer = self.removed.remove(&fb).unwrap();
+      context.remove_scanout_buffer(buffer);
+    }
+
+    Ok(feedback)
+  }
+}
+
+/// Readable when the renderer sent something.
+impl AsRawFd for FramePresenter {
+  fn as_raw_fd(&self) -> RawFd {
+    self.channel.as_raw_fd()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::Write;
+  use std::thread;
+  use std::time::Duration;
+
+  #[test]
+  fn round_trips_messages() {
+    let (a, b) = FrameChannel::pair().unwrap();
+    b.set_nonblocking(true).unwrap();
+    assert!(b.recv().unwrap().is_none());
+
+    a.send(&ShareMessage::Present { id: 3, frame: 7 }).unwrap();
+    match b.recv().unwrap() {
+      Some(ShareMessage::Present { id: 3, frame: 7 }) => {}
+      other => panic!("Unexpected {:?}", other),
+    }
+  }
+
+  #[test]
+  fn waits_for_the_rest_of_a_split_message() {
+    let (a, b) = UnixStream::pair().unwrap();
+    let receiver = FrameChannel::new(b);
+    receiver.set_nonblocking(true).unwrap();
+
+    let raw = RawShareMessage {
+      kind: MESSAGE_RELEASE,
+      id: 5,
+      ..Default::default()
+    };
+    let bytes = unsafe {
+      std::slice::from_raw_parts(
+        &raw as *const RawShareMessage as *const u8,
+        size_of::<RawShareMessage>(),
+      )
+    }
+    .to_vec();
+
+    let writer = thread::spawn(move || {
+      let mut a = a;
+      a.write_all(&bytes[..10]).unwrap();
+      thread::sleep(Duration::from_millis(50));
+      a.write_all(&bytes[10..]).unwrap();
+      a
+    });
+    let message = loop {
+      if let Some(message) = receiver.recv().unwrap() {
+        break message;
+      }
+      thread::sleep(Duration::from_millis(1));
+    };
+    match message {
+      ShareMessage::Release { id: 5 } => {}
+      other => panic!("Unexpected {:?}", other),
+    }
+    let _a = writer.join().unwrap();
+    assert!(receiver.recv().unwrap().is_none());
+  }
+}
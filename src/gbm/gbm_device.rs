@@ -0,0 +1,227 @@
+#![allow(dead_code)]
+
+// Owned GBM objects on top of `mini_gbm`. The lifetimes make the compiler check what GBM wants:
+// the DRM fd outlives the device, the device outlives its surfaces, and a surface outlives the
+// front buffers locked from it.
+
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+use std::os::unix::io::{AsRawFd, BorrowedFd};
+
+use super::mini_gbm as gbm;
+
+/// A GBM device on a DRM fd.
+#[derive(Debug)]
+pub struct GbmDevice<'fd> {
+  raw: *mut gbm::RawDevice,
+  _fd: PhantomData<BorrowedFd<'fd>>,
+}
+
+impl<'fd> GbmDevice<'fd> {
+  pub fn new(fd: BorrowedFd<'fd>) -> io::Result<GbmDevice<'fd>> {
+    let raw = unsafe { gbm::ffi::gbm_create_device(fd.as_raw_fd()) };
+    if raw.is_null() {
+      return Err(io::Error::other("Couldn't create GBM device"));
+    }
+
+    Ok(GbmDevice {
+      raw,
+      _fd: PhantomData,
+    })
+  }
+
+  /// For EGL (`eglGetDisplay`) and the `mini_gbm` functions. Stays owned by `self`.
+  pub fn as_raw(&self) -> *mut gbm::RawDevice {
+    self.raw
+  }
+
+  /// `flags` are `gbm_bo_flags`, e.g. `GBM_BO_USE_SCANOUT | GBM_BO_USE_RENDERING` for a surface
+  /// that goes to the display.
+  pub fn create_surface(
+    &self,
+    width: u32,
+    height: u32,
+    format: u32,
+    flags: u32,
+  ) -> io::Result<GbmSurface<'_>> {
+    let raw = gbm::surface_create(self.raw, width, height, format, flags);
+    if raw.is_null() {
+      return Err(io::Error::other("Couldn't create GBM surface"));
+    }
+
+    Ok(unsafe { GbmSurface::from_raw(raw) })
+  }
+
+  /// A standalone buffer, `flags` as for `create_surface`.
+  pub fn create_bo(
+    &self,
+    width: u32,
+    height: u32,
+    format: u32,
+    flags: u32,
+  ) -> io::Result<GbmBo<'_>> {
+    let raw = gbm::bo_create(self.raw, width, height, format, flags);
+    if raw.is_null() {
+      return Err(io::Error::last_os_error());
+    }
+
+    Ok(GbmBo {
+      raw,
+      _device: PhantomData,
+    })
+  }
+}
+
+impl Drop for GbmDevice<'_> {
+  fn drop(&mut self) {
+    gbm::device_destroy(self.raw);
+  }
+}
+
+/// A GBM surface, EGL renders into it through a window surface (`eglCreateWindowSurface`).
+#[derive(Debug)]
+pub struct GbmSurface<'dev> {
+  raw: *mut gbm::RawSurface,
+  _device: PhantomData<&'dev ()>,
+}
+
+impl<'dev> GbmSurface<'dev> {
+  /// Takes ownership of a surface.
+  ///
+  /// # Safety
+  ///
+  /// `raw` comes from `gbm_surface_create` (or `into_raw`) and isn't owned by anything else, and
+  /// the device it was created on outlives `'dev`.
+  pub unsafe fn from_raw(raw: *mut gbm::RawSurface) -> GbmSurface<'dev> {
+    GbmSurface {
+      raw,
+      _device: PhantomData,
+    }
+  }
+
+  pub fn as_raw(&self) -> *mut gbm::RawSurface {
+    self.raw
+  }
+
+  /// Gives up ownership, `from_raw` takes it back.
+  pub fn into_raw(self) -> *mut gbm::RawSurface {
+    let raw = self.raw;
+    mem::forget(self);
+    raw
+  }
+
+  /// Locks the buffer `eglSwapBuffers` just finished, `None` if there is none. It isn't rendered
+  /// to again until the returned guard is dropped.
+  pub fn lock_front_buffer(&self) -> Option<FrontBuffer<'_>> {
+    let bo = gbm::surface_lock_front_buffer(self.raw);
+    if bo.is_null() {
+      return None;
+    }
+
+    Some(unsafe { FrontBuffer::from_raw(self.raw, bo) })
+  }
+}
+
+impl Drop for GbmSurface<'_> {
+  fn drop(&mut self) {
+    gbm::surface_destroy(self.raw);
+  }
+}
+
+/// A buffer locked from a `GbmSurface`, handed back to it on drop.
+#[derive(Debug)]
+pub struct FrontBuffer<'surface> {
+  surface: *mut gbm::RawSurface,
+  bo: *mut gbm::RawBO,
+  _surface: PhantomData<&'surface ()>,
+}
+
+impl<'surface> FrontBuffer<'surface> {
+  /// Takes over a locked buffer.
+  ///
+  /// # Safety
+  ///
+  /// `bo` was locked from `surface` (`gbm_surface_lock_front_buffer`, or `into_raw`) and isn't
+  /// released by anything else, and `surface` outlives `'surface`.
+  pub unsafe fn from_raw(surface: *mut gbm::RawSurface, bo: *mut gbm::RawBO) -> Self {
+    FrontBuffer {
+      surface,
+      bo,
+      _surface: PhantomData,
+    }
+  }
+
+  pub fn as_raw(&self) -> *mut gbm::RawBO {
+    self.bo
+  }
+
+  /// Keeps the buffer locked, `from_raw` takes it back.
+  pub fn into_raw(self) -> *mut gbm::RawBO {
+    let bo = self.bo;
+    mem::forget(self);
+    bo
+  }
+
+  pub fn width(&self) -> u32 {
+    gbm::bo_get_width(self.bo)
+  }
+
+  pub fn height(&self) -> u32 {
+    gbm::bo_get_height(self.bo)
+  }
+
+  pub fn stride(&self) -> u32 {
+    gbm::bo_get_stride(self.bo)
+  }
+
+  pub fn format(&self) -> u32 {
+    gbm::bo_get_format(self.bo)
+  }
+
+  /// GEM handle, for `drmModeAddFB`
+  pub fn handle(&self) -> u32 {
+    gbm::bo_get_handle_u32(self.bo)
+  }
+}
+
+impl Drop for FrontBuffer<'_> {
+  fn drop(&mut self) {
+    gbm::surface_release_buffer(self.surface, self.bo);
+  }
+}
+
+/// A buffer object allocated on a `GbmDevice`, destroyed on drop.
+#[derive(Debug)]
+pub struct GbmBo<'dev> {
+  raw: *mut gbm::RawBO,
+  _device: PhantomData<&'dev ()>,
+}
+
+impl GbmBo<'_> {
+  pub fn as_raw(&self) -> *mut gbm::RawBO {
+    self.raw
+  }
+
+  pub fn width(&self) -> u32 {
+    gbm::bo_get_width(self.raw)
+  }
+
+  pub fn height(&self) -> u32 {
+    gbm::bo_get_height(self.raw)
+  }
+
+  pub fn stride(&self) -> u32 {
+    gbm::bo_get_stride(self.raw)
+  }
+
+  pub fn format(&self) -> u32 {
+    gbm::bo_get_format(self.raw)
+  }
+}
+
+impl Drop for GbmBo<'_> {
+  fn drop(&mut self) {
+    gbm::bo_destroy(self.raw);
+  }
+}
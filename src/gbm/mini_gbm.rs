@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use std::fs::File;
+use std::os::raw::{c_int, c_void};
 // use std::os::raw::c_uint;
 use std::os::unix::io::{AsRawFd, RawFd};
 
@@ -49,6 +50,17 @@ pub mod gbm_bo_flags {
 
 pub use gbm_formats::*;
 
+pub mod gbm_bo_transfer_flags {
+  pub type Type = u32;
+  pub const GBM_BO_TRANSFER_READ: Type = 1;
+  pub const GBM_BO_TRANSFER_WRITE: Type = 2;
+  pub const GBM_BO_TRANSFER_READ_WRITE: Type = 3;
+}
+
+// No explicit modifier, the layout is whatever the driver picks implicitly
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ffffffffffffff;
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+
 // #[repr(C, align(4))]
 #[repr(C)]
 pub union gbm_bo_handle {
@@ -62,7 +74,8 @@ pub union gbm_bo_handle {
 pub mod ffi {
   use super::*;
 
-  extern "C" {
+  dynamic_ffi! {
+    gbm;
     pub fn gbm_create_device(fd: RawFd) -> *mut RawDevice;
     pub fn gbm_device_destroy(gbm: *mut RawDevice);
 
@@ -70,6 +83,36 @@ pub mod ffi {
 
     pub fn gbm_bo_get_handle(bo: *mut RawBO) -> gbm_bo_handle;
 
+    pub fn gbm_bo_create(
+      gbm: *mut RawDevice,
+      width: u32,
+      height: u32,
+      format: u32,
+      flags: u32,
+    ) -> *mut RawBO;
+    pub fn gbm_bo_destroy(bo: *mut RawBO);
+
+    pub fn gbm_bo_get_width(bo: *mut RawBO) -> u32;
+    pub fn gbm_bo_get_height(bo: *mut RawBO) -> u32;
+    pub fn gbm_bo_get_format(bo: *mut RawBO) -> u32;
+    pub fn gbm_bo_get_modifier(bo: *mut RawBO) -> u64;
+    pub fn gbm_bo_get_plane_count(bo: *mut RawBO) -> c_int;
+    pub fn gbm_bo_get_stride_for_plane(bo: *mut RawBO, plane: c_int) -> u32;
+    pub fn gbm_bo_get_offset(bo: *mut RawBO, plane: c_int) -> u32;
+    pub fn gbm_bo_get_fd(bo: *mut RawBO) -> c_int;
+    pub fn gbm_bo_get_fd_for_plane(bo: *mut RawBO, plane: c_int) -> c_int;
+    pub fn gbm_bo_map(
+      bo: *mut RawBO,
+      x: u32,
+      y: u32,
+      width: u32,
+      height: u32,
+      flags: u32,
+      stride: *mut u32,
+      map_data: *mut *mut c_void,
+    ) -> *mut c_void;
+    pub fn gbm_bo_unmap(bo: *mut RawBO, map_data: *mut c_void);
+
     pub fn gbm_surface_create(
       gbm: *mut RawDevice,
       width: u32,
@@ -105,6 +148,84 @@ pub fn bo_get_handle_u32(bo: *mut RawBO) -> u32 {
   return unsafe { ffi::gbm_bo_get_handle(bo).u32 };
 }
 
+pub fn bo_create(
+  gbm: *mut RawDevice,
+  width: u32,
+  height: u32,
+  format: u32,
+  flags: u32,
+) -> *mut RawBO {
+  return unsafe { ffi::gbm_bo_create(gbm, width, height, format, flags) };
+}
+
+pub fn bo_destroy(bo: *mut RawBO) {
+  unsafe { ffi::gbm_bo_destroy(bo) };
+}
+
+pub fn bo_get_width(bo: *mut RawBO) -> u32 {
+  return unsafe { ffi::gbm_bo_get_width(bo) };
+}
+
+pub fn bo_get_height(bo: *mut RawBO) -> u32 {
+  return unsafe { ffi::gbm_bo_get_height(bo) };
+}
+
+/// One of the `GBM_FORMAT_*` fourccs
+pub fn bo_get_format(bo: *mut RawBO) -> u32 {
+  return unsafe { ffi::gbm_bo_get_format(bo) };
+}
+
+/// `DRM_FORMAT_MOD_INVALID` when the layout was chosen implicitly
+pub fn bo_get_modifier(bo: *mut RawBO) -> u64 {
+  return unsafe { ffi::gbm_bo_get_modifier(bo) };
+}
+
+pub fn bo_get_plane_count(bo: *mut RawBO) -> u32 {
+  return unsafe { ffi::gbm_bo_get_plane_count(bo) as u32 };
+}
+
+pub fn bo_get_stride_for_plane(bo: *mut RawBO, plane: u32) -> u32 {
+  return unsafe { ffi::gbm_bo_get_stride_for_plane(bo, plane as c_int) };
+}
+
+pub fn bo_get_offset(bo: *mut RawBO, plane: u32) -> u32 {
+  return unsafe { ffi::gbm_bo_get_offset(bo, plane as c_int) };
+}
+
+/// New dmabuf fd for the whole buffer, -1 on failure. The caller owns it.
+pub fn bo_get_fd(bo: *mut RawBO) -> RawFd {
+  return unsafe { ffi::gbm_bo_get_fd(bo) };
+}
+
+/// New dmabuf fd for one plane, -1 on failure. The caller owns it.
+pub fn bo_get_fd_for_plane(bo: *mut RawBO, plane: u32) -> RawFd {
+  return unsafe { ffi::gbm_bo_get_fd_for_plane(bo, plane as c_int) };
+}
+
+/// Maps a region of the buffer for CPU access, `None` on failure. Returns the address of the
+/// region, its stride and the cookie `bo_unmap` wants back.
+pub fn bo_map(
+  bo: *mut RawBO,
+  x: u32,
+  y: u32,
+  width: u32,
+  height: u32,
+  flags: u32,
+) -> Option<(*mut u8, u32, *mut c_void)> {
+  let mut stride = 0u32;
+  let mut map_data = std::ptr::null_mut();
+  let address =
+    unsafe { ffi::gbm_bo_map(bo, x, y, width, height, flags, &mut stride, &mut map_data) };
+  if address.is_null() {
+    return None;
+  }
+  return Some((address as *mut u8, stride, map_data));
+}
+
+pub fn bo_unmap(bo: *mut RawBO, map_data: *mut c_void) {
+  unsafe { ffi::gbm_bo_unmap(bo, map_data) };
+}
+
 pub fn surface_create(
   gbm: *mut RawDevice,
   width: u32,
@@ -0,0 +1,285 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use super::input_event::{EventDecoder, InputEvent};
+use super::mini_evdev as evdev;
+
+const INPUT_DIR: &str = "/dev/input";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeviceCapabilities {
+  pub keyboard: bool,
+  pub pointer: bool,
+  pub touchscreen: bool,
+}
+
+impl DeviceCapabilities {
+  fn probe(device: &File) -> io::Result<DeviceCapabilities> {
+    let types = evdev::get_bits(device, 0, evdev::EV_MAX)?;
+    let keys = evdev::get_bits(device, evdev::EV_KEY, evdev::KEY_MAX)?;
+    let rel = evdev::get_bits(device, evdev::EV_REL, evdev::REL_MAX)?;
+    let abs = evdev::get_bits(device, evdev::EV_ABS, evdev::ABS_MAX)?;
+    let props = evdev::get_props(device)?;
+
+    let has_keys = types.has(evdev::EV_KEY);
+
+    // Same heuristics as udev's input_id builtin, simplified
+    let keyboard = has_keys
+      && (evdev::KEY_ESC..=evdev::KEY_SPACE)
+        .filter(|key| keys.has(*key))
+        .count()
+        > 30;
+
+    let absolute = types.has(evdev::EV_ABS)
+      && ((abs.has(evdev::ABS_X) && abs.has(evdev::ABS_Y))
+        || (abs.has(evdev::ABS_MT_POSITION_X) && abs.has(evdev::ABS_MT_POSITION_Y)));
+    let touchscreen = absolute
+      && has_keys
+      && keys.has(evdev::BTN_TOUCH)
+      && (props.has(evdev::INPUT_PROP_DIRECT) || !keys.has(evdev::BTN_TOOL_FINGER));
+
+    let mouse = types.has(evdev::EV_REL)
+      && rel.has(evdev::REL_X)
+      && rel.has(evdev::REL_Y)
+      && keys.has(evdev::BTN_LEFT);
+    let touchpad = absolute && keys.has(evdev::BTN_TOOL_FINGER) && !touchscreen;
+
+    Ok(DeviceCapabilities {
+      keyboard,
+      pointer: mouse || touchpad,
+      touchscreen,
+    })
+  }
+
+  pub fn is_empty(&self) -> bool {
+    !(self.keyboard || self.pointer || self.touchscreen)
+  }
+}
+
+pub struct InputDevice {
+  path: PathBuf,
+  name: String,
+  capabilities: DeviceCapabilities,
+  file: File,
+  decoder: EventDecoder,
+}
+
+impl InputDevice {
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn capabilities(&self) -> DeviceCapabilities {
+    self.capabilities
+  }
+
+  pub fn file(&self) -> &File {
+    &self.file
+  }
+
+  /// Range and resolution of an `ABS_*` axis.
+  pub fn abs_info(&self, axis: u16) -> io::Result<evdev::RawAbsInfo> {
+    evdev::get_abs_info(&self.file, axis)
+  }
+
+  /// Exclusive access, so key presses don't also end up on the console.
+  pub fn grab(&self, grab: bool) -> io::Result<()> {
+    evdev::grab(&self.file, grab)
+  }
+}
+
+/// Every keyboard, pointer and touchscreen found under `/dev/input`, behind one epoll fd that
+/// becomes readable whenever any of them has events.
+pub struct Input {
+  epoll: File,
+  devices: Vec<InputDevice>,
+}
+
+fn event_nodes() -> io::Result<Vec<PathBuf>> {
+  let mut nodes: Vec<(u32, PathBuf)> = Vec::new();
+  for entry in std::fs::read_dir(INPUT_DIR)? {
+    let entry = entry?;
+    let name = entry.file_name();
+    let number = name
+      .to_str()
+      .and_then(|name| name.strip_prefix("event"))
+      .and_then(|number| number.parse().ok());
+    if let Some(number) = number {
+      nodes.push((number, entry.path()));
+    }
+  }
+  nodes.sort();
+
+  Ok(nodes.into_iter().map(|(_, path)| path).collect())
+}
+
+fn unwatch(epoll: &File, device: &File) {
+  unsafe {
+    libc::epoll_ctl(
+      epoll.as_raw_fd(),
+      libc::EPOLL_CTL_DEL,
+      device.as_raw_fd(),
+      std::ptr::null_mut(),
+    );
+  }
+}
+
+impl Input {
+  /// Opens the devices directly, needs root or membership of the `input` group.
+  pub fn enumerate() -> io::Result<Input> {
+    Input::enumerate_with(|path| {
+      std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+    })
+  }
+
+  /// Opens the devices through `open`, e.g. `|path| session.open_device(path)` to go through a
+  /// seat manager. Nodes that fail to open or aren't keyboards, pointers or touchscreens are
+  /// skipped.
+  pub fn enumerate_with<F>(mut open: F) -> io::Result<Input>
+  where
+    F: FnMut(&Path) -> io::Result<File>,
+  {
+    let epoll = unsafe {
+      let fd = libc::epoll_create1(libc::EPOLL_CLOEXEC);
+      if fd < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      File::from_raw_fd(fd)
+    };
+
+    let mut input = Input {
+      epoll,
+      devices: Vec::new(),
+    };
+
+    for path in event_nodes()? {
+      let file = match open(&path) {
+        Ok(file) => file,
+        Err(_) => continue,
+      };
+      let capabilities = match DeviceCapabilities::probe(&file) {
+        Ok(capabilities) if !capabilities.is_empty() => capabilities,
+        _ => continue,
+      };
+      let name = evdev::get_name(&file).unwrap_or_default();
+
+      input.add_device(path, name, capabilities, file)?;
+    }
+
+    Ok(input)
+  }
+
+  fn add_device(
+    &mut self,
+    path: PathBuf,
+    name: String,
+    capabilities: DeviceCapabilities,
+    file: File,
+  ) -> io::Result<()> {
+    evdev::set_nonblocking(file.as_raw_fd())?;
+
+    let index = self.devices.len();
+    let mut event: libc::epoll_event = unsafe { mem::zeroed() };
+    event.events = libc::EPOLLIN as u32;
+    event.u64 = index as u64;
+    if unsafe {
+      libc::epoll_ctl(
+        self.epoll.as_raw_fd(),
+        libc::EPOLL_CTL_ADD,
+        file.as_raw_fd(),
+        &mut event,
+      )
+    } < 0
+    {
+      return Err(io::Error::last_os_error());
+    }
+
+    let mut decoder = EventDecoder::new(index);
+    if let Ok(slots) = evdev::get_abs_info(&file, evdev::ABS_MT_SLOT) {
+      decoder = decoder.with_slot_count(slots.maximum.max(0) as usize + 1);
+    }
+
+    self.devices.push(InputDevice {
+      path,
+      name,
+      capabilities,
+      file,
+      decoder,
+    });
+
+    Ok(())
+  }
+
+  pub fn devices(&self) -> &[InputDevice] {
+    &self.devices
+  }
+
+  /// Reads and decodes whatever is pending, never blocks.
+  pub fn dispatch(&mut self) -> io::Result<Vec<InputEvent>> {
+    let mut ready: [libc::epoll_event; 16] = unsafe { mem::zeroed() };
+    let mut events = Vec::new();
+
+    loop {
+      let count = unsafe {
+        libc::epoll_wait(
+          self.epoll.as_raw_fd(),
+          ready.as_mut_ptr(),
+          ready.len() as i32,
+          0,
+        )
+      };
+      if count < 0 {
+        let error = io::Error::last_os_error();
+        if error.kind() == io::ErrorKind::Interrupted {
+          continue;
+        }
+        return Err(error);
+      }
+
+      for ready_device in ready[..count as usize].iter() {
+        let device = &mut self.devices[ready_device.u64 as usize];
+        if ready_device.events & (libc::EPOLLHUP | libc::EPOLLERR) as u32 != 0 {
+          // Unplugged, or revoked by the seat manager
+          unwatch(&self.epoll, &device.file);
+          continue;
+        }
+
+        match evdev::read_events(&mut device.file) {
+          Ok(raw_events) => {
+            for raw_event in raw_events.iter() {
+              events.extend(device.decoder.feed(raw_event));
+            }
+          }
+          Err(ref error) if error.raw_os_error() == Some(libc::ENODEV) => {
+            unwatch(&self.epoll, &device.file)
+          }
+          Err(error) => return Err(error),
+        }
+      }
+
+      if (count as usize) < ready.len() {
+        break;
+      }
+    }
+
+    Ok(events)
+  }
+}
+
+impl AsRawFd for Input {
+  fn as_raw_fd(&self) -> RawFd {
+    self.epoll.as_raw_fd()
+  }
+}
@@ -0,0 +1,568 @@
+#![allow(dead_code)]
+
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use super::mini_evdev::{self as evdev, RawInputEvent};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyState {
+  Released,
+  Pressed,
+  Repeated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputEventKind {
+  /// `key` is one of the kernel's `KEY_*` codes
+  Key {
+    key: u16,
+    state: KeyState,
+  },
+  PointerMotion {
+    dx: i32,
+    dy: i32,
+  },
+  /// `button` is one of the kernel's `BTN_*` codes
+  PointerButton {
+    button: u16,
+    pressed: bool,
+  },
+  /// Wheel clicks
+  PointerAxis {
+    vertical: i32,
+    horizontal: i32,
+  },
+  /// Raw device coordinates, see `TouchMapping` to get output pixels
+  TouchDown {
+    slot: i32,
+    id: i32,
+    x: i32,
+    y: i32,
+  },
+  TouchMotion {
+    slot: i32,
+    x: i32,
+    y: i32,
+  },
+  TouchUp {
+    slot: i32,
+  },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+  /// Index of the device in `Input::devices()`
+  pub device: usize,
+  /// Kernel timestamp, `CLOCK_REALTIME` unless the device was switched to another clock
+  pub time: Duration,
+  pub kind: InputEventKind,
+}
+
+// More than any panel tracks, `ABS_MT_SLOT` values come straight from the device
+const MAX_SLOTS: usize = 64;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Slot {
+  tracking_id: i32,
+  x: i32,
+  y: i32,
+  down: bool,
+  up: bool,
+  moved: bool,
+}
+
+/// Turns the raw event stream of one device into typed events, one `SYN_REPORT` frame at a
+/// time. Multitouch follows protocol B (slots), single touch devices are reported as slot 0.
+pub struct EventDecoder {
+  device: usize,
+
+  dx: i32,
+  dy: i32,
+  wheel: i32,
+  hwheel: i32,
+
+  slots: Vec<Slot>,
+  // `None` while the device talks about a slot past `slot_count`, those events are dropped
+  slot: Option<usize>,
+  slot_count: usize,
+  multitouch: bool,
+
+  pending: Vec<InputEventKind>,
+  dropped: bool,
+}
+
+impl EventDecoder {
+  pub fn new(device: usize) -> EventDecoder {
+    EventDecoder {
+      device,
+      dx: 0,
+      dy: 0,
+      wheel: 0,
+      hwheel: 0,
+      slots: vec![Slot {
+        tracking_id: -1,
+        ..Default::default()
+      }],
+      slot: Some(0),
+      slot_count: MAX_SLOTS,
+      multitouch: false,
+      pending: Vec::new(),
+      dropped: false,
+    }
+  }
+
+  /// Slots the device has, from its `ABS_MT_SLOT` range. Capped at 64, events for slots past
+  /// the count are ignored.
+  pub fn with_slot_count(mut self, count: usize) -> EventDecoder {
+    self.slot_count = count.clamp(1, MAX_SLOTS);
+    self
+  }
+
+  fn current_slot(&mut self) -> Option<&mut Slot> {
+    let index = self.slot?;
+    if index >= self.slots.len() {
+      self.slots.resize(
+        index + 1,
+        Slot {
+          tracking_id: -1,
+          ..Default::default()
+        },
+      );
+    }
+    Some(&mut self.slots[index])
+  }
+
+  /// Feeds one raw event, returns whatever got completed by it (only on `SYN_REPORT`).
+  pub fn feed(&mut self, event: &RawInputEvent) -> Vec<InputEvent> {
+    if self.dropped {
+      // Kernel buffer overrun, everything up to the next report is garbage
+      if event.r#type == evdev::EV_SYN && event.code == evdev::SYN_REPORT {
+        self.dropped = false;
+      }
+      return Vec::new();
+    }
+
+    match event.r#type {
+      evdev::EV_SYN => match event.code {
+        evdev::SYN_REPORT => return self.flush(event.time()),
+        evdev::SYN_DROPPED => self.drop_frame(),
+        _ => {}
+      },
+      evdev::EV_KEY => self.key(event.code, event.value),
+      evdev::EV_REL => match event.code {
+        evdev::REL_X => self.dx += event.value,
+        evdev::REL_Y => self.dy += event.value,
+        evdev::REL_WHEEL => self.wheel += event.value,
+        evdev::REL_HWHEEL => self.hwheel += event.value,
+        _ => {}
+      },
+      evdev::EV_ABS => self.abs(event.code, event.value),
+      _ => {}
+    }
+
+    Vec::new()
+  }
+
+  fn key(&mut self, code: u16, value: i32) {
+    if code == evdev::BTN_TOUCH {
+      if !self.multitouch {
+        let slot = &mut self.slots[0];
+        if value != 0 {
+          slot.down = true;
+        } else {
+          slot.up = true;
+        }
+      }
+      return;
+    }
+
+    if (evdev::BTN_DIGI..evdev::BTN_WHEEL).contains(&code) {
+      // BTN_TOOL_*, touch state is tracked through slots
+      return;
+    }
+
+    if (evdev::BTN_MISC..evdev::KEY_OK).contains(&code) {
+      self.pending.push(InputEventKind::PointerButton {
+        button: code,
+        pressed: value != 0,
+      });
+    } else {
+      self.pending.push(InputEventKind::Key {
+        key: code,
+        state: match value {
+          0 => KeyState::Released,
+          2 => KeyState::Repeated,
+          _ => KeyState::Pressed,
+        },
+      });
+    }
+  }
+
+  fn abs(&mut self, code: u16, value: i32) {
+    match code {
+      evdev::ABS_MT_SLOT => {
+        self.multitouch = true;
+        let slot_count = self.slot_count;
+        self.slot = usize::try_from(value)
+          .ok()
+          .filter(|slot| *slot < slot_count);
+      }
+      evdev::ABS_MT_TRACKING_ID => {
+        self.multitouch = true;
+        if let Some(slot) = self.current_slot() {
+          if value < 0 {
+            slot.up = true;
+          } else {
+            slot.tracking_id = value;
+            slot.down = true;
+          }
+        }
+      }
+      evdev::ABS_MT_POSITION_X => {
+        if let Some(slot) = self.current_slot() {
+          slot.x = value;
+          slot.moved = true;
+        }
+      }
+      evdev::ABS_MT_POSITION_Y => {
+        if let Some(slot) = self.current_slot() {
+          slot.y = value;
+          slot.moved = true;
+        }
+      }
+      evdev::ABS_X | evdev::ABS_Y if !self.multitouch => {
+        let slot = &mut self.slots[0];
+        if code == evdev::ABS_X {
+          slot.x = value;
+        } else {
+          slot.y = value;
+        }
+        slot.moved = true;
+      }
+      _ => {}
+    }
+  }
+
+  fn drop_frame(&mut self) {
+    self.dx = 0;
+    self.dy = 0;
+    self.wheel = 0;
+    self.hwheel = 0;
+    self.pending.clear();
+    for slot in self.slots.iter_mut() {
+      slot.moved = false;
+      slot.down = false;
+      slot.up = false;
+    }
+    self.dropped = true;
+  }
+
+  fn flush(&mut self, time: Duration) -> Vec<InputEvent> {
+    let mut kinds: Vec<InputEventKind> = self.pending.drain(..).collect();
+
+    if self.dx != 0 || self.dy != 0 {
+      kinds.push(InputEventKind::PointerMotion {
+        dx: self.dx,
+        dy: self.dy,
+      });
+    }
+    if self.wheel != 0 || self.hwheel != 0 {
+      kinds.push(InputEventKind::PointerAxis {
+        vertical: self.wheel,
+        horizontal: self.hwheel,
+      });
+    }
+    self.dx = 0;
+    self.dy = 0;
+    self.wheel = 0;
+    self.hwheel = 0;
+
+    for (i, slot) in self.slots.iter_mut().enumerate() {
+      let index = i as i32;
+      if slot.down && slot.up {
+        // Lifted and put down again within the same frame
+        kinds.push(InputEventKind::TouchUp { slot: index });
+        slot.up = false;
+      }
+      if slot.down {
+        if !self.multitouch {
+          slot.tracking_id = 0;
+        }
+        kinds.push(InputEventKind::TouchDown {
+          slot: index,
+          id: slot.tracking_id,
+          x: slot.x,
+          y: slot.y,
+        });
+      } else if slot.up {
+        kinds.push(InputEventKind::TouchUp { slot: index });
+        slot.tracking_id = -1;
+      } else if slot.moved && slot.tracking_id >= 0 {
+        kinds.push(InputEventKind::TouchMotion {
+          slot: index,
+          x: slot.x,
+          y: slot.y,
+        });
+      }
+      slot.down = false;
+      slot.up = false;
+      slot.moved = false;
+    }
+
+    let device = self.device;
+    kinds
+      .into_iter()
+      .map(|kind| InputEvent { device, time, kind })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A recorded stream: (type, code, value), a report ends each frame
+  fn replay(events: &[(u16, u16, i32)]) -> Vec<Vec<InputEventKind>> {
+    replay_with(&mut EventDecoder::new(3), events)
+  }
+
+  fn replay_with(
+    decoder: &mut EventDecoder,
+    events: &[(u16, u16, i32)],
+  ) -> Vec<Vec<InputEventKind>> {
+    let mut frames = Vec::new();
+    for (i, &(r#type, code, value)) in events.iter().enumerate() {
+      let event = RawInputEvent {
+        sec: 1,
+        usec: i as _,
+        r#type,
+        code,
+        value,
+      };
+      let decoded = decoder.feed(&event);
+      if event.r#type == evdev::EV_SYN && event.code == evdev::SYN_REPORT {
+        assert!(decoded.iter().all(|decoded| decoded.device == 3));
+        assert!(decoded.iter().all(|decoded| decoded.time == event.time()));
+        frames.push(decoded.into_iter().map(|decoded| decoded.kind).collect());
+      } else {
+        assert!(decoded.is_empty());
+      }
+    }
+    frames
+  }
+
+  const REPORT: (u16, u16, i32) = (evdev::EV_SYN, evdev::SYN_REPORT, 0);
+
+  #[test]
+  fn decodes_keys() {
+    let frames = replay(&[
+      (evdev::EV_MSC, 4, 0x70004),
+      (evdev::EV_KEY, evdev::KEY_A, 1),
+      REPORT,
+      (evdev::EV_KEY, evdev::KEY_A, 2),
+      REPORT,
+      (evdev::EV_KEY, evdev::KEY_A, 0),
+      REPORT,
+    ]);
+    let key = |state| InputEventKind::Key {
+      key: evdev::KEY_A,
+      state,
+    };
+    assert_eq!(
+      frames,
+      [
+        [key(KeyState::Pressed)],
+        [key(KeyState::Repeated)],
+        [key(KeyState::Released)],
+      ]
+    );
+  }
+
+  #[test]
+  fn sums_pointer_motion_per_frame() {
+    let frames = replay(&[
+      (evdev::EV_REL, evdev::REL_X, 3),
+      (evdev::EV_REL, evdev::REL_Y, -1),
+      (evdev::EV_REL, evdev::REL_X, 2),
+      (evdev::EV_REL, evdev::REL_WHEEL, -1),
+      (evdev::EV_KEY, evdev::BTN_LEFT, 1),
+      REPORT,
+      REPORT,
+    ]);
+    assert_eq!(
+      frames,
+      [
+        vec![
+          InputEventKind::PointerButton {
+            button: evdev::BTN_LEFT,
+            pressed: true,
+          },
+          InputEventKind::PointerMotion { dx: 5, dy: -1 },
+          InputEventKind::PointerAxis {
+            vertical: -1,
+            horizontal: 0,
+          },
+        ],
+        vec![],
+      ]
+    );
+  }
+
+  #[test]
+  fn tracks_multitouch_slots() {
+    let frames = replay(&[
+      (evdev::EV_ABS, evdev::ABS_MT_SLOT, 0),
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, 10),
+      (evdev::EV_ABS, evdev::ABS_MT_POSITION_X, 100),
+      (evdev::EV_ABS, evdev::ABS_MT_POSITION_Y, 200),
+      (evdev::EV_KEY, evdev::BTN_TOUCH, 1),
+      (evdev::EV_KEY, evdev::BTN_TOOL_FINGER, 1),
+      REPORT,
+      (evdev::EV_ABS, evdev::ABS_MT_SLOT, 1),
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, 11),
+      (evdev::EV_ABS, evdev::ABS_MT_POSITION_X, 300),
+      (evdev::EV_ABS, evdev::ABS_MT_POSITION_Y, 400),
+      (evdev::EV_ABS, evdev::ABS_MT_SLOT, 0),
+      (evdev::EV_ABS, evdev::ABS_MT_POSITION_X, 110),
+      REPORT,
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, -1),
+      (evdev::EV_ABS, evdev::ABS_MT_SLOT, 1),
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, -1),
+      (evdev::EV_KEY, evdev::BTN_TOUCH, 0),
+      REPORT,
+    ]);
+    assert_eq!(
+      frames,
+      [
+        vec![InputEventKind::TouchDown {
+          slot: 0,
+          id: 10,
+          x: 100,
+          y: 200,
+        }],
+        vec![
+          InputEventKind::TouchMotion {
+            slot: 0,
+            x: 110,
+            y: 200,
+          },
+          InputEventKind::TouchDown {
+            slot: 1,
+            id: 11,
+            x: 300,
+            y: 400,
+          },
+        ],
+        vec![
+          InputEventKind::TouchUp { slot: 0 },
+          InputEventKind::TouchUp { slot: 1 },
+        ],
+      ]
+    );
+  }
+
+  #[test]
+  fn ignores_slots_out_of_range() {
+    // A touch in the last slot, then in ones the device can't have
+    let touch = |slot: i32, id: i32| {
+      [
+        (evdev::EV_ABS, evdev::ABS_MT_SLOT, slot),
+        (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, id),
+        (evdev::EV_ABS, evdev::ABS_MT_POSITION_X, 1),
+        (evdev::EV_ABS, evdev::ABS_MT_POSITION_Y, 2),
+        REPORT,
+      ]
+    };
+    let events: Vec<_> = [touch(1, 7), touch(2, 8), touch(i32::MAX, 9), touch(-1, 10)].concat();
+
+    let mut decoder = EventDecoder::new(3).with_slot_count(2);
+    let frames = replay_with(&mut decoder, &events);
+    let down = InputEventKind::TouchDown {
+      slot: 1,
+      id: 7,
+      x: 1,
+      y: 2,
+    };
+    assert_eq!(frames, [vec![down], vec![], vec![], vec![]]);
+    assert_eq!(decoder.slots.len(), 2);
+
+    // Without a count from the device, at most 64
+    let mut decoder = EventDecoder::new(3);
+    let frames = replay_with(&mut decoder, &[touch(63, 1), touch(64, 2)].concat());
+    assert_eq!(frames[1], []);
+    assert_eq!(decoder.slots.len(), 64);
+  }
+
+  #[test]
+  fn reports_single_touch_as_slot_0() {
+    let frames = replay(&[
+      (evdev::EV_ABS, evdev::ABS_X, 5),
+      (evdev::EV_ABS, evdev::ABS_Y, 6),
+      (evdev::EV_KEY, evdev::BTN_TOUCH, 1),
+      REPORT,
+      (evdev::EV_ABS, evdev::ABS_X, 7),
+      REPORT,
+      (evdev::EV_KEY, evdev::BTN_TOUCH, 0),
+      REPORT,
+    ]);
+    assert_eq!(
+      frames,
+      [
+        [InputEventKind::TouchDown {
+          slot: 0,
+          id: 0,
+          x: 5,
+          y: 6,
+        }],
+        [InputEventKind::TouchMotion {
+          slot: 0,
+          x: 7,
+          y: 6
+        }],
+        [InputEventKind::TouchUp { slot: 0 }],
+      ]
+    );
+  }
+
+  #[test]
+  fn lifting_and_touching_in_one_frame_ends_the_first_touch() {
+    let frames = replay(&[
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, 1),
+      REPORT,
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, -1),
+      (evdev::EV_ABS, evdev::ABS_MT_TRACKING_ID, 2),
+      (evdev::EV_ABS, evdev::ABS_MT_POSITION_X, 9),
+      REPORT,
+    ]);
+    assert_eq!(
+      frames[1],
+      [
+        InputEventKind::TouchUp { slot: 0 },
+        InputEventKind::TouchDown {
+          slot: 0,
+          id: 2,
+          x: 9,
+          y: 0,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn discards_frames_after_a_drop() {
+    let frames = replay(&[
+      (evdev::EV_REL, evdev::REL_X, 1),
+      (evdev::EV_SYN, evdev::SYN_DROPPED, 0),
+      (evdev::EV_REL, evdev::REL_X, 2),
+      (evdev::EV_KEY, evdev::KEY_Q, 1),
+      REPORT,
+      (evdev::EV_REL, evdev::REL_X, 4),
+      REPORT,
+    ]);
+    assert_eq!(
+      frames,
+      [vec![], vec![InputEventKind::PointerMotion { dx: 4, dy: 0 }]]
+    );
+  }
+}
@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+
+// Kernel evdev interface, from <linux/input.h> and <linux/input-event-codes.h>
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::raw::{c_int, c_ulong};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::slice;
+use std::time::Duration;
+
+use crate::ioctl::{ioc, ior, iow};
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawInputEvent {
+  pub sec: c_ulong,
+  pub usec: c_ulong,
+  pub r#type: u16,
+  pub code: u16,
+  pub value: i32,
+}
+
+impl RawInputEvent {
+  pub fn time(&self) -> Duration {
+    Duration::new(self.sec as _, (self.usec as u32) * 1000)
+  }
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawAbsInfo {
+  pub value: i32,
+  pub minimum: i32,
+  pub maximum: i32,
+  pub fuzz: i32,
+  pub flat: i32,
+  pub resolution: i32,
+}
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_MSC: u16 = 0x04;
+pub const EV_MAX: u16 = 0x1f;
+
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+pub const REL_MAX: u16 = 0x0f;
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+pub const ABS_MAX: u16 = 0x3f;
+
+pub const KEY_ESC: u16 = 1;
+pub const KEY_Q: u16 = 16;
+pub const KEY_A: u16 = 30;
+pub const KEY_Z: u16 = 44;
+pub const KEY_SPACE: u16 = 57;
+pub const BTN_MISC: u16 = 0x100;
+pub const BTN_MOUSE: u16 = 0x110;
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_JOYSTICK: u16 = 0x120;
+pub const BTN_TOOL_PEN: u16 = 0x140;
+pub const BTN_TOOL_FINGER: u16 = 0x145;
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const BTN_TOOL_DOUBLETAP: u16 = 0x14d;
+pub const BTN_TOOL_QUINTTAP: u16 = 0x148;
+pub const BTN_DIGI: u16 = 0x140;
+pub const BTN_WHEEL: u16 = 0x150;
+pub const KEY_OK: u16 = 0x160;
+pub const KEY_MAX: u16 = 0x2ff;
+
+pub const INPUT_PROP_POINTER: u16 = 0x00;
+pub const INPUT_PROP_DIRECT: u16 = 0x01;
+pub const INPUT_PROP_MAX: u16 = 0x1f;
+
+const EVIOC_TYPE: u8 = b'E';
+
+pub const fn eviocgname(len: usize) -> c_ulong {
+  ioc(2, EVIOC_TYPE, 0x06, len)
+}
+
+pub const fn eviocgprop(len: usize) -> c_ulong {
+  ioc(2, EVIOC_TYPE, 0x09, len)
+}
+
+pub const fn eviocgbit(ev: u16, len: usize) -> c_ulong {
+  ioc(2, EVIOC_TYPE, 0x20 + ev as u8, len)
+}
+
+pub const fn eviocgabs(abs: u16) -> c_ulong {
+  ior(EVIOC_TYPE, 0x40 + abs as u8, size_of::<RawAbsInfo>())
+}
+
+pub const EVIOCGRAB: c_ulong = iow(EVIOC_TYPE, 0x90, size_of::<c_int>());
+
+/// Bitmask as returned by `EVIOCGBIT`/`EVIOCGPROP`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bits(Vec<u8>);
+
+impl Bits {
+  pub fn has(&self, bit: u16) -> bool {
+    let byte = (bit / 8) as usize;
+    byte < self.0.len() && (self.0[byte] & (1 << (bit % 8))) != 0
+  }
+}
+
+fn check(ret: c_int) -> io::Result<c_int> {
+  if ret < 0 {
+    Err(io::Error::last_os_error())
+  } else {
+    Ok(ret)
+  }
+}
+
+pub fn get_name(device: &File) -> io::Result<String> {
+  let mut name = [0u8; 256];
+  let len = check(unsafe {
+    libc::ioctl(
+      device.as_raw_fd(),
+      eviocgname(name.len()) as _,
+      name.as_mut_ptr(),
+    )
+  })? as usize;
+
+  let end = name[..len].iter().position(|c| *c == 0).unwrap_or(len);
+  Ok(String::from_utf8_lossy(&name[..end]).into_owned())
+}
+
+/// `ev` is 0 for the supported event types, or one of `EV_*` for the codes of that type.
+pub fn get_bits(device: &File, ev: u16, max: u16) -> io::Result<Bits> {
+  let mut bits = vec![0u8; max as usize / 8 + 1];
+  check(unsafe {
+    libc::ioctl(
+      device.as_raw_fd(),
+      eviocgbit(ev, bits.len()) as _,
+      bits.as_mut_ptr(),
+    )
+  })?;
+
+  Ok(Bits(bits))
+}
+
+pub fn get_props(device: &File) -> io::Result<Bits> {
+  let mut bits = vec![0u8; INPUT_PROP_MAX as usize / 8 + 1];
+  check(unsafe {
+    libc::ioctl(
+      device.as_raw_fd(),
+      eviocgprop(bits.len()) as _,
+      bits.as_mut_ptr(),
+    )
+  })?;
+
+  Ok(Bits(bits))
+}
+
+pub fn get_abs_info(device: &File, axis: u16) -> io::Result<RawAbsInfo> {
+  let mut info = RawAbsInfo::default();
+  check(unsafe { libc::ioctl(device.as_raw_fd(), eviocgabs(axis) as _, &mut info) })?;
+
+  Ok(info)
+}
+
+/// Exclusive access, events stop reaching the console and everyone else.
+pub fn grab(device: &File, grab: bool) -> io::Result<()> {
+  check(unsafe { libc::ioctl(device.as_raw_fd(), EVIOCGRAB as _, grab as c_int) })?;
+  Ok(())
+}
+
+pub fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+  unsafe {
+    let flags = check(libc::fcntl(fd, libc::F_GETFL))?;
+    check(libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK))?;
+  }
+  Ok(())
+}
+
+/// Reads whole events until the source runs dry (`WouldBlock` on a non-blocking device, EOF on
+/// a recording). Recordings are just the raw bytes read from a device node.
+pub fn read_events<R: Read>(source: &mut R) -> io::Result<Vec<RawInputEvent>> {
+  let mut events = Vec::new();
+  let mut buffer = [RawInputEvent::default(); 64];
+
+  loop {
+    let bytes = unsafe {
+      slice::from_raw_parts_mut(
+        buffer.as_mut_ptr() as *mut u8,
+        buffer.len() * size_of::<RawInputEvent>(),
+      )
+    };
+
+    let len = match source.read(bytes) {
+      Ok(0) => break,
+      Ok(len) => len,
+      Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => break,
+      Err(ref error) if error.kind() == io::ErrorKind::Interrupted => continue,
+      Err(error) => return Err(error),
+    };
+
+    // evdev never hands out partial events
+    events.extend_from_slice(&buffer[..len / size_of::<RawInputEvent>()]);
+  }
+
+  Ok(events)
+}
@@ -0,0 +1,287 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use super::input_device::InputDevice;
+use super::mini_evdev as evdev;
+use crate::Context;
+
+/// Clockwise rotation of the picture with respect to the panel's native orientation.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rotation {
+  #[default]
+  Normal,
+  Rotate90,
+  Rotate180,
+  Rotate270,
+}
+
+impl Rotation {
+  // Whether the picture's width runs along the panel's height
+  fn is_sideways(self) -> bool {
+    matches!(self, Rotation::Rotate90 | Rotation::Rotate270)
+  }
+}
+
+/// First two rows of a 3×3 matrix applied to normalized (0..1) touch coordinates, same
+/// convention as libinput's `LIBINPUT_CALIBRATION_MATRIX`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationMatrix(pub [f32; 6]);
+
+impl Default for CalibrationMatrix {
+  fn default() -> Self {
+    CalibrationMatrix([1.0, 0.0, 0.0, 0.0, 1.0, 0.0])
+  }
+}
+
+impl CalibrationMatrix {
+  /// Reads the matrix from a file holding either the six values or a
+  /// `LIBINPUT_CALIBRATION_MATRIX=...` line (as found in udev rules/hwdb entries).
+  pub fn load<P: AsRef<Path>>(path: P) -> io::Result<CalibrationMatrix> {
+    let config = fs::read_to_string(path)?;
+
+    config
+      .lines()
+      .map(|line| line.split('#').next().unwrap_or("").trim())
+      .find(|line| !line.is_empty())
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Empty calibration file"))?
+      .parse()
+  }
+
+  fn apply(&self, x: f32, y: f32) -> (f32, f32) {
+    let m = &self.0;
+    (m[0] * x + m[1] * y + m[2], m[3] * x + m[4] * y + m[5])
+  }
+}
+
+impl FromStr for CalibrationMatrix {
+  type Err = io::Error;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let values = match s.find('=') {
+      Some(idx) => &s[idx + 1..],
+      None => s,
+    };
+    let values = values.trim().trim_matches('"');
+
+    let mut matrix = [0f32; 6];
+    let mut count = 0;
+    for value in values.split_whitespace() {
+      if count == matrix.len() {
+        count += 1;
+        break;
+      }
+      matrix[count] = value
+        .parse()
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+      count += 1;
+    }
+    if count != matrix.len() {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "A calibration matrix needs exactly 6 values",
+      ));
+    }
+
+    Ok(CalibrationMatrix(matrix))
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AxisRange {
+  pub minimum: i32,
+  pub maximum: i32,
+}
+
+impl AxisRange {
+  fn normalize(&self, value: i32) -> f32 {
+    let span = (self.maximum - self.minimum).max(1) as f32;
+    (value - self.minimum) as f32 / span
+  }
+}
+
+/// Maps raw touch coordinates (`TouchDown`/`TouchMotion` events) to output pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TouchMapping {
+  pub x: AxisRange,
+  pub y: AxisRange,
+  /// Size of the picture as drawn, after rotation
+  pub width: u32,
+  pub height: u32,
+  pub rotation: Rotation,
+  pub calibration: CalibrationMatrix,
+}
+
+impl TouchMapping {
+  /// Uses the multitouch axes when available, `ABS_X`/`ABS_Y` otherwise. `width` and `height`
+  /// are the size of the output (the mode), `with_rotation` turns them into the size of the
+  /// picture as drawn.
+  pub fn for_device(device: &InputDevice, width: u32, height: u32) -> io::Result<TouchMapping> {
+    let (x, y) = match (
+      device.abs_info(evdev::ABS_MT_POSITION_X),
+      device.abs_info(evdev::ABS_MT_POSITION_Y),
+    ) {
+      (Ok(x), Ok(y)) if x.maximum > x.minimum && y.maximum > y.minimum => (x, y),
+      _ => (
+        device.abs_info(evdev::ABS_X)?,
+        device.abs_info(evdev::ABS_Y)?,
+      ),
+    };
+
+    let x = AxisRange {
+      minimum: x.minimum,
+      maximum: x.maximum,
+    };
+    let y = AxisRange {
+      minimum: y.minimum,
+      maximum: y.maximum,
+    };
+    Ok(TouchMapping::for_axes(x, y, width, height))
+  }
+
+  fn for_axes(x: AxisRange, y: AxisRange, width: u32, height: u32) -> TouchMapping {
+    TouchMapping {
+      x,
+      y,
+      width,
+      height,
+      rotation: Rotation::Normal,
+      calibration: CalibrationMatrix::default(),
+    }
+  }
+
+  /// For the context's mode, landscape until `with_rotation` says otherwise.
+  pub fn for_context(context: &Context, device: &InputDevice) -> io::Result<TouchMapping> {
+    TouchMapping::for_device(device, context.width(), context.height())
+  }
+
+  /// Swaps `width` and `height` when going between landscape and portrait.
+  pub fn with_rotation(mut self, rotation: Rotation) -> TouchMapping {
+    if self.rotation.is_sideways() != rotation.is_sideways() {
+      std::mem::swap(&mut self.width, &mut self.height);
+    }
+    self.rotation = rotation;
+    self
+  }
+
+  pub fn with_calibration(mut self, calibration: CalibrationMatrix) -> TouchMapping {
+    self.calibration = calibration;
+    self
+  }
+
+  /// Output pixel coordinates for a raw touch position. Not clamped, calibrated touches can land
+  /// slightly outside.
+  pub fn map(&self, x: i32, y: i32) -> (f32, f32) {
+    let (u, v) = self
+      .calibration
+      .apply(self.x.normalize(x), self.y.normalize(y));
+
+    let (u, v) = match self.rotation {
+      Rotation::Normal => (u, v),
+      // The panel's top right corner shows the picture's top left
+      Rotation::Rotate90 => (v, 1.0 - u),
+      Rotation::Rotate180 => (1.0 - u, 1.0 - v),
+      Rotation::Rotate270 => (1.0 - v, u),
+    };
+
+    (u * self.width as f32, v * self.height as f32)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A 100..=1100 square panel showing an 800x480 picture
+  fn mapping(rotation: Rotation) -> TouchMapping {
+    TouchMapping {
+      x: AxisRange {
+        minimum: 100,
+        maximum: 1100,
+      },
+      y: AxisRange {
+        minimum: 100,
+        maximum: 1100,
+      },
+      width: 800,
+      height: 480,
+      rotation,
+      calibration: CalibrationMatrix::default(),
+    }
+  }
+
+  // Where the panel's top left, top right, bottom right and bottom left corners land
+  fn corners(rotation: Rotation) -> [(f32, f32); 4] {
+    let mapping = mapping(rotation);
+    [
+      mapping.map(100, 100),
+      mapping.map(1100, 100),
+      mapping.map(1100, 1100),
+      mapping.map(100, 1100),
+    ]
+  }
+
+  #[test]
+  fn maps_without_rotation() {
+    assert_eq!(
+      corners(Rotation::Normal),
+      [(0.0, 0.0), (800.0, 0.0), (800.0, 480.0), (0.0, 480.0)]
+    );
+    assert_eq!(mapping(Rotation::Normal).map(600, 350), (400.0, 120.0));
+  }
+
+  #[test]
+  fn rotates_90_clockwise() {
+    assert_eq!(
+      corners(Rotation::Rotate90),
+      [(0.0, 480.0), (0.0, 0.0), (800.0, 0.0), (800.0, 480.0)]
+    );
+  }
+
+  #[test]
+  fn rotates_180() {
+    assert_eq!(
+      corners(Rotation::Rotate180),
+      [(800.0, 480.0), (0.0, 480.0), (0.0, 0.0), (800.0, 0.0)]
+    );
+  }
+
+  #[test]
+  fn rotates_270_clockwise() {
+    assert_eq!(
+      corners(Rotation::Rotate270),
+      [(800.0, 0.0), (800.0, 480.0), (0.0, 480.0), (0.0, 0.0)]
+    );
+  }
+
+  #[test]
+  fn rotating_the_output_size_turns_it_portrait() {
+    // What `for_context` builds for an 800x480 mode
+    let panel = mapping(Rotation::Normal);
+    let output = TouchMapping::for_axes(panel.x, panel.y, 800, 480);
+
+    let portrait = output.with_rotation(Rotation::Rotate90);
+    assert_eq!((portrait.width, portrait.height), (480, 800));
+    // The panel's top right corner is the picture's top left, its bottom right the top right
+    assert_eq!(portrait.map(1100, 100), (0.0, 0.0));
+    assert_eq!(portrait.map(1100, 1100), (480.0, 0.0));
+    assert_eq!(portrait.map(100, 1100), (480.0, 800.0));
+
+    let back = portrait
+      .with_rotation(Rotation::Rotate270)
+      .with_rotation(Rotation::Rotate180);
+    assert_eq!((back.width, back.height), (800, 480));
+  }
+
+  #[test]
+  fn calibrates_before_rotating() {
+    // Panel x mirrored
+    let calibration: CalibrationMatrix =
+      "LIBINPUT_CALIBRATION_MATRIX=-1 0 1 0 1 0".parse().unwrap();
+    let mapping = mapping(Rotation::Rotate90).with_calibration(calibration);
+    assert_eq!(mapping.map(100, 100), (0.0, 0.0));
+  }
+}
@@ -0,0 +1,40 @@
+#![allow(dead_code)]
+
+// Request number encoding from <asm-generic/ioctl.h>
+use std::os::raw::c_ulong;
+
+const IOC_NRBITS: c_ulong = 8;
+const IOC_TYPEBITS: c_ulong = 8;
+const IOC_SIZEBITS: c_ulong = 14;
+
+const IOC_NRSHIFT: c_ulong = 0;
+const IOC_TYPESHIFT: c_ulong = IOC_NRSHIFT + IOC_NRBITS;
+const IOC_SIZESHIFT: c_ulong = IOC_TYPESHIFT + IOC_TYPEBITS;
+const IOC_DIRSHIFT: c_ulong = IOC_SIZESHIFT + IOC_SIZEBITS;
+
+const IOC_NONE: c_ulong = 0;
+const IOC_WRITE: c_ulong = 1;
+const IOC_READ: c_ulong = 2;
+
+pub const fn ioc(dir: c_ulong, ty: u8, nr: u8, size: usize) -> c_ulong {
+  (dir << IOC_DIRSHIFT)
+    | ((ty as c_ulong) << IOC_TYPESHIFT)
+    | ((nr as c_ulong) << IOC_NRSHIFT)
+    | ((size as c_ulong) << IOC_SIZESHIFT)
+}
+
+pub const fn io(ty: u8, nr: u8) -> c_ulong {
+  ioc(IOC_NONE, ty, nr, 0)
+}
+
+pub const fn ior(ty: u8, nr: u8, size: usize) -> c_ulong {
+  ioc(IOC_READ, ty, nr, size)
+}
+
+pub const fn iow(ty: u8, nr: u8, size: usize) -> c_ulong {
+  ioc(IOC_WRITE, ty, nr, size)
+}
+
+pub const fn iowr(ty: u8, nr: u8, size: usize) -> c_ulong {
+  ioc(IOC_READ | IOC_WRITE, ty, nr, size)
+}
@@ -1,21 +1,110 @@
+mod frame_loop;
+mod ioctl;
+#[macro_use]
+pub mod loader;
+mod presentation;
+pub use frame_loop::{FrameInfo, FramePacing, FrameStats};
+pub use presentation::{PresentMode, PresentationFeedback, VblankInfo};
+
+pub mod input {
+  pub mod input_device;
+  pub mod input_event;
+  pub(crate) mod mini_evdev;
+  pub mod touch_mapping;
+}
+pub use input::input_device::{DeviceCapabilities, Input, InputDevice};
+pub use input::input_event::{EventDecoder, InputEvent, InputEventKind, KeyState};
+pub use input::mini_evdev::{RawAbsInfo, RawInputEvent};
+pub use input::touch_mapping::{CalibrationMatrix, Rotation, TouchMapping};
+
 cfg_if::cfg_if! {
   if #[cfg(feature = "vc6")] {
     // mod mini_drm;
     // mod mini_gbm;
-    mod drm {
+    pub mod drm {
+      pub mod card;
+      pub(crate) mod drm_ioctl;
+      pub(crate) mod dumb_buffer;
+      #[cfg(test)]
+      mod fake_drm;
+      pub mod hotplug;
+      #[cfg(feature = "pure-ioctl")]
+      mod ioctl_ffi;
       pub mod mini_drm;
+      pub(crate) mod output;
     }
+    pub use drm::card::Card;
+    pub use drm::hotplug::{HotplugEvent, HotplugMonitor};
     mod gbm {
+      pub mod gbm_device;
       pub mod mini_gbm;
       pub mod gbm_formats;
     }
+    pub use gbm::gbm_device::{FrontBuffer, GbmBo, GbmDevice, GbmSurface};
 
+    #[cfg(feature = "embedded-graphics")]
+    mod canvas;
+    #[cfg(feature = "embedded-graphics")]
+    pub use canvas::{Canvas, LinearBuffer, PixelFormat};
+    mod dmabuf;
+    pub use dmabuf::{DmabufFrame, OffscreenTarget, RenderBuffer, ScanoutBuffer};
+    mod egl_image;
+    pub use egl_image::{DmabufDesc, DmabufPlane, ImportedImage, GL_TEXTURE_EXTERNAL_OES};
+    mod egl_device;
+    pub use egl_device::EglDevice;
+    mod egl_ffi;
+    mod egl_platform;
+    pub use egl_platform::DisplayPlatform;
     mod egl_utils;
+    pub use egl_utils::{ConfigCaveat, ConfigRequest, EglConfigInfo};
+    mod prime;
+    pub use prime::{PrimeMode, RenderDevice};
+    pub mod event_loop {
+      #[cfg(feature = "calloop")]
+      pub mod calloop_source;
+      #[cfg(feature = "mio")]
+      pub mod mio_source;
+      #[cfg(feature = "tokio")]
+      pub mod tokio_source;
+    }
+    #[cfg(feature = "calloop")]
+    pub use event_loop::calloop_source::ContextSource;
+    #[cfg(feature = "tokio")]
+    pub use event_loop::tokio_source::{AsyncContext, VblankStream};
+    mod frame_share;
+    pub use frame_share::{FrameChannel, FramePresenter, FrameSender, ShareMessage};
+    mod restore;
+    mod session {
+      pub mod direct;
+      #[cfg(feature = "libseat")]
+      pub mod libseat;
+      #[cfg(feature = "logind")]
+      pub mod logind;
+      pub mod session_backend;
+    }
+    pub use session::direct::DirectSession;
+    #[cfg(feature = "libseat")]
+    pub use session::libseat::LibseatSession;
+    #[cfg(feature = "logind")]
+    pub use session::logind::LogindSession;
+    pub use session::session_backend::{SessionBackend, SessionEvent};
+
+    mod software_context;
+    pub use software_context::SoftwareContext;
+
+    mod v4l2 {
+      pub mod capture;
+      pub mod mini_v4l2;
+    }
+    pub use v4l2::capture::{Capture, CaptureFormat, CapturedFrame};
 
     mod vc6_context;
     pub use vc6_context::Context;
   } else {
+    mod bcm_host;
+    mod egl_ffi;
     mod egl_utils;
+    pub use egl_utils::{ConfigCaveat, ConfigRequest, EglConfigInfo};
 
     mod vc4_context;
     pub use vc4_context::Context;
@@ -0,0 +1,238 @@
+#![allow(dead_code)]
+
+// With the `dlopen` feature libEGL, libGLESv2, libgbm and libbcm_host are opened at runtime
+// instead of being linked, so a binary starts (and can fall back to e.g. `SoftwareContext`) on
+// hosts without them. `probe` says whether the GL backend can be used, looking up every symbol
+// declared with `dynamic_ffi!`.
+//
+// libdrm is left out, see the `pure-ioctl` feature for that one.
+
+#[cfg(feature = "dlopen")]
+use std::ffi::{CStr, CString};
+#[cfg(feature = "dlopen")]
+use std::fmt;
+#[cfg(feature = "dlopen")]
+use std::os::raw::c_void;
+#[cfg(feature = "dlopen")]
+use std::sync::OnceLock;
+
+/// Declares the functions of a C library, `extern "C"` when linking and lazily resolved through
+/// `library()` (one of the loaders below) with `dlopen`. One block per module: with `dlopen` it
+/// also defines `resolve_symbols`, for `probe`.
+///
+/// A symbol that can't be resolved at call time (only possible when `probe` wasn't called or
+/// failed) makes the call fail the way C functions do, returning zero or null.
+macro_rules! dynamic_ffi {
+  (@call $library:ident, $name:ident, ($($arg:ident: $ty:ty),*) $(-> $ret:ty)?) => {{
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static SYMBOL: AtomicUsize = AtomicUsize::new(0);
+    let mut address = SYMBOL.load(Ordering::Relaxed);
+    if address == 0 {
+      match $crate::loader::resolve($crate::loader::$library, stringify!($name)) {
+        Ok(resolved) => address = resolved,
+        Err(error) => {
+          println!("{}", error);
+          return std::mem::zeroed();
+        }
+      }
+      SYMBOL.store(address, Ordering::Relaxed);
+    }
+    let function: unsafe extern "C" fn($($ty),*) $(-> $ret)? = std::mem::transmute(address);
+    function($($arg),*)
+  }};
+
+  ($library:ident; $(pub fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*) => {
+    #[cfg(not(feature = "dlopen"))]
+    extern "C" {
+      $(pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+    }
+
+    $(
+      #[cfg(feature = "dlopen")]
+      #[allow(non_snake_case, clippy::too_many_arguments)]
+      pub unsafe fn $name($($arg: $ty),*) $(-> $ret)? {
+        dynamic_ffi!(@call $library, $name, ($($arg: $ty),*) $(-> $ret)?)
+      }
+    )*
+
+    /// Looks up every function above, the first one missing is the error.
+    #[cfg(feature = "dlopen")]
+    pub(crate) fn resolve_symbols() -> Result<(), $crate::loader::Error> {
+      $($crate::loader::resolve($crate::loader::$library, stringify!($name))?;)*
+      Ok(())
+    }
+  };
+}
+
+#[cfg(feature = "dlopen")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+  /// None of the names the library goes by could be opened, `message` is from `dlerror`.
+  LibraryNotFound {
+    library: &'static str,
+    message: String,
+  },
+  SymbolNotFound {
+    library: &'static str,
+    symbol: String,
+  },
+}
+
+#[cfg(feature = "dlopen")]
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Error::LibraryNotFound { library, message } => {
+        write!(f, "Couldn't load {}: {}", library, message)
+      }
+      Error::SymbolNotFound { library, symbol } => {
+        write!(f, "{} has no symbol {}", library, symbol)
+      }
+    }
+  }
+}
+
+#[cfg(feature = "dlopen")]
+impl std::error::Error for Error {}
+
+/// A `dlopen`ed library, open for the life of the process.
+#[cfg(feature = "dlopen")]
+#[derive(Debug)]
+pub struct Library {
+  name: &'static str,
+  handle: *mut c_void,
+}
+
+// The handle is only passed to `dlsym`, which is thread safe
+#[cfg(feature = "dlopen")]
+unsafe impl Send for Library {}
+#[cfg(feature = "dlopen")]
+unsafe impl Sync for Library {}
+
+#[cfg(feature = "dlopen")]
+fn dlerror() -> String {
+  let message = unsafe { libc::dlerror() };
+  if message.is_null() {
+    return "unknown error".to_string();
+  }
+  unsafe { CStr::from_ptr(message) }
+    .to_string_lossy()
+    .into_owned()
+}
+
+#[cfg(feature = "dlopen")]
+impl Library {
+  /// Tries `file_names` in order (sonames first, then unversioned and vendor paths).
+  pub fn open(name: &'static str, file_names: &[&str]) -> Result<Library, Error> {
+    let mut message = String::new();
+    for file_name in file_names {
+      let c_name = CString::new(*file_name).unwrap();
+      let handle = unsafe { libc::dlopen(c_name.as_ptr(), libc::RTLD_NOW | libc::RTLD_GLOBAL) };
+      if !handle.is_null() {
+        return Ok(Library { name, handle });
+      }
+      message = dlerror();
+    }
+
+    Err(Error::LibraryNotFound {
+      library: name,
+      message,
+    })
+  }
+
+  pub fn name(&self) -> &'static str {
+    self.name
+  }
+
+  pub fn symbol(&self, symbol: &str) -> Result<*mut c_void, Error> {
+    let c_symbol = CString::new(symbol).unwrap();
+    let address = unsafe { libc::dlsym(self.handle, c_symbol.as_ptr()) };
+    if address.is_null() {
+      return Err(Error::SymbolNotFound {
+        library: self.name,
+        symbol: symbol.to_string(),
+      });
+    }
+    Ok(address)
+  }
+}
+
+#[cfg(feature = "dlopen")]
+fn load(
+  cell: &'static OnceLock<Result<Library, Error>>,
+  name: &'static str,
+  file_names: &[&str],
+) -> Result<&'static Library, Error> {
+  cell
+    .get_or_init(|| Library::open(name, file_names))
+    .as_ref()
+    .map_err(Clone::clone)
+}
+
+#[cfg(feature = "dlopen")]
+pub fn egl() -> Result<&'static Library, Error> {
+  static LIBRARY: OnceLock<Result<Library, Error>> = OnceLock::new();
+  load(
+    &LIBRARY,
+    "libEGL",
+    &["libEGL.so.1", "libEGL.so", "/opt/vc/lib/libbrcmEGL.so"],
+  )
+}
+
+#[cfg(feature = "dlopen")]
+pub fn gles() -> Result<&'static Library, Error> {
+  static LIBRARY: OnceLock<Result<Library, Error>> = OnceLock::new();
+  load(
+    &LIBRARY,
+    "libGLESv2",
+    &[
+      "libGLESv2.so.2",
+      "libGLESv2.so",
+      "/opt/vc/lib/libbrcmGLESv2.so",
+    ],
+  )
+}
+
+#[cfg(feature = "dlopen")]
+pub fn gbm() -> Result<&'static Library, Error> {
+  static LIBRARY: OnceLock<Result<Library, Error>> = OnceLock::new();
+  load(&LIBRARY, "libgbm", &["libgbm.so.1", "libgbm.so"])
+}
+
+#[cfg(feature = "dlopen")]
+pub fn bcm_host() -> Result<&'static Library, Error> {
+  static LIBRARY: OnceLock<Result<Library, Error>> = OnceLock::new();
+  load(
+    &LIBRARY,
+    "libbcm_host",
+    &["libbcm_host.so", "/opt/vc/lib/libbcm_host.so"],
+  )
+}
+
+/// Loads everything `Context` needs and looks up every function it calls, the first missing
+/// library or symbol is the error.
+#[cfg(feature = "dlopen")]
+pub fn probe() -> Result<(), Error> {
+  // Whatever the application calls GL through
+  gles()?;
+  crate::egl_ffi::ffi::resolve_symbols()?;
+  #[cfg(feature = "vc6")]
+  {
+    crate::egl_image::ffi::resolve_symbols()?;
+    crate::gbm::mini_gbm::ffi::resolve_symbols()?;
+  }
+  #[cfg(not(feature = "vc6"))]
+  crate::bcm_host::ffi::resolve_symbols()?;
+  Ok(())
+}
+
+#[cfg(feature = "dlopen")]
+pub(crate) fn resolve(
+  library: fn() -> Result<&'static Library, Error>,
+  symbol: &str,
+) -> Result<usize, Error> {
+  library()
+    .and_then(|library| library.symbol(symbol))
+    .map(|address| address as usize)
+}
@@ -0,0 +1,87 @@
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+/// How `Context::swap_buffers` hands frames to the display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PresentMode {
+  /// Every frame is shown for at least one refresh, `swap_buffers` blocks until it is on screen.
+  /// No tearing, lowest power.
+  #[default]
+  Fifo,
+  /// Frames replace the current one as soon as they are ready, tearing allowed. Falls back to
+  /// flipping on vblank (without waiting for it) when the driver has no async flips.
+  Immediate,
+  /// `swap_buffers` never waits for vblank: the newest frame queued behind the current flip wins
+  /// and older ones are dropped. No tearing, lowest latency.
+  Mailbox,
+}
+
+/// When and how a frame made it to the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PresentationFeedback {
+  /// Number of the `swap_buffers` call that submitted the frame, starting at 0
+  pub frame: u64,
+  /// Hardware vblank counter of the refresh the frame was first shown at
+  pub sequence: u32,
+  /// `CLOCK_MONOTONIC` time of that vblank
+  pub timestamp: Duration,
+  /// Time between two vblanks, `None` when the backend can't tell
+  pub refresh: Option<Duration>,
+  /// The frame was shown more than one refresh after the previous one
+  pub missed_deadline: bool,
+}
+
+/// A vblank of the CRTC driving the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VblankInfo {
+  /// Hardware vblank counter
+  pub sequence: u32,
+  /// `CLOCK_MONOTONIC` time of the vblank
+  pub timestamp: Duration,
+}
+
+// Feedback for the frames of one CRTC, missed deadlines told from the vblank counter
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FeedbackTracker {
+  last_sequence: Option<u32>,
+}
+
+impl FeedbackTracker {
+  pub fn presented(
+    &mut self,
+    frame: u64,
+    sequence: u32,
+    timestamp: Duration,
+    refresh: Duration,
+  ) -> PresentationFeedback {
+    let missed_deadline = match self.last_sequence {
+      Some(last_sequence) => sequence.wrapping_sub(last_sequence) > 1,
+      None => false,
+    };
+    self.last_sequence = Some(sequence);
+
+    PresentationFeedback {
+      frame,
+      sequence,
+      timestamp,
+      refresh: Some(refresh),
+      missed_deadline,
+    }
+  }
+
+  /// The next frame can't be compared to the previous one (e.g. lost vblank counter).
+  pub fn reset(&mut self) {
+    self.last_sequence = None;
+  }
+}
+
+pub fn monotonic_now() -> Duration {
+  let mut now = libc::timespec {
+    tv_sec: 0,
+    tv_nsec: 0,
+  };
+  unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+
+  Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
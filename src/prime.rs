@@ -0,0 +1,229 @@
+#![allow(dead_code)]
+
+// Rendering on one DRM device and scanning out on another (PRIME), e.g. `v3d` and `vc4-drm` on
+// the Pi 4, or a discrete GPU next to a display controller. Frames rendered on the render node
+// are shared with the card as dmabufs.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::ptr;
+
+use crate::dmabuf;
+use crate::drm::drm_ioctl;
+use crate::drm::dumb_buffer::DumbBuffer;
+use crate::drm::mini_drm as drm;
+use crate::drm::mini_drm::DRMFramebufferLayout;
+use crate::gbm::gbm_device::FrontBuffer;
+use crate::gbm::mini_gbm as gbm;
+use crate::gbm::mini_gbm::{gbm_bo_transfer_flags, DRM_FORMAT_MOD_INVALID};
+
+/// Where `Context` renders, see `Context::with_render_device`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum RenderDevice {
+  /// The card, on its own EGL device when EGL lists one. Unless EGL can't render there, then
+  /// the render node of another GPU
+  #[default]
+  Auto,
+  /// The card driving the display, failing when EGL renders on another GPU than the card's
+  Card,
+  /// A render node, e.g. `/dev/dri/renderD128`
+  Node(PathBuf),
+}
+
+/// How frames from a separate render device get to the card.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PrimeMode {
+  /// The rendered buffers are imported into the card and scanned out as they are
+  Import,
+  /// The rendered buffers are copied into linear buffers of the card by the CPU. Slow, but
+  /// works whatever the tiling of the render device.
+  Copy,
+}
+
+// A rendered buffer as a framebuffer of the card
+pub(crate) struct PrimeFrame {
+  pub fb: u32,
+  // GEM handles on the card to close with the framebuffer, none for copies
+  pub handles: Vec<u32>,
+  // Copies go to framebuffers owned by `Prime`
+  pub copied: bool,
+}
+
+// Most frames copied between import attempts while the card keeps rejecting them
+const MAX_IMPORT_BACKOFF: u32 = 64;
+
+pub(crate) struct Prime {
+  mode: PrimeMode,
+  copies: Vec<DumbBuffer>,
+  // Failed imports in a row, and frames left to copy before trying again
+  failures: u32,
+  backoff: u32,
+  error: Option<io::Error>,
+}
+
+impl Prime {
+  pub fn new(mode: PrimeMode) -> Prime {
+    Prime {
+      mode,
+      copies: Vec::new(),
+      failures: 0,
+      backoff: 0,
+      error: None,
+    }
+  }
+
+  pub fn mode(&self) -> PrimeMode {
+    self.mode
+  }
+
+  pub fn set_mode(&mut self, mode: PrimeMode) {
+    self.mode = mode;
+    self.failures = 0;
+    self.backoff = 0;
+  }
+
+  /// Why the last import failed, since the last call.
+  pub fn take_error(&mut self) -> Option<io::Error> {
+    self.error.take()
+  }
+
+  /// Framebuffer on `card` for `bo`. Frames the card can't take are copied, and import is
+  /// retried after a number of copied frames that doubles with each failure in a row.
+  /// `in_use` are the framebuffers still on screen or queued.
+  pub fn import(
+    &mut self,
+    card: &File,
+    bo: &FrontBuffer<'_>,
+    in_use: &[u32],
+  ) -> io::Result<PrimeFrame> {
+    if self.mode == PrimeMode::Import {
+      if self.backoff > 0 {
+        self.backoff -= 1;
+      } else {
+        match import_bo(card, bo) {
+          Ok(frame) => {
+            self.failures = 0;
+            return Ok(frame);
+          }
+          Err(error) => {
+            self.failures = self.failures.saturating_add(1);
+            self.backoff = (1 << (self.failures - 1).min(6)).min(MAX_IMPORT_BACKOFF);
+            self.error = Some(error);
+          }
+        }
+      }
+    }
+
+    self.copy(card, bo, in_use)
+  }
+
+  fn copy(&mut self, card: &File, bo: &FrontBuffer<'_>, in_use: &[u32]) -> io::Result<PrimeFrame> {
+    let bo = bo.as_raw();
+    let width = gbm::bo_get_width(bo);
+    let height = gbm::bo_get_height(bo);
+
+    let index = match self
+      .copies
+      .iter()
+      .position(|copy| !in_use.contains(&copy.fb))
+    {
+      Some(index) => index,
+      None => {
+        self.copies.push(DumbBuffer::new(card, width, height)?);
+        self.copies.len() - 1
+      }
+    };
+
+    // Mapping a tiled buffer gives a linear view of it, the driver detiles
+    let (source, stride, map_data) = gbm::bo_map(
+      bo,
+      0,
+      0,
+      width,
+      height,
+      gbm_bo_transfer_flags::GBM_BO_TRANSFER_READ,
+    )
+    .ok_or_else(|| io::Error::other("Couldn't map rendered buffer"))?;
+
+    let target = &mut self.copies[index];
+    let pitch = target.pitch as usize;
+    let row = (width as usize * 4).min(stride as usize).min(pitch);
+    let pixels = target.pixels();
+    for y in 0..height as usize {
+      unsafe {
+        ptr::copy_nonoverlapping(
+          source.add(y * stride as usize),
+          pixels.as_mut_ptr().add(y * pitch),
+          row,
+        );
+      }
+    }
+    gbm::bo_unmap(bo, map_data);
+
+    Ok(PrimeFrame {
+      fb: target.fb,
+      handles: Vec::new(),
+      copied: true,
+    })
+  }
+
+  pub fn destroy(&mut self, card: &File) {
+    for copy in self.copies.iter_mut() {
+      copy.destroy(card);
+    }
+    self.copies.clear();
+  }
+}
+
+fn import_bo(card: &File, bo: &FrontBuffer<'_>) -> io::Result<PrimeFrame> {
+  let frame = dmabuf::export_bo(bo)?;
+
+  let mut layout = DRMFramebufferLayout {
+    width: frame.width,
+    height: frame.height,
+    pixel_format: frame.fourcc,
+    modifier: if frame.modifier == DRM_FORMAT_MOD_INVALID {
+      None
+    } else {
+      Some(frame.modifier)
+    },
+    ..Default::default()
+  };
+  let mut owned = Vec::with_capacity(frame.fds.len());
+  for (i, fd) in frame.fds.iter().enumerate().take(4) {
+    let handle = match drm::prime_fd_to_handle(card, fd.as_raw_fd()) {
+      Ok(handle) => handle,
+      Err(error) => {
+        close_handles(card, &owned);
+        return Err(error);
+      }
+    };
+    // Planes sharing a dmabuf get the same handle
+    if !owned.contains(&handle) {
+      owned.push(handle);
+    }
+    layout.handles[i] = handle;
+    layout.pitches[i] = frame.strides[i];
+    layout.offsets[i] = frame.offsets[i];
+  }
+
+  match drm::mode_add_fb2(card, &layout) {
+    Ok(fb) => Ok(PrimeFrame {
+      fb,
+      handles: owned,
+      copied: false,
+    }),
+    Err(error) => {
+      close_handles(card, &owned);
+      Err(error)
+    }
+  }
+}
+
+pub(crate) fn close_handles(card: &File, handles: &[u32]) {
+  for handle in handles {
+    drm_ioctl::gem_close(card.as_raw_fd(), *handle);
+  }
+}
@@ -0,0 +1,393 @@
+#![allow(dead_code)]
+
+// Restores the display configuration found at startup when the process dies without running
+// `Drop for Context`: on fatal signals and, with `panic = "abort"`, from the panic hook.
+// Signals the application handles or ignores aren't fatal, they're passed on untouched.
+//
+// Everything done from the handlers goes through the pre-saved `Snapshot` and plain ioctls,
+// no allocation, no locks and no libdrm, so it stays async-signal-safe.
+
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::Once;
+
+use crate::drm::drm_ioctl::{self, drm_mode_crtc, drm_mode_crtc_lut};
+use crate::drm::mini_drm as drm;
+
+const KDSETMODE: c_ulong = 0x4B3A;
+const KDGETMODE: c_ulong = 0x4B3B;
+
+const SIGNALS: [c_int; 5] = [
+  libc::SIGINT,
+  libc::SIGTERM,
+  libc::SIGHUP,
+  libc::SIGQUIT,
+  libc::SIGABRT,
+];
+
+static SNAPSHOT: AtomicPtr<Snapshot> = AtomicPtr::new(ptr::null_mut());
+static PANIC_HOOK: Once = Once::new();
+
+struct Snapshot {
+  drm_fd: RawFd,
+  crtc: drm_mode_crtc,
+  connectors: Box<[u32]>,
+
+  red: Box<[u16]>,
+  green: Box<[u16]>,
+  blue: Box<[u16]>,
+
+  tty_fd: RawFd,
+  kd_mode: c_int,
+
+  previous_actions: [libc::sigaction; SIGNALS.len()],
+}
+
+impl Snapshot {
+  fn take(drm_fd: RawFd, crtc: &drm::DRMModeCrtc, connector_id: u32) -> Snapshot {
+    let connectors: Box<[u32]> = Box::new([connector_id]);
+
+    let gamma_size = crtc.gamma_size.max(0) as usize;
+    let mut red = vec![0u16; gamma_size].into_boxed_slice();
+    let mut green = vec![0u16; gamma_size].into_boxed_slice();
+    let mut blue = vec![0u16; gamma_size].into_boxed_slice();
+    if gamma_size > 0 {
+      let mut lut = drm_mode_crtc_lut {
+        crtc_id: crtc.crtc_id,
+        gamma_size: gamma_size as u32,
+        red: red.as_mut_ptr() as u64,
+        green: green.as_mut_ptr() as u64,
+        blue: blue.as_mut_ptr() as u64,
+      };
+      if unsafe { drm_ioctl::drm_ioctl(drm_fd, drm_ioctl::DRM_IOCTL_MODE_GETGAMMA, &mut lut) } != 0
+      {
+        // Nothing to restore then
+        red = Box::new([]);
+        green = Box::new([]);
+        blue = Box::new([]);
+      }
+    }
+
+    let (tty_fd, kd_mode) = save_console_mode();
+
+    Snapshot {
+      drm_fd,
+      crtc: drm_mode_crtc {
+        set_connectors_ptr: connectors.as_ptr() as u64,
+        count_connectors: connectors.len() as u32,
+        crtc_id: crtc.crtc_id,
+        fb_id: crtc.buffer_id,
+        x: crtc.x,
+        y: crtc.y,
+        gamma_size: 0,
+        mode_valid: crtc.mode_valid as u32,
+        mode: crtc.mode.raw,
+      },
+      connectors,
+      red,
+      green,
+      blue,
+      tty_fd,
+      kd_mode,
+      previous_actions: unsafe { mem::zeroed() },
+    }
+  }
+
+  // Only ioctl(2) in here
+  fn restore(&self) {
+    unsafe {
+      let mut crtc = self.crtc;
+      drm_ioctl::drm_ioctl(self.drm_fd, drm_ioctl::DRM_IOCTL_MODE_SETCRTC, &mut crtc);
+
+      if !self.red.is_empty() {
+        let mut lut = drm_mode_crtc_lut {
+          crtc_id: self.crtc.crtc_id,
+          gamma_size: self.red.len() as u32,
+          red: self.red.as_ptr() as u64,
+          green: self.green.as_ptr() as u64,
+          blue: self.blue.as_ptr() as u64,
+        };
+        drm_ioctl::drm_ioctl(self.drm_fd, drm_ioctl::DRM_IOCTL_MODE_SETGAMMA, &mut lut);
+      }
+
+      if self.tty_fd >= 0 {
+        libc::ioctl(self.tty_fd, KDSETMODE as _, self.kd_mode);
+      }
+    }
+  }
+}
+
+impl Drop for Snapshot {
+  fn drop(&mut self) {
+    if self.tty_fd >= 0 {
+      unsafe { libc::close(self.tty_fd) };
+    }
+  }
+}
+
+fn save_console_mode() -> (RawFd, c_int) {
+  unsafe {
+    let tty_fd = libc::open(
+      b"/dev/tty\0".as_ptr() as *const _,
+      libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC,
+    );
+    if tty_fd < 0 {
+      return (-1, 0);
+    }
+
+    let mut kd_mode: c_int = 0;
+    if libc::ioctl(tty_fd, KDGETMODE as _, &mut kd_mode) != 0 {
+      // Not a virtual terminal
+      libc::close(tty_fd);
+      return (-1, 0);
+    }
+
+    (tty_fd, kd_mode)
+  }
+}
+
+fn restore_snapshot() {
+  let snapshot = SNAPSHOT.swap(ptr::null_mut(), Ordering::SeqCst);
+  if !snapshot.is_null() {
+    unsafe { (*snapshot).restore() };
+  }
+}
+
+// What a signal does with the action that was there before the guard
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Disposition {
+  // Kills the process: restore, then die the default way
+  Fatal,
+  Ignore,
+  // The application's handler, shutting down through `Drop for Context` if it wants to
+  Chain,
+}
+
+fn disposition(previous: &libc::sigaction) -> Disposition {
+  match previous.sa_sigaction {
+    libc::SIG_DFL => Disposition::Fatal,
+    libc::SIG_IGN => Disposition::Ignore,
+    _ => Disposition::Chain,
+  }
+}
+
+extern "C" fn on_signal(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+  let snapshot = SNAPSHOT.load(Ordering::SeqCst);
+  unsafe {
+    let previous = match snapshot.is_null() {
+      true => None,
+      false => SIGNALS
+        .iter()
+        .position(|s| *s == signal)
+        .map(|i| &(*snapshot).previous_actions[i]),
+    };
+
+    match previous {
+      Some(previous) if disposition(previous) == Disposition::Ignore => {}
+      Some(previous) if disposition(previous) == Disposition::Chain => {
+        if (previous.sa_flags & libc::SA_SIGINFO) != 0 {
+          let handler = mem::transmute::<
+            libc::sighandler_t,
+            extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void),
+          >(previous.sa_sigaction);
+          handler(signal, info, context);
+        } else {
+          let handler =
+            mem::transmute::<libc::sighandler_t, extern "C" fn(c_int)>(previous.sa_sigaction);
+          handler(signal);
+        }
+      }
+      _ => {
+        restore_snapshot();
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = libc::SIG_DFL;
+        libc::sigaction(signal, &action, ptr::null_mut());
+        libc::raise(signal);
+      }
+    }
+  }
+}
+
+fn install_panic_hook() {
+  PANIC_HOOK.call_once(|| {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+      // When unwinding `Drop for Context` takes care of it, and the panic might still be caught
+      if cfg!(panic = "abort") {
+        restore_snapshot();
+      }
+      previous(info);
+    }));
+  });
+}
+
+pub struct RestoreGuard {
+  snapshot: Box<Snapshot>,
+}
+
+impl RestoreGuard {
+  pub fn install(
+    drm_fd: RawFd,
+    crtc: &drm::DRMModeCrtc,
+    connector_id: u32,
+  ) -> io::Result<RestoreGuard> {
+    let mut snapshot = Box::new(Snapshot::take(drm_fd, crtc, connector_id));
+
+    unsafe {
+      let mut action: libc::sigaction = mem::zeroed();
+      action.sa_sigaction =
+        on_signal as extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) as libc::sighandler_t;
+      action.sa_flags = libc::SA_SIGINFO;
+      libc::sigemptyset(&mut action.sa_mask);
+      for (i, signal) in SIGNALS.iter().enumerate() {
+        if libc::sigaction(*signal, ptr::null(), &mut snapshot.previous_actions[i]) != 0 {
+          return Err(io::Error::last_os_error());
+        }
+      }
+
+      let snapshot_ptr: *mut Snapshot = &mut *snapshot;
+      if SNAPSHOT
+        .compare_exchange(
+          ptr::null_mut(),
+          snapshot_ptr,
+          Ordering::SeqCst,
+          Ordering::SeqCst,
+        )
+        .is_err()
+      {
+        return Err(io::Error::new(
+          io::ErrorKind::AlreadyExists,
+          "A restore guard is already installed",
+        ));
+      }
+
+      for signal in SIGNALS.iter() {
+        if libc::sigaction(*signal, &action, ptr::null_mut()) != 0 {
+          let error = io::Error::last_os_error();
+          let guard = RestoreGuard { snapshot };
+          drop(guard);
+          return Err(error);
+        }
+      }
+    }
+
+    install_panic_hook();
+
+    Ok(RestoreGuard { snapshot })
+  }
+}
+
+impl Drop for RestoreGuard {
+  fn drop(&mut self) {
+    let snapshot_ptr: *mut Snapshot = &mut *self.snapshot;
+    let _ = SNAPSHOT.compare_exchange(
+      snapshot_ptr,
+      ptr::null_mut(),
+      Ordering::SeqCst,
+      Ordering::SeqCst,
+    );
+
+    for (i, signal) in SIGNALS.iter().enumerate() {
+      unsafe {
+        libc::sigaction(*signal, &self.snapshot.previous_actions[i], ptr::null_mut());
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::atomic::AtomicUsize;
+
+  static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+  extern "C" fn application_handler(_signal: c_int) {
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+  }
+
+  fn action(handler: libc::sighandler_t) -> libc::sigaction {
+    let mut action: libc::sigaction = unsafe { mem::zeroed() };
+    action.sa_sigaction = handler;
+    action
+  }
+
+  fn set_action(signal: c_int, handler: libc::sighandler_t) {
+    unsafe { libc::sigaction(signal, &action(handler), ptr::null_mut()) };
+  }
+
+  fn current_handler(signal: c_int) -> libc::sighandler_t {
+    let mut current: libc::sigaction = unsafe { mem::zeroed() };
+    unsafe { libc::sigaction(signal, ptr::null(), &mut current) };
+    current.sa_sigaction
+  }
+
+  fn crtc() -> drm::DRMModeCrtc {
+    let raw_mode: drm::RawDRMModeModeInfo = unsafe { mem::zeroed() };
+    drm::DRMModeCrtc {
+      crtc_id: 0,
+      buffer_id: 0,
+      x: 0,
+      y: 0,
+      width: 0,
+      height: 0,
+      mode_valid: 0,
+      mode: drm::DRMModeModeInfo::from_raw(&raw_mode),
+      gamma_size: 0,
+    }
+  }
+
+  #[test]
+  fn only_default_actions_are_fatal() {
+    let handler = application_handler as extern "C" fn(c_int) as libc::sighandler_t;
+    assert_eq!(disposition(&action(libc::SIG_DFL)), Disposition::Fatal);
+    assert_eq!(disposition(&action(libc::SIG_IGN)), Disposition::Ignore);
+    assert_eq!(disposition(&action(handler)), Disposition::Chain);
+  }
+
+  // One test, the handlers and the snapshot are process-wide
+  #[test]
+  fn passes_handled_and_ignored_signals_on() {
+    let handler = application_handler as extern "C" fn(c_int) as libc::sighandler_t;
+    set_action(libc::SIGHUP, handler);
+    set_action(libc::SIGQUIT, libc::SIG_IGN);
+
+    // No card behind -1, restoring would only fail
+    let guard = RestoreGuard::install(-1, &crtc(), 0).unwrap();
+    assert_ne!(current_handler(libc::SIGHUP), handler);
+    let second = RestoreGuard::install(-1, &crtc(), 0);
+    assert_eq!(second.err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+
+    unsafe {
+      libc::raise(libc::SIGHUP);
+      libc::raise(libc::SIGQUIT);
+    }
+    assert_eq!(HANDLED.load(Ordering::SeqCst), 1);
+    // Still armed for a later fatal signal, which kills as it would without the guard
+    assert!(!SNAPSHOT.load(Ordering::SeqCst).is_null());
+    unsafe {
+      let child = libc::fork();
+      if child == 0 {
+        libc::raise(libc::SIGTERM);
+        libc::_exit(0);
+      }
+      let mut status = 0;
+      assert_eq!(libc::waitpid(child, &mut status, 0), child);
+      assert!(libc::WIFSIGNALED(status));
+      assert_eq!(libc::WTERMSIG(status), libc::SIGTERM);
+    }
+
+    drop(guard);
+    assert!(SNAPSHOT.load(Ordering::SeqCst).is_null());
+    assert_eq!(current_handler(libc::SIGHUP), handler);
+    assert_eq!(current_handler(libc::SIGQUIT), libc::SIG_IGN);
+
+    set_action(libc::SIGHUP, libc::SIG_DFL);
+    set_action(libc::SIGQUIT, libc::SIG_DFL);
+  }
+}
@@ -0,0 +1,176 @@
+#![allow(dead_code)]
+
+// Device brokering through libseat (seatd, or logind behind libseat's own backend)
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use super::session_backend::{SessionBackend, SessionEvent};
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+pub struct RawSeat {
+  _unused: [u8; 0],
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+pub struct RawSeatListener {
+  pub enable_seat: extern "C" fn(seat: *mut RawSeat, userdata: *mut c_void),
+  pub disable_seat: extern "C" fn(seat: *mut RawSeat, userdata: *mut c_void),
+}
+
+pub mod ffi {
+  use super::*;
+
+  #[link(name = "seat")]
+  extern "C" {
+    pub fn libseat_open_seat(
+      listener: *const RawSeatListener,
+      userdata: *mut c_void,
+    ) -> *mut RawSeat;
+    pub fn libseat_disable_seat(seat: *mut RawSeat) -> c_int;
+    pub fn libseat_close_seat(seat: *mut RawSeat) -> c_int;
+    pub fn libseat_open_device(seat: *mut RawSeat, path: *const c_char, fd: *mut c_int) -> c_int;
+    pub fn libseat_close_device(seat: *mut RawSeat, device_id: c_int) -> c_int;
+    pub fn libseat_get_fd(seat: *mut RawSeat) -> c_int;
+    pub fn libseat_dispatch(seat: *mut RawSeat, timeout: c_int) -> c_int;
+  }
+}
+
+static LISTENER: RawSeatListener = RawSeatListener {
+  enable_seat,
+  disable_seat,
+};
+
+// Filled in from the listener callbacks, which only run inside `libseat_dispatch`
+struct SeatState {
+  active: bool,
+  events: Vec<SessionEvent>,
+}
+
+extern "C" fn enable_seat(_seat: *mut RawSeat, userdata: *mut c_void) {
+  let state = unsafe { &mut *(userdata as *mut SeatState) };
+  state.active = true;
+  state.events.push(SessionEvent::Resumed);
+}
+
+extern "C" fn disable_seat(_seat: *mut RawSeat, userdata: *mut c_void) {
+  let state = unsafe { &mut *(userdata as *mut SeatState) };
+  state.active = false;
+  state.events.push(SessionEvent::Paused);
+}
+
+pub struct LibseatSession {
+  seat: *mut RawSeat,
+  state: Box<SeatState>,
+  devices: HashMap<PathBuf, c_int>,
+}
+
+impl LibseatSession {
+  pub fn open() -> io::Result<LibseatSession> {
+    let mut state = Box::new(SeatState {
+      active: false,
+      events: Vec::new(),
+    });
+
+    let seat =
+      unsafe { ffi::libseat_open_seat(&LISTENER, &mut *state as *mut SeatState as *mut c_void) };
+    if seat.is_null() {
+      return Err(io::Error::last_os_error());
+    }
+
+    let mut session = LibseatSession {
+      seat,
+      state,
+      devices: HashMap::new(),
+    };
+
+    // The seat is usually enabled right away, pick that up before handing out devices
+    session.dispatch()?;
+
+    Ok(session)
+  }
+}
+
+impl SessionBackend for LibseatSession {
+  fn open_device(&mut self, path: &Path) -> io::Result<File> {
+    let c_path = CString::new(path.as_os_str().as_bytes())
+      .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+    let mut fd: c_int = -1;
+    let device_id = unsafe { ffi::libseat_open_device(self.seat, c_path.as_ptr(), &mut fd) };
+    if device_id < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    self.devices.insert(path.to_path_buf(), device_id);
+
+    Ok(unsafe { File::from_raw_fd(fd) })
+  }
+
+  fn close_device(&mut self, path: &Path, device: File) -> io::Result<()> {
+    // libseat wants the fd to be closed by the caller
+    drop(device);
+
+    match self.devices.remove(path) {
+      Some(device_id) => {
+        if unsafe { ffi::libseat_close_device(self.seat, device_id) } < 0 {
+          return Err(io::Error::last_os_error());
+        }
+        Ok(())
+      }
+      None => Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Device wasn't opened through this seat",
+      )),
+    }
+  }
+
+  fn is_active(&self) -> bool {
+    self.state.active
+  }
+
+  fn dispatch(&mut self) -> io::Result<Vec<SessionEvent>> {
+    if unsafe { ffi::libseat_dispatch(self.seat, 0) } < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    let events: Vec<SessionEvent> = self.state.events.drain(..).collect();
+    for event in events.iter() {
+      if let SessionEvent::Paused = event {
+        if unsafe { ffi::libseat_disable_seat(self.seat) } < 0 {
+          return Err(io::Error::last_os_error());
+        }
+      }
+    }
+
+    Ok(events)
+  }
+
+  fn fd(&self) -> Option<RawFd> {
+    let fd = unsafe { ffi::libseat_get_fd(self.seat) };
+    if fd < 0 {
+      return None;
+    }
+    Some(fd)
+  }
+}
+
+impl Drop for LibseatSession {
+  fn drop(&mut self) {
+    for (_, device_id) in self.devices.drain() {
+      unsafe { ffi::libseat_close_device(self.seat, device_id) };
+    }
+    if unsafe { ffi::libseat_close_seat(self.seat) } < 0 {
+      println!("Error closing seat");
+    }
+    self.seat = ptr::null_mut();
+  }
+}
@@ -0,0 +1,485 @@
+#![allow(dead_code)]
+
+// Device brokering through systemd-logind, see
+// https://www.freedesktop.org/wiki/Software/systemd/logind/ ("Session Objects")
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+
+use zbus::blocking::{Connection, ConnectionBuilder, Proxy};
+use zbus::zvariant::{OwnedFd, OwnedObjectPath};
+use zbus::Message;
+
+use super::session_backend::{SessionBackend, SessionEvent};
+
+const LOGIN1_SERVICE: &str = "org.freedesktop.login1";
+const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+const LOGIN1_MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const LOGIN1_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+
+const DRM_MAJOR: u32 = 226;
+
+pub struct LogindSession {
+  session: Proxy<'static>,
+  signals: Receiver<Arc<Message>>,
+  // Signalled by the thread forwarding `signals`, so event loops can wait on it
+  wakeup: Arc<File>,
+  active: bool,
+  // Taken DRM nodes, their pauses are the session's. Other devices (evdev) pause on their own.
+  drm_devices: Vec<(u32, u32)>,
+}
+
+fn dbus_error(error: zbus::Error) -> io::Error {
+  io::Error::other(error)
+}
+
+fn device_number(path: &Path) -> io::Result<(u32, u32)> {
+  let rdev = std::fs::metadata(path)?.rdev();
+  #[allow(unused_unsafe)]
+  Ok(unsafe { (libc::major(rdev), libc::minor(rdev)) })
+}
+
+impl LogindSession {
+  /// Takes control of the current session on the system bus.
+  pub fn connect() -> io::Result<LogindSession> {
+    LogindSession::with_connection(Connection::system().map_err(dbus_error)?)
+  }
+
+  /// Same as `connect`, but on a private bus (e.g. one running a mock logind service).
+  pub fn connect_to(address: &str) -> io::Result<LogindSession> {
+    let connection = ConnectionBuilder::address(address)
+      .and_then(|builder| builder.build())
+      .map_err(dbus_error)?;
+
+    LogindSession::with_connection(connection)
+  }
+
+  fn with_connection(connection: Connection) -> io::Result<LogindSession> {
+    let manager = Proxy::new(
+      &connection,
+      LOGIN1_SERVICE,
+      LOGIN1_PATH,
+      LOGIN1_MANAGER_INTERFACE,
+    )
+    .map_err(dbus_error)?;
+
+    let session_path: OwnedObjectPath = match std::env::var("XDG_SESSION_ID") {
+      Ok(session_id) => manager.call("GetSession", &(session_id,)),
+      Err(_) => manager.call("GetSessionByPID", &(std::process::id(),)),
+    }
+    .map_err(dbus_error)?;
+
+    let session = Proxy::new_owned(
+      connection,
+      LOGIN1_SERVICE,
+      session_path,
+      LOGIN1_SESSION_INTERFACE,
+    )
+    .map_err(dbus_error)?;
+
+    // Subscribe before taking control so no pause/resume gets lost. One stream for both, a
+    // quick pause and resume (switching VTs away and back) must not swap.
+    let signals = session.receive_all_signals().map_err(dbus_error)?;
+
+    session
+      .call::<_, _, ()>("TakeControl", &(false,))
+      .map_err(dbus_error)?;
+
+    let active = session.get_property::<bool>("Active").unwrap_or(true);
+
+    let wakeup = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+    if wakeup < 0 {
+      return Err(io::Error::last_os_error());
+    }
+    let wakeup = Arc::new(unsafe { File::from_raw_fd(wakeup) });
+
+    let (sender, receiver) = mpsc::channel();
+    forward_signals(signals, sender, wakeup.clone());
+
+    Ok(LogindSession {
+      session,
+      signals: receiver,
+      wakeup,
+      active,
+      drm_devices: Vec::new(),
+    })
+  }
+}
+
+fn forward_signals<I>(signals: I, sender: Sender<Arc<Message>>, wakeup: Arc<File>)
+where
+  I: Iterator<Item = Arc<Message>> + Send + 'static,
+{
+  thread::spawn(move || {
+    for message in signals {
+      if sender.send(message).is_err() {
+        break;
+      }
+      // Only fails when the counter is about to overflow, in which case it's readable anyway
+      let _ = (&*wakeup).write(&1u64.to_ne_bytes());
+    }
+    // The connection is gone, for `dispatch` to find out
+    let _ = (&*wakeup).write(&1u64.to_ne_bytes());
+  });
+}
+
+impl SessionBackend for LogindSession {
+  /// Opens a DRM or evdev node through `TakeDevice`, no privileges needed.
+  fn open_device(&mut self, path: &Path) -> io::Result<File> {
+    let (major, minor) = device_number(path)?;
+
+    let (fd, inactive): (OwnedFd, bool) = self
+      .session
+      .call("TakeDevice", &(major, minor))
+      .map_err(dbus_error)?;
+    if major == DRM_MAJOR {
+      if inactive {
+        self.active = false;
+      }
+      self.drm_devices.push((major, minor));
+    }
+
+    Ok(unsafe { File::from_raw_fd(fd.into_raw_fd()) })
+  }
+
+  fn close_device(&mut self, path: &Path, _device: File) -> io::Result<()> {
+    let (major, minor) = device_number(path)?;
+    self.drm_devices.retain(|&device| device != (major, minor));
+
+    self
+      .session
+      .call::<_, _, ()>("ReleaseDevice", &(major, minor))
+      .map_err(dbus_error)
+  }
+
+  fn is_active(&self) -> bool {
+    self.active
+  }
+
+  fn dispatch(&mut self) -> io::Result<Vec<SessionEvent>> {
+    // Reset before draining, so whatever arrives from now on wakes the event loop again
+    let _ = (&*self.wakeup).read(&mut [0u8; 8]);
+
+    let mut events = Vec::new();
+
+    loop {
+      let message = match self.signals.try_recv() {
+        Ok(message) => message,
+        Err(TryRecvError::Empty) => break,
+        Err(TryRecvError::Disconnected) => {
+          // The bus went away and the devices with it, for good
+          if self.active {
+            self.active = false;
+            events.push(SessionEvent::Paused);
+          }
+          break;
+        }
+      };
+
+      let member = message.member();
+      match member.as_ref().map(|name| name.as_str()) {
+        Some("PauseDevice") => {
+          let (major, minor, kind): (u32, u32, String) = message.body().map_err(dbus_error)?;
+          if kind == "gone" {
+            events.push(SessionEvent::DeviceGone { major, minor });
+            continue;
+          }
+          if kind == "pause" {
+            self
+              .session
+              .call::<_, _, ()>("PauseDeviceComplete", &(major, minor))
+              .map_err(dbus_error)?;
+          }
+          // Otherwise "force", the device is already gone
+          if self.drm_devices.contains(&(major, minor)) && self.active {
+            self.active = false;
+            events.push(SessionEvent::Paused);
+          }
+        }
+        Some("ResumeDevice") => {
+          let (major, minor, fd): (u32, u32, OwnedFd) = message.body().map_err(dbus_error)?;
+          let device = unsafe { File::from_raw_fd(fd.into_raw_fd()) };
+          if !self.drm_devices.contains(&(major, minor)) {
+            events.push(SessionEvent::DeviceResumed {
+              major,
+              minor,
+              device,
+            });
+          } else if !self.active {
+            // DRM nodes keep their fd, the new one goes
+            self.active = true;
+            events.push(SessionEvent::Resumed);
+          }
+        }
+        _ => {}
+      }
+    }
+
+    Ok(events)
+  }
+
+  fn fd(&self) -> Option<RawFd> {
+    Some(self.wakeup.as_raw_fd())
+  }
+}
+
+impl Drop for LogindSession {
+  fn drop(&mut self) {
+    // Releases every device taken as well
+    if self
+      .session
+      .call::<_, _, ()>("ReleaseControl", &())
+      .is_err()
+    {
+      println!("Error releasing logind session control");
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::convert::TryFrom;
+  use std::io::{BufRead, BufReader};
+  use std::process::{Child, Command, Stdio};
+  use std::sync::Mutex;
+  use std::time::{Duration, Instant};
+
+  use zbus::blocking::Connection;
+  use zbus::dbus_interface;
+  use zbus::zvariant::Fd;
+
+  const SESSION_PATH: &str = "/org/freedesktop/login1/session/mock";
+
+  struct MockManager;
+
+  #[dbus_interface(name = "org.freedesktop.login1.Manager")]
+  impl MockManager {
+    fn get_session(&self, _session_id: String) -> OwnedObjectPath {
+      OwnedObjectPath::try_from(SESSION_PATH).unwrap()
+    }
+
+    #[dbus_interface(name = "GetSessionByPID")]
+    fn get_session_by_pid(&self, _pid: u32) -> OwnedObjectPath {
+      OwnedObjectPath::try_from(SESSION_PATH).unwrap()
+    }
+  }
+
+  struct MockSession {
+    calls: Arc<Mutex<Vec<String>>>,
+  }
+
+  #[dbus_interface(name = "org.freedesktop.login1.Session")]
+  impl MockSession {
+    fn take_control(&self, _force: bool) {
+      self.calls.lock().unwrap().push("TakeControl".into());
+    }
+
+    fn release_control(&self) {
+      self.calls.lock().unwrap().push("ReleaseControl".into());
+    }
+
+    fn take_device(&self, major: u32, minor: u32) -> (OwnedFd, bool) {
+      let calls = format!("TakeDevice {} {}", major, minor);
+      self.calls.lock().unwrap().push(calls);
+      let device = File::open("/dev/null").unwrap();
+      (unsafe { OwnedFd::from_raw_fd(device.into_raw_fd()) }, false)
+    }
+
+    fn release_device(&self, major: u32, minor: u32) {
+      let call = format!("ReleaseDevice {} {}", major, minor);
+      self.calls.lock().unwrap().push(call);
+    }
+
+    fn pause_device_complete(&self, major: u32, minor: u32) {
+      let call = format!("PauseDeviceComplete {} {}", major, minor);
+      self.calls.lock().unwrap().push(call);
+    }
+
+    #[dbus_interface(property)]
+    fn active(&self) -> bool {
+      true
+    }
+  }
+
+  // A private bus, killed on drop
+  struct Bus {
+    daemon: Child,
+    address: String,
+  }
+
+  impl Bus {
+    fn start() -> Option<Bus> {
+      let mut daemon = Command::new("dbus-daemon")
+        .args(["--session", "--nofork", "--print-address"])
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+      let mut address = String::new();
+      BufReader::new(daemon.stdout.take().unwrap())
+        .read_line(&mut address)
+        .ok()?;
+      Some(Bus {
+        daemon,
+        address: address.trim().to_string(),
+      })
+    }
+  }
+
+  impl Drop for Bus {
+    fn drop(&mut self) {
+      let _ = self.daemon.kill();
+      let _ = self.daemon.wait();
+    }
+  }
+
+  // Dispatches until `done` or a few seconds passed, the events seen so far
+  fn dispatch_until(
+    session: &mut LogindSession,
+    done: impl Fn(&[SessionEvent]) -> bool,
+  ) -> Vec<SessionEvent> {
+    let mut events = Vec::new();
+    let start = Instant::now();
+    while !done(&events) && start.elapsed() < Duration::from_secs(5) {
+      let mut fd = libc::pollfd {
+        fd: session.fd().unwrap(),
+        events: libc::POLLIN,
+        revents: 0,
+      };
+      unsafe { libc::poll(&mut fd, 1, 100) };
+      events.extend(session.dispatch().unwrap());
+    }
+    events
+  }
+
+  // The mock logind service on `bus`, recording the calls it gets into `calls`
+  fn serve(bus: &Bus, calls: Arc<Mutex<Vec<String>>>) -> Connection {
+    zbus::blocking::ConnectionBuilder::address(bus.address.as_str())
+      .unwrap()
+      .name(LOGIN1_SERVICE)
+      .unwrap()
+      .serve_at(LOGIN1_PATH, MockManager)
+      .unwrap()
+      .serve_at(SESSION_PATH, MockSession { calls })
+      .unwrap()
+      .build()
+      .unwrap()
+  }
+
+  fn emit<B: zbus::export::serde::Serialize + zbus::zvariant::DynamicType>(
+    service: &Connection,
+    name: &str,
+    body: &B,
+  ) {
+    service
+      .emit_signal(
+        None::<&str>,
+        SESSION_PATH,
+        LOGIN1_SESSION_INTERFACE,
+        name,
+        body,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn follows_the_drm_node_through_pauses() {
+    let bus = match Bus::start() {
+      Some(bus) => bus,
+      None => return println!("No dbus-daemon, skipped"),
+    };
+
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let service = serve(&bus, calls.clone());
+
+    let mut session = LogindSession::connect_to(&bus.address).unwrap();
+    assert!(session.is_active());
+
+    // /dev/null stands in for an input device, the DRM node can't be taken without one
+    let input = session.open_device(Path::new("/dev/null")).unwrap();
+    drop(input);
+    session.drm_devices.push((DRM_MAJOR, 0));
+
+    emit(&service, "PauseDevice", &(1u32, 3u32, "pause"));
+    emit(&service, "PauseDevice", &(DRM_MAJOR, 0u32, "pause"));
+    let events = dispatch_until(&mut session, |events| !events.is_empty());
+    assert!(matches!(events[..], [SessionEvent::Paused]));
+    assert!(!session.is_active());
+
+    let null = File::open("/dev/null").unwrap();
+    for (major, minor) in [(1u32, 3u32), (DRM_MAJOR, 0)] {
+      emit(&service, "ResumeDevice", &(major, minor, Fd::from(&null)));
+    }
+    let events = dispatch_until(&mut session, |events| events.len() == 2);
+    assert!(matches!(
+      events[..],
+      [
+        SessionEvent::DeviceResumed {
+          major: 1,
+          minor: 3,
+          ..
+        },
+        SessionEvent::Resumed
+      ]
+    ));
+    assert!(session.is_active());
+
+    // Both pauses were acknowledged, the input device's didn't pause the session
+    let calls = calls.lock().unwrap().clone();
+    assert_eq!(
+      calls,
+      [
+        "TakeControl",
+        "TakeDevice 1 3",
+        "PauseDeviceComplete 1 3",
+        "PauseDeviceComplete 226 0",
+      ]
+    );
+
+    // Losing the bus pauses for good instead of failing
+    drop(service);
+    drop(bus);
+    let events = dispatch_until(&mut session, |events| !events.is_empty());
+    assert!(matches!(events[..], [SessionEvent::Paused]));
+    assert!(!session.is_active());
+    assert!(session.dispatch().unwrap().is_empty());
+  }
+
+  #[test]
+  fn keeps_pause_and_resume_in_order() {
+    let bus = match Bus::start() {
+      Some(bus) => bus,
+      None => return println!("No dbus-daemon, skipped"),
+    };
+    let service = serve(&bus, Arc::new(Mutex::new(Vec::new())));
+
+    let mut session = LogindSession::connect_to(&bus.address).unwrap();
+    session.drm_devices.push((DRM_MAJOR, 0));
+
+    // Switching VTs away and back, a few times over
+    let null = File::open("/dev/null").unwrap();
+    for _ in 0..20 {
+      emit(&service, "PauseDevice", &(DRM_MAJOR, 0u32, "pause"));
+      emit(
+        &service,
+        "ResumeDevice",
+        &(DRM_MAJOR, 0u32, Fd::from(&null)),
+      );
+    }
+    let events = dispatch_until(&mut session, |events| events.len() == 40);
+    assert_eq!(events.len(), 40);
+    for pair in events.chunks(2) {
+      assert!(matches!(
+        pair,
+        [SessionEvent::Paused, SessionEvent::Resumed]
+      ));
+    }
+    assert!(session.is_active());
+  }
+}
@@ -0,0 +1,96 @@
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum SessionEvent {
+  /// The seat was taken away (VT switch, session going inactive). Devices must not be used
+  /// until `Resumed`.
+  Paused,
+  Resumed,
+  /// A revoked device was handed back as a new fd (evdev nodes under logind). DRM devices keep
+  /// their original fd.
+  DeviceResumed {
+    major: u32,
+    minor: u32,
+    device: File,
+  },
+  /// The device was unplugged.
+  DeviceGone {
+    major: u32,
+    minor: u32,
+  },
+}
+
+/// How the context gets hold of device nodes: opening them directly, or brokered by a seat
+/// manager so the process needs no privileges.
+pub trait SessionBackend {
+  fn open_device(&mut self, path: &Path) -> io::Result<File>;
+
+  fn close_device(&mut self, path: &Path, device: File) -> io::Result<()>;
+
+  fn is_active(&self) -> bool;
+
+  /// Processes pending seat notifications without blocking. Pauses are acknowledged here, so
+  /// callers must have stopped using their devices when this returns `Paused`.
+  fn dispatch(&mut self) -> io::Result<Vec<SessionEvent>>;
+
+  /// Readable when `dispatch` has something to process, for event loops. `None` when nothing
+  /// ever changes (`DirectSession`).
+  fn fd(&self) -> Option<RawFd> {
+    None
+  }
+}
+
+// A backend with the events the contexts don't consume kept for the caller
+pub(crate) struct Session {
+  backend: Box<dyn SessionBackend>,
+  events: Vec<SessionEvent>,
+}
+
+impl Session {
+  pub fn new(backend: Box<dyn SessionBackend>) -> Session {
+    Session {
+      backend,
+      events: Vec::new(),
+    }
+  }
+
+  pub fn backend(&self) -> &dyn SessionBackend {
+    &*self.backend
+  }
+
+  pub fn backend_mut(&mut self) -> &mut dyn SessionBackend {
+    &mut *self.backend
+  }
+
+  /// Processes pending notifications, whether the session is active afterwards.
+  pub fn dispatch(&mut self) -> bool {
+    // DRM devices keep their fd across pauses, only the active state matters to the contexts.
+    // The events are the caller's, e.g. evdev nodes it opened through the session come back as
+    // new fds.
+    match self.backend.dispatch() {
+      Ok(events) => self.events.extend(events),
+      Err(error) => println!("Error dispatching session events: {}", error),
+    }
+    self.backend.is_active()
+  }
+
+  pub fn take_events(&mut self) -> Vec<SessionEvent> {
+    std::mem::take(&mut self.events)
+  }
+
+  /// Closes `device`, which is still in use until the context is gone: the backend gets a
+  /// duplicate.
+  pub fn close_device(&mut self, path: &Path, device: &File) {
+    let result = device
+      .try_clone()
+      .and_then(|device| self.backend.close_device(path, device));
+    if let Err(error) = result {
+      println!("Error closing device: {}", error);
+    }
+  }
+}
@@ -0,0 +1,350 @@
+#![allow(dead_code)]
+
+#[cfg(feature = "embedded-graphics")]
+use embedded_graphics_core::primitives::Rectangle;
+use std::io;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(feature = "embedded-graphics")]
+use crate::canvas::{Canvas, PixelFormat};
+use crate::drm::card::Card;
+use crate::drm::dumb_buffer::DumbBuffer;
+use crate::drm::mini_drm as drm;
+use crate::drm::output::{self, Output};
+use crate::gbm::mini_gbm as gbm;
+use crate::presentation::{FeedbackTracker, PresentationFeedback};
+use crate::session::direct::DirectSession;
+use crate::session::session_backend::{Session, SessionBackend, SessionEvent};
+use crate::vc6_context::CARD_PATH;
+
+const FORMAT: u32 = gbm::GBM_FORMAT_XRGB8888;
+
+/// CPU-only counterpart of `Context` for displays that don't need (or can't have) GL: draws into
+/// mmapped dumb buffers and presents them with page flips, double buffered. Works on any KMS
+/// driver, `vkms` included.
+pub struct SoftwareContext {
+  card: Card,
+  path: PathBuf,
+  mode: drm::DRMModeModeInfo,
+  connector_id: u32,
+  crtc: drm::DRMModeCrtc,
+  pipe: u32,
+
+  buffers: [DumbBuffer; 2],
+  // Index of the buffer on screen, the other one is the canvas
+  front: usize,
+  needs_modeset: bool,
+  flip_pending: bool,
+  frame_count: u64,
+  tracker: FeedbackTracker,
+
+  active: bool,
+  session: Session,
+}
+
+impl Default for SoftwareContext {
+  fn default() -> Self {
+    SoftwareContext::new()
+  }
+}
+
+impl SoftwareContext {
+  pub fn new() -> Self {
+    SoftwareContext::with_session(Box::new(DirectSession))
+  }
+
+  /// See `Context::with_session`.
+  pub fn with_session(session: Box<dyn SessionBackend>) -> Self {
+    SoftwareContext::with_card(Path::new(CARD_PATH), session)
+  }
+
+  /// Drives another card than the Pi's, e.g. `/dev/dri/card0` with `vkms`.
+  pub fn with_card(path: &Path, mut session: Box<dyn SessionBackend>) -> Self {
+    let device = session.open_device(path).expect("Couldn't open device");
+
+    let Output {
+      connector_id,
+      mode,
+      crtc,
+      pipe,
+    } = Output::probe(&device);
+
+    let width = mode.hdisplay as u32;
+    let height = mode.vdisplay as u32;
+    let buffers = [
+      DumbBuffer::new(&device, width, height).expect("Couldn't create dumb buffer"),
+      DumbBuffer::new(&device, width, height).expect("Couldn't create dumb buffer"),
+    ];
+
+    let active = session.is_active();
+
+    return SoftwareContext {
+      card: Card::from_file(device),
+      path: path.to_path_buf(),
+      mode,
+      connector_id,
+      crtc,
+      pipe,
+      buffers,
+      front: 1,
+      needs_modeset: true,
+      flip_pending: false,
+      frame_count: 0,
+      tracker: FeedbackTracker::default(),
+      active,
+      session: Session::new(session),
+    };
+  }
+
+  /// The DRM device, see `Context::card`.
+  pub fn card(&self) -> &Card {
+    &self.card
+  }
+
+  pub fn width(&self) -> u32 {
+    self.mode.hdisplay as u32
+  }
+
+  pub fn height(&self) -> u32 {
+    self.mode.vdisplay as u32
+  }
+
+  /// Bytes per row of the canvas, at least `width * 4`.
+  pub fn stride(&self) -> u32 {
+    self.buffers[1 - self.front].pitch
+  }
+
+  /// `GBM_FORMAT_XRGB8888`: little endian, so B, G, R, X in memory.
+  pub fn format(&self) -> u32 {
+    FORMAT
+  }
+
+  /// The buffer drawn into for the next `present`, `stride * height` bytes (maybe more). With
+  /// double buffering it holds the frame from before last, not the one on screen.
+  pub fn canvas(&mut self) -> &mut [u8] {
+    self.buffers[1 - self.front].pixels()
+  }
+
+  /// The canvas as an `embedded-graphics` draw target.
+  #[cfg(feature = "embedded-graphics")]
+  pub fn draw_target(&mut self) -> Canvas<'_> {
+    let (width, height, stride) = (self.width(), self.height(), self.stride());
+    Canvas::new(self.canvas(), width, height, stride, PixelFormat::Xrgb8888)
+  }
+
+  /// Reports which part of the frame on screen changed, i.e. the `Canvas::damage` of the frame
+  /// just `present`ed, for displays that only update on demand (SPI panels, USB adapters). A
+  /// no-op for the others.
+  #[cfg(feature = "embedded-graphics")]
+  pub fn flush(&mut self, damage: Rectangle) {
+    let area = damage.intersection(&Rectangle::new(
+      Default::default(),
+      (self.width(), self.height()).into(),
+    ));
+    let bottom_right = match area.bottom_right() {
+      Some(bottom_right) => bottom_right,
+      None => return,
+    };
+    let clip = drm::RawDRMClipRect {
+      x1: area.top_left.x as u16,
+      y1: area.top_left.y as u16,
+      x2: bottom_right.x as u16 + 1,
+      y2: bottom_right.y as u16 + 1,
+    };
+    if drm::mode_dirty_fb(self.card.file(), self.buffers[self.front].fb, &[clip]) != 0 {
+      let error = io::Error::last_os_error();
+      if error.raw_os_error() != Some(libc::ENOSYS) {
+        println!("Error flushing framebuffer: {}", error);
+      }
+    }
+  }
+
+  /// Whether frames reach the screen. Stays `true` unless the session backend paused us.
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+
+  fn dispatch_session(&mut self) {
+    self.active = self.session.dispatch();
+  }
+
+  /// See `Context::take_session_events`.
+  pub fn take_session_events(&mut self) -> Vec<SessionEvent> {
+    self.session.take_events()
+  }
+
+  pub fn session(&self) -> &dyn SessionBackend {
+    self.session.backend()
+  }
+
+  /// For opening input devices through the same seat.
+  pub fn session_mut(&mut self) -> &mut dyn SessionBackend {
+    self.session.backend_mut()
+  }
+
+  fn handle_events(&mut self, block: bool) -> Option<PresentationFeedback> {
+    if !drm::poll_events(self.card.file(), if block { -1 } else { 0 })
+      .expect("Couldn't poll device")
+    {
+      return None;
+    }
+
+    let mut feedback = None;
+    for event in drm::read_events(self.card.file()).expect("Couldn't read DRM events") {
+      if let drm::DRMEvent::FlipComplete(vblank) = event {
+        self.flip_pending = false;
+        feedback = Some(self.presented(
+          vblank.sequence,
+          Duration::new(vblank.tv_sec as u64, vblank.tv_usec * 1000),
+        ));
+      }
+    }
+    feedback
+  }
+
+  // Feedback for the last frame submitted
+  fn presented(&mut self, sequence: u32, timestamp: Duration) -> PresentationFeedback {
+    let refresh = self.mode.refresh_interval();
+    self
+      .tracker
+      .presented(self.frame_count - 1, sequence, timestamp, refresh)
+  }
+
+  /// Puts the canvas on screen and blocks until it's there, the old front buffer becomes the
+  /// canvas. `None` while the session is paused, or when the flip failed (the frame doesn't
+  /// count then).
+  pub fn present(&mut self) -> Option<PresentationFeedback> {
+    self.dispatch_session();
+    if !self.active {
+      self.needs_modeset = true;
+      return None;
+    }
+
+    let back = 1 - self.front;
+
+    if self.needs_modeset {
+      drm::mode_set_crtc(
+        self.card.file(),
+        self.crtc.crtc_id,
+        self.buffers[back].fb,
+        0,
+        0,
+        &[self.connector_id],
+        Some(&self.mode.raw),
+      );
+      self.front = back;
+      self.needs_modeset = false;
+      self.frame_count += 1;
+
+      return match output::last_vblank(self.card.file(), self.pipe) {
+        Ok((sequence, timestamp)) => Some(self.presented(sequence, timestamp)),
+        Err(error) => {
+          println!("Error reading vblank counter: {}", error);
+          self.tracker.reset();
+          None
+        }
+      };
+    }
+
+    let ret = drm::mode_page_flip(
+      self.card.file(),
+      self.crtc.crtc_id,
+      self.buffers[back].fb,
+      drm::DRM_MODE_PAGE_FLIP_EVENT,
+      0,
+    );
+    if ret != 0 {
+      println!("Error flipping page: {}", io::Error::last_os_error());
+      return None;
+    }
+    self.flip_pending = true;
+    self.front = back;
+    self.frame_count += 1;
+
+    // The old front buffer is scanned out until the flip lands
+    let mut feedback = None;
+    while self.flip_pending {
+      feedback = self.handle_events(true).or(feedback);
+    }
+    feedback
+  }
+}
+
+/// Readable when a page flip completed.
+impl AsRawFd for SoftwareContext {
+  fn as_raw_fd(&self) -> RawFd {
+    self.card.as_raw_fd()
+  }
+}
+
+impl AsFd for SoftwareContext {
+  fn as_fd(&self) -> BorrowedFd<'_> {
+    self.card.as_fd()
+  }
+}
+
+impl Drop for SoftwareContext {
+  fn drop(&mut self) {
+    while self.flip_pending && self.active {
+      self.handle_events(true);
+    }
+
+    drm::mode_set_crtc(
+      self.card.file(),
+      self.crtc.crtc_id,
+      self.crtc.buffer_id,
+      self.crtc.x,
+      self.crtc.y,
+      &[self.connector_id],
+      Some(&self.crtc.mode.raw),
+    );
+
+    for buffer in self.buffers.iter_mut() {
+      buffer.destroy(self.card.file());
+    }
+
+    self.session.close_device(&self.path, self.card.file());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // The card driven by vkms, when the module is loaded
+  fn vkms_card() -> Option<PathBuf> {
+    std::fs::read_dir("/sys/class/drm")
+      .ok()?
+      .flatten()
+      .find_map(|entry| {
+        let name = entry.file_name().into_string().ok()?;
+        let driver = std::fs::read_link(entry.path().join("device/driver")).ok()?;
+        if !name.starts_with("card") || name.contains('-') || !driver.ends_with("vkms") {
+          return None;
+        }
+        Some(Path::new("/dev/dri").join(name))
+      })
+  }
+
+  #[test]
+  fn presents_on_vkms() {
+    let path = match vkms_card() {
+      Some(path) => path,
+      None => return println!("No vkms card, skipped"),
+    };
+    let mut context = SoftwareContext::with_card(&path, Box::new(DirectSession));
+    assert!(context.stride() >= context.width() * 4);
+
+    context.canvas().fill(0xff);
+    let first = context.present().expect("No feedback for the modeset");
+    context.canvas().fill(0x00);
+    let second = context.present().expect("No feedback for the flip");
+
+    assert_eq!((first.frame, second.frame), (0, 1));
+    assert!(second.sequence.wrapping_sub(first.sequence) >= 1);
+    assert!(second.timestamp > first.timestamp);
+    assert_eq!(second.refresh, Some(context.mode.refresh_interval()));
+  }
+}
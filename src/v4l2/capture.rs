@@ -0,0 +1,359 @@
+#![allow(dead_code)]
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
+
+use super::mini_v4l2 as v4l2;
+use crate::egl_image::{DmabufDesc, DmabufPlane, GLuint, ImportedImage};
+use crate::gbm::gbm_formats::*;
+use crate::gbm::mini_gbm::DRM_FORMAT_MOD_INVALID;
+use crate::Context;
+
+// V4L2 formats EGL can import and their DRM fourccs, which aren't always the same (RGB565 is
+// 'RGBP' in V4L2, 'RG16' in DRM). Preferred first: the YUV ones are what cameras produce
+// natively and are cheapest to sample.
+#[rustfmt::skip]
+const FORMATS: [(u32, u32); 9] = [
+  (v4l2::V4L2_PIX_FMT_NV12,   GBM_FORMAT_NV12),
+  (v4l2::V4L2_PIX_FMT_YUV420, GBM_FORMAT_YUV420),
+  (v4l2::V4L2_PIX_FMT_YVU420, GBM_FORMAT_YVU420),
+  (v4l2::V4L2_PIX_FMT_YUYV,   GBM_FORMAT_YUYV),
+  (v4l2::V4L2_PIX_FMT_UYVY,   GBM_FORMAT_UYVY),
+  (v4l2::V4L2_PIX_FMT_XBGR32, GBM_FORMAT_XRGB8888),
+  (v4l2::V4L2_PIX_FMT_BGR32,  GBM_FORMAT_XRGB8888),
+  (v4l2::V4L2_PIX_FMT_ABGR32, GBM_FORMAT_ARGB8888),
+  (v4l2::V4L2_PIX_FMT_RGB565, GBM_FORMAT_RGB565),
+];
+
+// The DRM fourcc of a V4L2 pixel format, `None` for formats EGL can't import
+fn drm_format(v4l2_fourcc: u32) -> Option<u32> {
+  FORMATS
+    .iter()
+    .find(|(v4l2, _)| *v4l2 == v4l2_fourcc)
+    .map(|(_, drm)| *drm)
+}
+
+const BUFFER_COUNT: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CaptureFormat {
+  pub width: u32,
+  pub height: u32,
+  /// One of the `GBM_FORMAT_*` fourccs
+  pub fourcc: u32,
+  /// Of the first plane, the others are derived from it
+  pub stride: u32,
+}
+
+impl CaptureFormat {
+  // Single-planar V4L2 formats keep their planes back to back in one buffer
+  fn planes(&self, fd: RawFd) -> Vec<DmabufPlane> {
+    let plane = |offset: u32, stride: u32| DmabufPlane { fd, offset, stride };
+    let luma_size = self.stride * self.height;
+
+    match self.fourcc {
+      GBM_FORMAT_NV12 => vec![plane(0, self.stride), plane(luma_size, self.stride)],
+      GBM_FORMAT_YUV420 | GBM_FORMAT_YVU420 => {
+        let chroma_stride = self.stride / 2;
+        let chroma_size = chroma_stride * (self.height / 2);
+        vec![
+          plane(0, self.stride),
+          plane(luma_size, chroma_stride),
+          plane(luma_size + chroma_size, chroma_stride),
+        ]
+      }
+      _ => vec![plane(0, self.stride)],
+    }
+  }
+}
+
+// The V4L2 side: a streaming device and its buffers, exported as dmabufs
+struct Stream {
+  device: File,
+  format: CaptureFormat,
+  dmabufs: Vec<File>,
+}
+
+impl Stream {
+  fn open(path: &Path, width: u32, height: u32) -> io::Result<Stream> {
+    let device = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .custom_flags(libc::O_NONBLOCK | libc::O_CLOEXEC)
+      .open(path)?;
+
+    let capability = v4l2::query_capabilities(&device)?;
+    let capabilities = if capability.capabilities & v4l2::V4L2_CAP_DEVICE_CAPS != 0 {
+      capability.device_caps
+    } else {
+      capability.capabilities
+    };
+    if capabilities & v4l2::V4L2_CAP_VIDEO_CAPTURE == 0
+      || capabilities & v4l2::V4L2_CAP_STREAMING == 0
+    {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Not a single-planar streaming capture device",
+      ));
+    }
+
+    let supported = v4l2::enum_formats(&device)?;
+    let (pixelformat, fourcc) = *FORMATS
+      .iter()
+      .find(|(v4l2, _)| supported.contains(v4l2))
+      .ok_or_else(|| {
+        io::Error::new(
+          io::ErrorKind::Unsupported,
+          "The device has no format EGL can import",
+        )
+      })?;
+
+    let pix = v4l2::set_format(
+      &device,
+      v4l2::RawV4L2PixFormat {
+        width,
+        height,
+        pixelformat,
+        field: v4l2::V4L2_FIELD_NONE,
+        ..Default::default()
+      },
+    )?;
+    if pix.pixelformat != pixelformat {
+      return Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "The driver didn't accept the pixel format",
+      ));
+    }
+    let format = CaptureFormat {
+      width: pix.width,
+      height: pix.height,
+      fourcc,
+      stride: pix.bytesperline,
+    };
+
+    let count = v4l2::request_buffers(&device, BUFFER_COUNT)?;
+    let mut dmabufs = Vec::with_capacity(count as usize);
+    for index in 0..count {
+      dmabufs.push(v4l2::export_buffer(&device, index)?);
+      v4l2::queue_buffer(&device, index)?;
+    }
+    v4l2::stream(&device, true)?;
+
+    Ok(Stream {
+      device,
+      format,
+      dmabufs,
+    })
+  }
+
+  // The newest captured buffer, older ones go straight back to the driver
+  fn dequeue_newest(&self) -> io::Result<Option<v4l2::RawV4L2Buffer>> {
+    let mut newest = None;
+    while let Some(buffer) = v4l2::dequeue_buffer(&self.device)? {
+      if let Some(skipped) = newest.replace(buffer) {
+        v4l2::queue_buffer(&self.device, skipped.index)?;
+      }
+    }
+    Ok(newest)
+  }
+}
+
+impl Drop for Stream {
+  fn drop(&mut self) {
+    if let Err(error) = v4l2::stream(&self.device, false) {
+      println!("Error stopping capture: {}", error);
+    }
+    // The dmabufs have to go before the driver can free its buffers
+    self.dmabufs.clear();
+    if let Err(error) = v4l2::request_buffers(&self.device, 0) {
+      println!("Error freeing capture buffers: {}", error);
+    }
+  }
+}
+
+/// A frame fresh from the device, as a `GL_TEXTURE_EXTERNAL_OES` texture. It stays valid until
+/// the next frame is dequeued.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CapturedFrame {
+  pub texture: GLuint,
+  pub width: u32,
+  pub height: u32,
+  /// Driver frame counter, gaps mean dropped frames
+  pub sequence: u32,
+  /// Capture time, `CLOCK_MONOTONIC` for most drivers
+  pub timestamp: Duration,
+}
+
+/// Streams frames from a V4L2 capture device (`/dev/videoN`) straight into GL textures, no copies:
+/// the kernel's buffers are exported as dmabufs and imported through EGL once, then recycled.
+/// Try it with the virtual `vivid` driver (`modprobe vivid`).
+pub struct Capture<'ctx> {
+  context: &'ctx Context,
+  // Imported buffers, dropped before the stream frees them
+  images: Vec<Option<ImportedImage<'ctx>>>,
+  stream: Stream,
+  // Dequeued, sampled by GL until the next one comes in
+  current: Option<u32>,
+}
+
+impl<'ctx> Capture<'ctx> {
+  /// Opens and starts streaming into textures of `context`. The driver may pick a different size
+  /// than asked for, see `format`.
+  pub fn open<P: AsRef<Path>>(
+    context: &'ctx Context,
+    path: P,
+    width: u32,
+    height: u32,
+  ) -> io::Result<Capture<'ctx>> {
+    let stream = Stream::open(path.as_ref(), width, height)?;
+
+    Ok(Capture {
+      context,
+      images: stream.dmabufs.iter().map(|_| None).collect(),
+      stream,
+      current: None,
+    })
+  }
+
+  pub fn format(&self) -> CaptureFormat {
+    self.stream.format
+  }
+
+  /// Takes the newest captured frame, skipping older ones, and hands the previous frame back to
+  /// the driver. `None` when nothing new came in, never blocks (wait for the fd to be readable).
+  /// The GL context must be current.
+  pub fn dequeue(&mut self) -> io::Result<Option<CapturedFrame>> {
+    let buffer = match self.stream.dequeue_newest()? {
+      Some(buffer) => buffer,
+      None => return Ok(None),
+    };
+
+    if let Some(previous) = self.current.replace(buffer.index) {
+      v4l2::queue_buffer(&self.stream.device, previous)?;
+    }
+
+    let format = self.stream.format;
+    let index = buffer.index as usize;
+    if self.images[index].is_none() {
+      // Buffers never move, one import each is enough
+      self.images[index] = Some(self.context.import_dmabuf(DmabufDesc {
+        width: format.width,
+        height: format.height,
+        fourcc: format.fourcc,
+        modifier: DRM_FORMAT_MOD_INVALID,
+        planes: format.planes(self.stream.dmabufs[index].as_raw_fd()),
+      })?);
+    }
+
+    Ok(Some(CapturedFrame {
+      texture: self.images[index].as_ref().unwrap().texture(),
+      width: format.width,
+      height: format.height,
+      sequence: buffer.sequence,
+      timestamp: buffer.time(),
+    }))
+  }
+}
+
+/// Readable when a frame was captured.
+impl AsRawFd for Capture<'_> {
+  fn as_raw_fd(&self) -> RawFd {
+    self.stream.device.as_raw_fd()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::fs;
+  use std::thread;
+
+  #[test]
+  fn maps_v4l2_formats_to_drm() {
+    assert_eq!(drm_format(v4l2::V4L2_PIX_FMT_NV12), Some(GBM_FORMAT_NV12));
+    assert_eq!(
+      drm_format(v4l2::V4L2_PIX_FMT_YUV420),
+      Some(GBM_FORMAT_YUV420)
+    );
+    assert_eq!(
+      drm_format(v4l2::V4L2_PIX_FMT_RGB565),
+      Some(GBM_FORMAT_RGB565)
+    );
+    assert_eq!(
+      drm_format(v4l2::V4L2_PIX_FMT_XBGR32),
+      Some(GBM_FORMAT_XRGB8888)
+    );
+    assert_eq!(
+      drm_format(v4l2::V4L2_PIX_FMT_ABGR32),
+      Some(GBM_FORMAT_ARGB8888)
+    );
+    // 'RG16' is the DRM name, V4L2 calls it 'RGBP'
+    assert_eq!(drm_format(GBM_FORMAT_RGB565), None);
+  }
+
+  #[test]
+  fn plane_layouts() {
+    let format = CaptureFormat {
+      width: 640,
+      height: 480,
+      fourcc: GBM_FORMAT_YUV420,
+      stride: 640,
+    };
+    let planes = format.planes(3);
+    let offsets: Vec<u32> = planes.iter().map(|plane| plane.offset).collect();
+    let strides: Vec<u32> = planes.iter().map(|plane| plane.stride).collect();
+    assert_eq!(offsets, vec![0, 640 * 480, 640 * 480 + 320 * 240]);
+    assert_eq!(strides, vec![640, 320, 320]);
+  }
+
+  // The first device of the `vivid` driver, if loaded (`modprobe vivid`)
+  fn vivid_device() -> Option<std::path::PathBuf> {
+    let mut paths: Vec<_> = fs::read_dir("/dev")
+      .ok()?
+      .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+      .filter(|path| {
+        path
+          .file_name()
+          .and_then(|name| name.to_str())
+          .is_some_and(|name| name.starts_with("video"))
+      })
+      .collect();
+    paths.sort();
+    paths.into_iter().find(|path| {
+      File::open(path)
+        .and_then(|device| v4l2::query_capabilities(&device))
+        .is_ok_and(|capability| capability.driver.starts_with(b"vivid"))
+    })
+  }
+
+  #[test]
+  fn streams_from_vivid() {
+    let path = match vivid_device() {
+      Some(path) => path,
+      None => {
+        eprintln!("vivid isn't loaded, skipping");
+        return;
+      }
+    };
+
+    let stream = Stream::open(&path, 640, 480).expect("Couldn't open vivid");
+    assert!(FORMATS.iter().any(|(_, drm)| *drm == stream.format.fourcc));
+    assert!(!stream.dmabufs.is_empty());
+
+    let mut buffer = None;
+    for _ in 0..100 {
+      buffer = stream.dequeue_newest().expect("Couldn't dequeue");
+      if buffer.is_some() {
+        break;
+      }
+      thread::sleep(Duration::from_millis(20));
+    }
+    let buffer = buffer.expect("vivid didn't capture anything");
+    assert!((buffer.index as usize) < stream.dmabufs.len());
+    assert!(buffer.bytesused > 0);
+  }
+}
@@ -0,0 +1,283 @@
+#![allow(dead_code)]
+
+// Kernel V4L2 interface, from <linux/videodev2.h>. Single-planar capture with kernel allocated
+// (MMAP) buffers only.
+
+use std::fs::File;
+use std::io;
+use std::mem::{self, size_of};
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::time::Duration;
+
+use crate::ioctl::{ior, iow, iowr};
+
+pub const V4L2_BUF_TYPE_VIDEO_CAPTURE: u32 = 1;
+pub const V4L2_MEMORY_MMAP: u32 = 1;
+pub const V4L2_FIELD_NONE: u32 = 1;
+
+const fn v4l2_fourcc(a: u8, b: u8, c: u8, d: u8) -> u32 {
+  (a as u32) | ((b as u32) << 8) | ((c as u32) << 16) | ((d as u32) << 24)
+}
+
+// Named after the byte order in memory, unlike DRM fourccs which describe a little endian word
+pub const V4L2_PIX_FMT_NV12: u32 = v4l2_fourcc(b'N', b'V', b'1', b'2');
+pub const V4L2_PIX_FMT_YUV420: u32 = v4l2_fourcc(b'Y', b'U', b'1', b'2');
+pub const V4L2_PIX_FMT_YVU420: u32 = v4l2_fourcc(b'Y', b'V', b'1', b'2');
+pub const V4L2_PIX_FMT_YUYV: u32 = v4l2_fourcc(b'Y', b'U', b'Y', b'V');
+pub const V4L2_PIX_FMT_UYVY: u32 = v4l2_fourcc(b'U', b'Y', b'V', b'Y');
+pub const V4L2_PIX_FMT_XBGR32: u32 = v4l2_fourcc(b'X', b'R', b'2', b'4');
+pub const V4L2_PIX_FMT_ABGR32: u32 = v4l2_fourcc(b'A', b'R', b'2', b'4');
+pub const V4L2_PIX_FMT_BGR32: u32 = v4l2_fourcc(b'B', b'G', b'R', b'4');
+pub const V4L2_PIX_FMT_RGB565: u32 = v4l2_fourcc(b'R', b'G', b'B', b'P');
+
+pub const V4L2_CAP_VIDEO_CAPTURE: u32 = 0x00000001;
+pub const V4L2_CAP_STREAMING: u32 = 0x04000000;
+pub const V4L2_CAP_DEVICE_CAPS: u32 = 0x80000000;
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawV4L2Capability {
+  pub driver: [u8; 16],
+  pub card: [u8; 32],
+  pub bus_info: [u8; 32],
+  pub version: u32,
+  pub capabilities: u32,
+  pub device_caps: u32,
+  pub reserved: [u32; 3],
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawV4L2FmtDesc {
+  pub index: u32,
+  pub r#type: u32,
+  pub flags: u32,
+  pub description: [u8; 32],
+  pub pixelformat: u32,
+  pub mbus_code: u32,
+  pub reserved: [u32; 3],
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawV4L2PixFormat {
+  pub width: u32,
+  pub height: u32,
+  pub pixelformat: u32,
+  pub field: u32,
+  pub bytesperline: u32,
+  pub sizeimage: u32,
+  pub colorspace: u32,
+  pub r#priv: u32,
+  pub flags: u32,
+  pub ycbcr_enc: u32,
+  pub quantization: u32,
+  pub xfer_func: u32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union RawV4L2FormatUnion {
+  pub pix: RawV4L2PixFormat,
+  pub raw_data: [u8; 200],
+  // struct v4l2_window holds pointers, which sets the alignment
+  _align: [*mut c_void; 0],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawV4L2Format {
+  pub r#type: u32,
+  pub fmt: RawV4L2FormatUnion,
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawV4L2RequestBuffers {
+  pub count: u32,
+  pub r#type: u32,
+  pub memory: u32,
+  pub capabilities: u32,
+  pub flags: u8,
+  pub reserved: [u8; 3],
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawV4L2Timecode {
+  pub r#type: u32,
+  pub flags: u32,
+  pub frames: u8,
+  pub seconds: u8,
+  pub minutes: u8,
+  pub hours: u8,
+  pub userbits: [u8; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union RawV4L2BufferM {
+  pub offset: u32,
+  pub userptr: c_ulong,
+  pub planes: *mut c_void,
+  pub fd: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawV4L2Buffer {
+  pub index: u32,
+  pub r#type: u32,
+  pub bytesused: u32,
+  pub flags: u32,
+  pub field: u32,
+  pub timestamp: libc::timeval,
+  pub timecode: RawV4L2Timecode,
+  pub sequence: u32,
+  pub memory: u32,
+  pub m: RawV4L2BufferM,
+  pub length: u32,
+  pub reserved2: u32,
+  pub request_fd: i32,
+}
+
+impl RawV4L2Buffer {
+  pub fn time(&self) -> Duration {
+    Duration::new(
+      self.timestamp.tv_sec as u64,
+      (self.timestamp.tv_usec as u32) * 1000,
+    )
+  }
+}
+
+#[warn(improper_ctypes)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RawV4L2ExportBuffer {
+  pub r#type: u32,
+  pub index: u32,
+  pub plane: u32,
+  pub flags: u32,
+  pub fd: i32,
+  pub reserved: [u32; 11],
+}
+
+pub const VIDIOC_QUERYCAP: c_ulong = ior(b'V', 0, size_of::<RawV4L2Capability>());
+pub const VIDIOC_ENUM_FMT: c_ulong = iowr(b'V', 2, size_of::<RawV4L2FmtDesc>());
+pub const VIDIOC_G_FMT: c_ulong = iowr(b'V', 4, size_of::<RawV4L2Format>());
+pub const VIDIOC_S_FMT: c_ulong = iowr(b'V', 5, size_of::<RawV4L2Format>());
+pub const VIDIOC_REQBUFS: c_ulong = iowr(b'V', 8, size_of::<RawV4L2RequestBuffers>());
+pub const VIDIOC_QUERYBUF: c_ulong = iowr(b'V', 9, size_of::<RawV4L2Buffer>());
+pub const VIDIOC_QBUF: c_ulong = iowr(b'V', 15, size_of::<RawV4L2Buffer>());
+pub const VIDIOC_EXPBUF: c_ulong = iowr(b'V', 16, size_of::<RawV4L2ExportBuffer>());
+pub const VIDIOC_DQBUF: c_ulong = iowr(b'V', 17, size_of::<RawV4L2Buffer>());
+pub const VIDIOC_STREAMON: c_ulong = iow(b'V', 18, size_of::<c_int>());
+pub const VIDIOC_STREAMOFF: c_ulong = iow(b'V', 19, size_of::<c_int>());
+
+// Retries when interrupted, like libv4l2's xioctl
+fn xioctl<T>(device: &File, request: c_ulong, arg: &mut T) -> io::Result<()> {
+  loop {
+    if unsafe { libc::ioctl(device.as_raw_fd(), request as _, arg as *mut T) } >= 0 {
+      return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    if error.kind() != io::ErrorKind::Interrupted {
+      return Err(error);
+    }
+  }
+}
+
+pub fn query_capabilities(device: &File) -> io::Result<RawV4L2Capability> {
+  let mut capability: RawV4L2Capability = unsafe { mem::zeroed() };
+  xioctl(device, VIDIOC_QUERYCAP, &mut capability)?;
+  Ok(capability)
+}
+
+/// Pixel formats (fourccs) the device can capture to, in the driver's order of preference.
+pub fn enum_formats(device: &File) -> io::Result<Vec<u32>> {
+  let mut formats = Vec::new();
+  for index in 0.. {
+    let mut desc: RawV4L2FmtDesc = unsafe { mem::zeroed() };
+    desc.index = index;
+    desc.r#type = V4L2_BUF_TYPE_VIDEO_CAPTURE;
+    match xioctl(device, VIDIOC_ENUM_FMT, &mut desc) {
+      Ok(()) => formats.push(desc.pixelformat),
+      Err(ref error) if error.raw_os_error() == Some(libc::EINVAL) => break,
+      Err(error) => return Err(error),
+    }
+  }
+  Ok(formats)
+}
+
+/// Asks for a format, returns what the driver settled on (may differ in size or stride).
+pub fn set_format(device: &File, pix: RawV4L2PixFormat) -> io::Result<RawV4L2PixFormat> {
+  let mut format: RawV4L2Format = unsafe { mem::zeroed() };
+  format.r#type = V4L2_BUF_TYPE_VIDEO_CAPTURE;
+  format.fmt.pix = pix;
+  xioctl(device, VIDIOC_S_FMT, &mut format)?;
+  Ok(unsafe { format.fmt.pix })
+}
+
+/// Returns how many buffers were actually allocated, `count` 0 frees them.
+pub fn request_buffers(device: &File, count: u32) -> io::Result<u32> {
+  let mut request = RawV4L2RequestBuffers {
+    count,
+    r#type: V4L2_BUF_TYPE_VIDEO_CAPTURE,
+    memory: V4L2_MEMORY_MMAP,
+    ..Default::default()
+  };
+  xioctl(device, VIDIOC_REQBUFS, &mut request)?;
+  Ok(request.count)
+}
+
+pub fn export_buffer(device: &File, index: u32) -> io::Result<File> {
+  let mut export = RawV4L2ExportBuffer {
+    r#type: V4L2_BUF_TYPE_VIDEO_CAPTURE,
+    index,
+    flags: (libc::O_RDONLY | libc::O_CLOEXEC) as u32,
+    ..Default::default()
+  };
+  xioctl(device, VIDIOC_EXPBUF, &mut export)?;
+  Ok(unsafe { File::from_raw_fd(export.fd) })
+}
+
+fn buffer(index: u32) -> RawV4L2Buffer {
+  let mut buffer: RawV4L2Buffer = unsafe { mem::zeroed() };
+  buffer.index = index;
+  buffer.r#type = V4L2_BUF_TYPE_VIDEO_CAPTURE;
+  buffer.memory = V4L2_MEMORY_MMAP;
+  buffer
+}
+
+pub fn queue_buffer(device: &File, index: u32) -> io::Result<()> {
+  xioctl(device, VIDIOC_QBUF, &mut buffer(index))
+}
+
+/// `None` when no frame is ready and the device was opened non-blocking.
+pub fn dequeue_buffer(device: &File) -> io::Result<Option<RawV4L2Buffer>> {
+  let mut dequeued = buffer(0);
+  match xioctl(device, VIDIOC_DQBUF, &mut dequeued) {
+    Ok(()) => Ok(Some(dequeued)),
+    Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => Ok(None),
+    Err(error) => Err(error),
+  }
+}
+
+pub fn stream(device: &File, on: bool) -> io::Result<()> {
+  let mut r#type = V4L2_BUF_TYPE_VIDEO_CAPTURE as c_int;
+  xioctl(
+    device,
+    if on {
+      VIDIOC_STREAMON
+    } else {
+      VIDIOC_STREAMOFF
+    },
+    &mut r#type,
+  )
+}
@@ -2,24 +2,25 @@
 // https://github.com/seankerr/rust-rpi-examples/blob/master/opengles/hello_opengl/src/main.rs
 // https://jan.newmarch.name/RPi/EGL/
 
-extern crate egl;
 extern crate videocore;
 
 use std::ptr;
 
-use egl::{EGLConfig, EGLContext, EGLDisplay, EGLNativeDisplayType, EGLSurface};
-
 // use crate::gbm_formats;
 use dispmanx::DisplayHandle;
 
-use videocore::bcm_host;
-use videocore::dispmanx;
 #[allow(unused_imports)]
 use videocore::dispmanx::{FlagsAlpha, Transform, VCAlpha, Window};
 use videocore::image::Rect;
 
-#[link(name = "EGL")]
-#[link(name = "GLESv2")]
+use crate::bcm_host::{self, dispmanx};
+use crate::egl_ffi as egl;
+use crate::egl_ffi::{EGLConfig, EGLContext, EGLDisplay, EGLNativeDisplayType, EGLSurface};
+use crate::egl_utils;
+use crate::presentation::{monotonic_now, PresentMode, PresentationFeedback};
+
+#[cfg_attr(not(feature = "dlopen"), link(name = "EGL"))]
+#[cfg_attr(not(feature = "dlopen"), link(name = "GLESv2"))]
 extern "C" {}
 
 #[rustfmt::skip]
@@ -117,6 +118,9 @@ fn init_egl() -> (
   i32, /* egl major */
   i32, /* egl minor */
 ) {
+  #[cfg(feature = "dlopen")]
+  crate::loader::probe().unwrap_or_else(|error| panic!("{}", error));
+
   bcm_host::init();
 
   let egl_display = egl::get_display(egl::EGL_DEFAULT_DISPLAY)
@@ -267,9 +271,13 @@ pub struct Context {
   window: Window,
   width: u32,
   height: u32,
+  present_mode: PresentMode,
+  frame_count: u64,
 }
 
 impl Context {
+  /// Panics if EGL or dispmanx can't be set up, or with `dlopen` when a library or symbol is
+  /// missing.
   pub fn new() -> Self {
     let device = 0u16; /* LCD */
 
@@ -290,6 +298,8 @@ impl Context {
       window,
       width,
       height,
+      present_mode: PresentMode::Fifo,
+      frame_count: 0,
     };
 
     context.egl_surface =
@@ -298,10 +308,25 @@ impl Context {
     return context;
   }
 
+  /// `new`, but a missing library or symbol is an error instead of a panic.
+  #[cfg(feature = "dlopen")]
+  pub fn try_new() -> Result<Self, crate::loader::Error> {
+    crate::loader::probe()?;
+    return Ok(Self::new());
+  }
+
   pub fn egl_version(&self) -> (i32, i32) {
     (self.egl_major, self.egl_minor)
   }
 
+  /// Every config of the EGL display.
+  pub fn configs(&self) -> Vec<egl_utils::EglConfigInfo> {
+    egl_utils::get_configs(self.egl_display)
+      .into_iter()
+      .map(|config| egl_utils::EglConfigInfo::query(self.egl_display, config))
+      .collect()
+  }
+
   #[inline(always)]
   pub fn width(&self) -> u32 {
     self.width
@@ -312,8 +337,43 @@ impl Context {
     self.height
   }
 
-  pub fn swap_buffers(&mut self) {
+  pub fn present_mode(&self) -> PresentMode {
+    self.present_mode
+  }
+
+  /// Maps to `eglSwapInterval`: 1 for Fifo, 0 otherwise. Dispmanx has no mailbox, the closest is
+  /// not waiting for vblank at all.
+  pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+    let interval = match present_mode {
+      PresentMode::Fifo => 1,
+      PresentMode::Immediate | PresentMode::Mailbox => 0,
+    };
+    if !egl_utils::swap_interval(self.egl_display, interval) {
+      println!("Error setting swap interval\n\n{}", egl_get_error_str());
+    }
+    self.present_mode = present_mode;
+  }
+
+  /// Dispmanx doesn't report vblanks: the timestamp is taken when the swap returns and the
+  /// frame counter stands in for the vblank sequence.
+  pub fn swap_buffers(&mut self) -> Option<PresentationFeedback> {
     egl::swap_buffers(self.egl_display, self.egl_surface);
+
+    let frame = self.frame_count;
+    self.frame_count += 1;
+
+    Some(PresentationFeedback {
+      frame,
+      sequence: frame as u32,
+      timestamp: monotonic_now(),
+      refresh: None,
+      missed_deadline: false,
+    })
+  }
+
+  /// Everything is reported by `swap_buffers` on Dispmanx.
+  pub fn take_feedback(&mut self) -> Vec<PresentationFeedback> {
+    Vec::new()
   }
 }
 
@@ -1,12 +1,33 @@
-use egl;
+use std::collections::HashMap;
 use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
 use std::os::raw::c_void;
-use std::ptr;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::Path;
+use std::time::Duration;
 
+#[cfg(feature = "embedded-graphics")]
+use crate::canvas::{LinearBuffer, PixelFormat};
+use crate::dmabuf::{self, DmabufFrame, OffscreenTarget, RenderBuffer, ScanoutBuffer};
+use crate::drm::card::Card;
+use crate::drm::drm_ioctl;
 use crate::drm::mini_drm as drm;
-use crate::egl_utils::{choose_config, match_config_to_visual};
+use crate::drm::mini_drm::DRMFramebufferLayout;
+use crate::drm::output::{self, Output};
+use crate::egl_device::EglDevice;
+use crate::egl_ffi as egl;
+use crate::egl_image::{DmabufDesc, ImportedImage};
+use crate::egl_platform::{self, DisplayPlatform, PlatformDisplay};
+use crate::egl_utils::{self, choose_config, select_config, ConfigRequest, EglConfigInfo};
+use crate::gbm::gbm_device::{FrontBuffer, GbmDevice, GbmSurface};
 use crate::gbm::mini_gbm as gbm;
-use crate::gbm::mini_gbm::gbm_bo_flags;
+use crate::gbm::mini_gbm::{gbm_bo_flags, DRM_FORMAT_MOD_INVALID};
+use crate::presentation::{FeedbackTracker, PresentMode, PresentationFeedback, VblankInfo};
+use crate::prime::{self, Prime, PrimeMode, RenderDevice};
+use crate::restore::RestoreGuard;
+use crate::session::direct::DirectSession;
+use crate::session::session_backend::{Session, SessionBackend, SessionEvent};
 
 #[rustfmt::skip]
 const ATTRIBUTES: [egl::EGLint; 13] = [
@@ -27,82 +48,279 @@ const CONTEXT_ATTRIBS: [egl::EGLint; 3] = [
 
 const GBM_FORMAT: u32 = gbm::GBM_FORMAT_XRGB8888;
 
-#[link(name = "drm")]
-#[link(name = "gbm")]
-#[link(name = "EGL")]
-#[link(name = "GLESv2")]
+pub(crate) const CARD_PATH: &str = "/dev/dri/by-path/platform-gpu-card";
+
+// Feedback kept for `take_feedback`, the oldest goes first when nobody drains it
+const MAX_FEEDBACK: usize = 64;
+
+#[cfg_attr(not(feature = "pure-ioctl"), link(name = "drm"))]
+#[cfg_attr(not(feature = "dlopen"), link(name = "gbm"))]
+#[cfg_attr(not(feature = "dlopen"), link(name = "EGL"))]
+#[cfg_attr(not(feature = "dlopen"), link(name = "GLESv2"))]
 extern "C" {}
 
-pub fn open_card(path: &str) -> File {
-  return std::fs::OpenOptions::new()
-    .read(true)
-    .write(true)
-    .open(path)
-    .expect("Couldn't open device");
+// Config matching the GBM format and a window surface with it
+fn window_surface(
+  display: &PlatformDisplay,
+  gbm_surface: *mut c_void,
+) -> Option<(egl::EGLConfig, egl::EGLSurface)> {
+  if !egl::bind_api(egl::EGL_OPENGL_ES_API) {
+    return None;
+  }
+
+  let configs = choose_config(display.display, &ATTRIBUTES)?;
+  let request = ConfigRequest {
+    native_visual_id: Some(GBM_FORMAT as i32),
+    ..ConfigRequest::default()
+  };
+  let config = select_config(display.display, configs, &request)?;
+  let surface = egl_platform::create_window_surface(display, config, gbm_surface)?;
+
+  Some((config, surface))
+}
+
+// A locked front buffer and the framebuffer wrapping it. No `buffer` for imported buffers
+// (`ScanoutBuffer`), their framebuffer belongs to the caller.
+#[derive(Debug)]
+struct Frame {
+  buffer: Option<FrontBuffer<'static>>,
+  fb: u32,
+  number: u64,
+  // GEM handles of a buffer imported from the render node (PRIME)
+  handles: Vec<u32>,
+  // The framebuffer is a copy owned by `Prime`
+  copied: bool,
+}
+
+// GBM and EGL set up for rendering, on the card or on a render node
+struct Renderer {
+  display: PlatformDisplay,
+  egl_device: Option<EglDevice>,
+  config: egl::EGLConfig,
+  surface: egl::EGLSurface,
+  gbm_surface: GbmSurface<'static>,
+  gbm_device: GbmDevice<'static>,
+  // The render node the device is on, `None` on the card
+  node: Option<File>,
+}
+
+impl Renderer {
+  fn on_card(card: &File, width: u32, height: u32) -> Option<Renderer> {
+    let flags = gbm_bo_flags::GBM_BO_USE_SCANOUT | gbm_bo_flags::GBM_BO_USE_RENDERING;
+    let egl_device = EglDevice::for_file(card);
+    Renderer::new(card.as_raw_fd(), None, egl_device, flags, width, height)
+  }
+
+  // Buffers on a render node never get scanned out directly, they go through `Prime`
+  fn on_node(path: &Path, width: u32, height: u32) -> Option<Renderer> {
+    let node = OpenOptions::new().read(true).write(true).open(path).ok()?;
+    let egl_device = EglDevice::for_file(&node);
+    let fd = node.as_raw_fd();
+    Renderer::new(
+      fd,
+      Some(node),
+      egl_device,
+      gbm_bo_flags::GBM_BO_USE_RENDERING,
+      width,
+      height,
+    )
+  }
+
+  // `fd` stays open as long as the renderer: it's the card's, or `node`'s
+  fn new(
+    fd: RawFd,
+    node: Option<File>,
+    egl_device: Option<EglDevice>,
+    flags: u32,
+    width: u32,
+    height: u32,
+  ) -> Option<Renderer> {
+    let gbm_device = GbmDevice::new(unsafe { BorrowedFd::borrow_raw(fd) }).ok()?;
+
+    let gbm_surface = gbm_device
+      .create_surface(width, height, GBM_FORMAT, flags)
+      .ok()?
+      .into_raw();
+    let gbm_surface = unsafe { GbmSurface::from_raw(gbm_surface) };
+
+    // Rendering needs a window surface on the GBM surface, displays that can't make one (or
+    // have no fitting config) are skipped. So are displays rendering on another GPU than the
+    // node's (Mesa may pick one behind our back), when EGL can tell.
+    let (display, config, surface) = egl_platform::candidates(gbm_device.as_raw() as *mut c_void)
+      .into_iter()
+      .find_map(|(platform, display)| {
+        let display = egl_platform::initialize(platform, display)?;
+        let on_device = match (&egl_device, EglDevice::for_display(display.display)) {
+          (Some(expected), Some(device)) => *expected == device,
+          _ => true,
+        };
+        let surface = match on_device {
+          true => window_surface(&display, gbm_surface.as_raw() as *mut c_void),
+          false => None,
+        };
+        match surface {
+          Some((config, surface)) => Some((display, config, surface)),
+          None => {
+            display.terminate();
+            None
+          }
+        }
+      })?;
+    let egl_device = egl_device.or_else(|| EglDevice::for_display(display.display));
+
+    Some(Renderer {
+      display,
+      egl_device,
+      config,
+      surface,
+      gbm_surface,
+      gbm_device,
+      node,
+    })
+  }
 }
 
 pub struct Context {
-  device: File,
   mode: drm::DRMModeModeInfo,
   connector_id: u32,
   crtc: drm::DRMModeCrtc,
-  gbm_device: *mut gbm::RawDevice,
-  gbm_surface: *mut gbm::RawSurface,
+  // Index of the CRTC, as vblank requests want it
+  pipe: u32,
+  display_platform: DisplayPlatform,
+  egl_device: Option<EglDevice>,
   egl_major: i32,
   egl_minor: i32,
   egl_display: egl::EGLDisplay,
+  egl_config: egl::EGLConfig,
   egl_context: egl::EGLContext,
   egl_surface: egl::EGLSurface,
 
-  previous_bo: *mut gbm::RawBO,
-  previous_fb: u32,
+  present_mode: PresentMode,
+  async_flips: bool,
+  needs_modeset: bool,
+  // On screen, flip submitted, waiting behind the submitted flip (mailbox)
+  scanout: Option<Frame>,
+  pending: Option<Frame>,
+  queued: Option<Frame>,
+
+  frame_count: u64,
+  tracker: FeedbackTracker,
+  feedback: Vec<PresentationFeedback>,
+
+  vblank_requested: bool,
+  vblanks: Vec<VblankInfo>,
+
+  // Framebuffers of `ScanoutBuffer`s that went off screen
+  released: Vec<u32>,
+  // Framebuffer and GEM handles of every `ScanoutBuffer` not removed yet, released on drop
+  scanout_buffers: HashMap<u32, Vec<u32>>,
+  // Hands frames to the card when rendering on a render node
+  prime: Option<Prime>,
+
+  restore_guard: Option<RestoreGuard>,
+
+  active: bool,
+  session: Session,
+
+  // Fields drop in declaration order: the locked buffers above, then the surface, the device and
+  // last the fd they borrow, hence `'static`
+  gbm_surface: GbmSurface<'static>,
+  gbm_device: GbmDevice<'static>,
+  render_node: Option<File>,
+  card: Card,
 }
 
 impl Context {
+  /// Panics if the card or EGL can't be set up, or with `dlopen` when a library or symbol is
+  /// missing. `try_new` reports the latter instead.
   pub fn new() -> Self {
-    let device = open_card("/dev/dri/by-path/platform-gpu-card");
-
-    let connector_id;
-    let mode;
-    let crtc;
-    {
-      let resources = drm::mode_get_resources(&device).expect("Couldn't get DRM Mode Resources");
+    Context::with_session(Box::new(DirectSession))
+  }
 
-      let connector = drm::find_connector(&device, &resources).expect("No connector found");
+  /// `new`, but a missing library or symbol is an error instead of a panic.
+  #[cfg(feature = "dlopen")]
+  pub fn try_new() -> Result<Self, crate::loader::Error> {
+    crate::loader::probe()?;
+    return Ok(Self::new());
+  }
 
-      connector_id = connector.connector_id;
-      mode = (&connector.modes[0]).copy();
-      let encoder = drm::find_encoder(&device, &connector).expect("No encoder found");
+  /// `with_session`, but a missing library or symbol is an error instead of a panic.
+  #[cfg(feature = "dlopen")]
+  pub fn try_with_session(session: Box<dyn SessionBackend>) -> Result<Self, crate::loader::Error> {
+    crate::loader::probe()?;
+    return Ok(Self::with_session(session));
+  }
 
-      crtc = drm::mode_get_crtc(&device, encoder.crtc_id);
-    }
+  /// `with_render_device`, but a missing library or symbol is an error instead of a panic.
+  #[cfg(feature = "dlopen")]
+  pub fn try_with_render_device(
+    session: Box<dyn SessionBackend>,
+    render_device: RenderDevice,
+  ) -> Result<Self, crate::loader::Error> {
+    crate::loader::probe()?;
+    return Ok(Self::with_render_device(session, render_device));
+  }
 
-    let gbm_device = gbm::create_device(&device);
+  /// Opens the card through a seat manager (`LogindSession`, `LibseatSession`) instead of
+  /// directly, so no root or `video` group membership is needed. Presentation stops while the
+  /// session is paused (e.g. VT switch).
+  pub fn with_session(session: Box<dyn SessionBackend>) -> Self {
+    Context::with_render_device(session, RenderDevice::Auto)
+  }
 
-    let gbm_surface = gbm::surface_create(
-      gbm_device,
-      mode.hdisplay as u32,
-      mode.vdisplay as u32,
-      GBM_FORMAT,
-      gbm_bo_flags::GBM_BO_USE_SCANOUT | gbm_bo_flags::GBM_BO_USE_RENDERING,
-    );
+  /// Like `with_session`, rendering on `render_device`. Frames rendered on a render node are
+  /// handed to the card through PRIME, see `set_prime_mode`.
+  pub fn with_render_device(
+    mut session: Box<dyn SessionBackend>,
+    render_device: RenderDevice,
+  ) -> Self {
+    #[cfg(feature = "dlopen")]
+    crate::loader::probe().unwrap_or_else(|error| panic!("{}", error));
 
-    let egl_display = egl::get_display(gbm_device as *mut c_void).expect("Couldn't get display");
+    let device = session
+      .open_device(Path::new(CARD_PATH))
+      .expect("Couldn't open device");
 
-    let mut egl_major = 0i32;
-    let mut egl_minor = 0i32;
-    if !egl::initialize(egl_display, &mut egl_major, &mut egl_minor) {
-      panic!("Couldn't initialize egl")
-    }
+    let mut context = Context::with_device(device, render_device, Session::new(session));
+    context.active = context.session.backend().is_active();
 
-    if !egl::bind_api(egl::EGL_OPENGL_ES_API) {
-      panic!("Couldn't bind API");
-    }
+    return context;
+  }
 
-    let egl_configs = choose_config(egl_display, &ATTRIBUTES).expect("Couldn't choose config");
+  fn with_device(device: File, render_device: RenderDevice, session: Session) -> Self {
+    let Output {
+      connector_id,
+      mode,
+      crtc,
+      pipe,
+    } = Output::probe(&device);
 
-    let egl_config = match_config_to_visual(egl_display, GBM_FORMAT as i32, egl_configs)
-      .expect("Could't match visual");
+    let width = mode.hdisplay as u32;
+    let height = mode.vdisplay as u32;
+    let renderer = match &render_device {
+      RenderDevice::Card => Renderer::on_card(&device, width, height),
+      RenderDevice::Node(path) => Renderer::on_node(path, width, height),
+      // The card's own EGL device, or else the first other GPU with a render node
+      RenderDevice::Auto => Renderer::on_card(&device, width, height).or_else(|| {
+        let card = EglDevice::for_file(&device);
+        EglDevice::enumerate()
+          .into_iter()
+          .filter(|candidate| card.as_ref() != Some(candidate))
+          .find_map(|candidate| Renderer::on_node(candidate.render_node()?, width, height))
+      }),
+    }
+    .expect("Couldn't set up rendering");
+    let Renderer {
+      display,
+      egl_device,
+      config: egl_config,
+      surface: egl_surface,
+      gbm_surface,
+      gbm_device,
+      node: render_node,
+    } = renderer;
+    let egl_display = display.display;
+    let prime = render_node.as_ref().map(|_| Prime::new(PrimeMode::Import));
 
     let egl_context = egl::create_context(
       egl_display,
@@ -112,37 +330,139 @@ impl Context {
     )
     .expect("Couldn't create context");
 
-    let egl_surface = egl::create_window_surface(
-      egl_display,
-      egl_config,
-      gbm_surface as egl::EGLNativeDisplayType,
-      &[],
-    )
-    .expect("Couldn't create window surface");
-
     egl::make_current(egl_display, egl_surface, egl_surface, egl_context);
 
     return Context {
-      device,
       mode,
       connector_id,
       crtc,
-      gbm_device,
-      gbm_surface,
-      egl_major,
-      egl_minor,
+      pipe,
+      display_platform: display.platform,
+      egl_device,
+      egl_major: display.major,
+      egl_minor: display.minor,
       egl_display,
+      egl_config,
       egl_context,
       egl_surface,
-      previous_bo: ptr::null_mut(),
-      previous_fb: 0,
+      present_mode: PresentMode::Fifo,
+      async_flips: true,
+      needs_modeset: true,
+      scanout: None,
+      pending: None,
+      queued: None,
+      frame_count: 0,
+      tracker: FeedbackTracker::default(),
+      feedback: Vec::new(),
+      vblank_requested: false,
+      vblanks: Vec::new(),
+      released: Vec::new(),
+      scanout_buffers: HashMap::new(),
+      prime,
+      restore_guard: None,
+      active: true,
+      session,
+      gbm_surface,
+      gbm_device,
+      render_node,
+      card: Card::from_file(device),
     };
   }
 
+  /// Restores the display configuration found at startup even if the process is killed by
+  /// SIGINT, SIGTERM, SIGHUP, SIGQUIT or SIGABRT, or panics with `panic = "abort"`. Signals the
+  /// application handles or ignores are passed on without restoring anything.
+  /// The guard is removed when the context is dropped.
+  pub fn install_restore_guard(&mut self) -> io::Result<()> {
+    if self.restore_guard.is_none() {
+      self.restore_guard = Some(RestoreGuard::install(
+        self.card.as_raw_fd(),
+        &self.crtc,
+        self.connector_id,
+      )?);
+    }
+    Ok(())
+  }
+
+  pub fn remove_restore_guard(&mut self) {
+    self.restore_guard = None;
+  }
+
+  /// The DRM device, to look at the display configuration or drive other CRTCs and planes.
+  pub fn card(&self) -> &Card {
+    &self.card
+  }
+
+  /// For buffers of its own next to the context's (e.g. another `GbmSurface`).
+  pub fn gbm_device(&self) -> &GbmDevice<'_> {
+    &self.gbm_device
+  }
+
+  /// Which way the EGL display was obtained, `Gbm` unless the driver lacks the platform
+  /// extensions.
+  pub fn display_platform(&self) -> DisplayPlatform {
+    self.display_platform
+  }
+
+  /// The EGL device rendering for the context, `None` when the driver doesn't say. It's the
+  /// card's whenever EGL lists one for the card and rendering happens there.
+  pub fn egl_device(&self) -> Option<&EglDevice> {
+    self.egl_device.as_ref()
+  }
+
+  /// Whether rendering happens on a render node instead of the card.
+  pub fn renders_on_node(&self) -> bool {
+    self.render_node.is_some()
+  }
+
+  /// How frames get from the render node to the card, `None` when rendering on the card.
+  /// With `Import`, frames the card rejects are copied instead, see `take_prime_error`.
+  pub fn prime_mode(&self) -> Option<PrimeMode> {
+    self.prime.as_ref().map(Prime::mode)
+  }
+
+  /// Why the card last rejected a frame from the render node, if it did since the last call.
+  /// Such frames were copied instead, and import is retried every now and then.
+  pub fn take_prime_error(&mut self) -> Option<io::Error> {
+    self.prime.as_mut().and_then(Prime::take_error)
+  }
+
+  /// No effect when rendering on the card.
+  pub fn set_prime_mode(&mut self, mode: PrimeMode) {
+    if let Some(prime) = self.prime.as_mut() {
+      prime.set_mode(mode);
+    }
+  }
+
   pub fn egl_version(&self) -> (i32, i32) {
     (self.egl_major, self.egl_minor)
   }
 
+  pub(crate) fn egl_display(&self) -> egl::EGLDisplay {
+    self.egl_display
+  }
+
+  pub(crate) fn egl_config(&self) -> egl::EGLConfig {
+    self.egl_config
+  }
+
+  pub(crate) fn egl_context(&self) -> egl::EGLContext {
+    self.egl_context
+  }
+
+  /// Every config of the EGL display, e.g. to see why a format or multisampling isn't there.
+  pub fn configs(&self) -> Vec<EglConfigInfo> {
+    egl_utils::get_configs(self.egl_display)
+      .into_iter()
+      .map(|config| EglConfigInfo::query(self.egl_display, config))
+      .collect()
+  }
+
+  /// The config the display surface was made with.
+  pub fn config(&self) -> EglConfigInfo {
+    EglConfigInfo::query(self.egl_display, self.egl_config)
+  }
+
   #[inline(always)]
   pub fn width(&self) -> u32 {
     self.mode.hdisplay as u32
@@ -153,59 +473,562 @@ impl Context {
     self.mode.vdisplay as u32
   }
 
-  pub fn swap_buffers(&mut self) {
-    egl::swap_buffers(self.egl_display, self.egl_surface);
-    let bo = gbm::surface_lock_front_buffer(self.gbm_surface);
-    let fb: u32 = 0;
-    drm::mode_add_fb(
-      &self.device,
-      self.mode.hdisplay as u32,
-      self.mode.vdisplay as u32,
-      24,
-      32,
-      gbm::bo_get_stride(bo),
-      gbm::bo_get_handle_u32(bo),
-      &fb,
+  /// Directs GL rendering back to the display, after using an `OffscreenTarget`.
+  pub fn make_current(&self) {
+    egl::make_current(
+      self.egl_display,
+      self.egl_surface,
+      self.egl_surface,
+      self.egl_context,
     );
-    drm::mode_set_crtc(
-      &self.device,
+  }
+
+  /// Same pixel format and GL context as the display surface, so textures and programs can be
+  /// shared with it.
+  pub fn create_offscreen_target(&self, width: u32, height: u32) -> OffscreenTarget<'_> {
+    OffscreenTarget::new(self, width, height, GBM_FORMAT)
+  }
+
+  /// The frame currently on screen, as dmabufs. No new frame can be presented until the
+  /// returned frame is dropped.
+  pub fn export_frame(&mut self) -> io::Result<DmabufFrame<'_>> {
+    match &self.scanout {
+      Some(Frame {
+        buffer: Some(buffer),
+        ..
+      }) => dmabuf::export_bo(buffer),
+      Some(_) => Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "An imported buffer is on screen",
+      )),
+      None => Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "Nothing presented yet",
+      )),
+    }
+  }
+
+  /// A buffer to render into with GL (through a framebuffer object) and share with other
+  /// processes, same pixel format as the display surface.
+  pub fn create_render_buffer(&self, width: u32, height: u32) -> io::Result<RenderBuffer<'_>> {
+    RenderBuffer::new(self, width, height, GBM_FORMAT)
+  }
+
+  /// A mapped buffer to draw into with the CPU, see `LinearBuffer`.
+  #[cfg(feature = "embedded-graphics")]
+  pub fn create_linear_buffer(
+    &self,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+  ) -> io::Result<LinearBuffer<'_>> {
+    LinearBuffer::new(self.gbm_device(), width, height, format)
+  }
+
+  /// Wraps a dmabuf (e.g. received from another process) in a KMS framebuffer, for
+  /// `present_buffer`. Remove it with `remove_scanout_buffer` once it's off screen.
+  pub fn import_scanout_buffer(&mut self, desc: &DmabufDesc) -> io::Result<ScanoutBuffer> {
+    if desc.planes.is_empty() || desc.planes.len() > 4 {
+      return Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "A dmabuf has 1 to 4 planes",
+      ));
+    }
+
+    let mut buffer = ScanoutBuffer {
+      fb: 0,
+      handles: Vec::with_capacity(desc.planes.len()),
+      width: desc.width,
+      height: desc.height,
+    };
+    let mut layout = DRMFramebufferLayout {
+      width: desc.width,
+      height: desc.height,
+      pixel_format: desc.fourcc,
+      modifier: if desc.modifier == DRM_FORMAT_MOD_INVALID {
+        None
+      } else {
+        Some(desc.modifier)
+      },
+      ..Default::default()
+    };
+    for (i, plane) in desc.planes.iter().enumerate() {
+      let handle = match drm::prime_fd_to_handle(self.card.file(), plane.fd) {
+        Ok(handle) => handle,
+        Err(error) => {
+          self.remove_scanout_buffer(buffer);
+          return Err(error);
+        }
+      };
+      // Planes sharing a dmabuf get the same handle, which must only be closed once
+      if !buffer.handles.contains(&handle) {
+        buffer.handles.push(handle);
+      }
+      layout.handles[i] = handle;
+      layout.pitches[i] = plane.stride;
+      layout.offsets[i] = plane.offset;
+    }
+
+    match drm::mode_add_fb2(self.card.file(), &layout) {
+      Ok(fb) => {
+        buffer.fb = fb;
+        self.scanout_buffers.insert(fb, buffer.handles.clone());
+      }
+      Err(error) => {
+        self.remove_scanout_buffer(buffer);
+        return Err(error);
+      }
+    }
+
+    Ok(buffer)
+  }
+
+  /// Only once it's off screen, i.e. its framebuffer came out of `take_released_buffers`.
+  pub fn remove_scanout_buffer(&mut self, buffer: ScanoutBuffer) {
+    self.scanout_buffers.remove(&buffer.fb);
+    self.release_scanout_buffer(buffer.fb, &buffer.handles);
+  }
+
+  fn release_scanout_buffer(&self, fb: u32, handles: &[u32]) {
+    if fb != 0 {
+      drm::mode_rm_fb(self.card.file(), fb);
+    }
+    for handle in handles.iter() {
+      drm_ioctl::gem_close(self.card.as_raw_fd(), *handle);
+    }
+  }
+
+  /// Like `queue_frame`, for a buffer rendered elsewhere. It has to be the size of the display
+  /// mode. Its framebuffer shows up in `take_released_buffers` once it's off screen again (or
+  /// was dropped in favor of a newer one).
+  pub fn present_buffer(&mut self, buffer: &ScanoutBuffer) -> Option<PresentationFeedback> {
+    self.dispatch_session();
+
+    let number = self.frame_count;
+    self.frame_count += 1;
+
+    let frame = Frame {
+      buffer: None,
+      fb: buffer.fb,
+      number,
+      handles: Vec::new(),
+      copied: false,
+    };
+
+    if !self.active {
+      self.retire(Some(frame));
+      self.needs_modeset = true;
+      return None;
+    }
+    if self.needs_modeset {
+      return self.modeset(frame);
+    }
+    self.submit(frame);
+
+    None
+  }
+
+  /// Whether the buffer is on screen or about to be.
+  pub fn is_buffer_in_use(&self, buffer: &ScanoutBuffer) -> bool {
+    [&self.scanout, &self.pending, &self.queued]
+      .iter()
+      .any(|frame| matches!(frame, Some(frame) if frame.buffer.is_none() && frame.fb == buffer.fb))
+  }
+
+  /// Framebuffers of the `ScanoutBuffer`s that can be reused or removed, oldest first.
+  pub fn take_released_buffers(&mut self) -> Vec<u32> {
+    std::mem::take(&mut self.released)
+  }
+
+  /// Zero-copy import of a dmabuf (e.g. a camera or decoder frame) as a GL texture, with the
+  /// layout given by `desc.modifier` when the driver supports explicit modifiers.
+  pub fn import_dmabuf(&self, desc: DmabufDesc) -> io::Result<ImportedImage<'_>> {
+    ImportedImage::import(self, &desc)
+  }
+
+  /// Whether frames reach the screen. Stays `true` unless the session backend paused us.
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+
+  fn dispatch_session(&mut self) {
+    self.active = self.session.dispatch();
+  }
+
+  /// Session events seen since the last call, oldest first. Drain it when opening other devices
+  /// through `session_mut`, to get their new fds after a pause.
+  pub fn take_session_events(&mut self) -> Vec<SessionEvent> {
+    self.session.take_events()
+  }
+
+  pub fn session(&self) -> &dyn SessionBackend {
+    self.session.backend()
+  }
+
+  /// For opening input devices through the same seat.
+  pub fn session_mut(&mut self) -> &mut dyn SessionBackend {
+    self.session.backend_mut()
+  }
+
+  pub fn present_mode(&self) -> PresentMode {
+    self.present_mode
+  }
+
+  /// Fifo blocks on page flip completion, Immediate uses `DRM_MODE_PAGE_FLIP_ASYNC` and Mailbox
+  /// keeps the latest frame queued behind the pending flip.
+  pub fn set_present_mode(&mut self, present_mode: PresentMode) {
+    self.present_mode = present_mode;
+  }
+
+  fn retire(&mut self, frame: Option<Frame>) {
+    match frame {
+      Some(frame) if frame.buffer.is_none() => self.released.push(frame.fb),
+      // Dropping the frame hands its buffer back to the surface
+      Some(frame) => {
+        if !frame.copied {
+          drm::mode_rm_fb(self.card.file(), frame.fb);
+        }
+        prime::close_handles(self.card.file(), &frame.handles);
+      }
+      None => {}
+    }
+  }
+
+  fn flip(&mut self, frame: Frame) {
+    let mut flags = drm::DRM_MODE_PAGE_FLIP_EVENT;
+    if self.present_mode == PresentMode::Immediate && self.async_flips {
+      flags |= drm::DRM_MODE_PAGE_FLIP_ASYNC;
+    }
+
+    let mut ret = drm::mode_page_flip(self.card.file(), self.crtc.crtc_id, frame.fb, flags, 0);
+    if ret != 0 && (flags & drm::DRM_MODE_PAGE_FLIP_ASYNC) != 0 {
+      // Not every driver does async flips
+      self.async_flips = false;
+      ret = drm::mode_page_flip(
+        self.card.file(),
+        self.crtc.crtc_id,
+        frame.fb,
+        drm::DRM_MODE_PAGE_FLIP_EVENT,
+        0,
+      );
+    }
+
+    if ret != 0 {
+      // Nothing in flight then, just forget about this one
+      println!("Error flipping page: {}", io::Error::last_os_error());
+      self.retire(Some(frame));
+      return;
+    }
+
+    self.pending = Some(frame);
+  }
+
+  /// Processes page flip completions and vblank events, waiting for one if `block` is set.
+  /// Returns whether there was anything to read.
+  fn handle_events(&mut self, block: bool) -> bool {
+    if !drm::poll_events(self.card.file(), if block { -1 } else { 0 })
+      .expect("Couldn't poll device")
+    {
+      return false;
+    }
+
+    for event in drm::read_events(self.card.file()).expect("Couldn't read DRM events") {
+      match event {
+        drm::DRMEvent::FlipComplete(vblank) => {
+          if let Some(frame) = self.pending.take() {
+            let feedback = self.presented(
+              frame.number,
+              vblank.sequence,
+              Duration::new(vblank.tv_sec as u64, vblank.tv_usec * 1000),
+            );
+            if self.feedback.len() == MAX_FEEDBACK {
+              self.feedback.remove(0);
+            }
+            self.feedback.push(feedback);
+
+            let previous = self.scanout.replace(frame);
+            self.retire(previous);
+          }
+
+          if let Some(next) = self.queued.take() {
+            self.flip(next);
+          }
+        }
+        drm::DRMEvent::VBlank(vblank) => {
+          self.vblank_requested = false;
+          self.vblanks.push(VblankInfo {
+            sequence: vblank.sequence,
+            timestamp: Duration::new(vblank.tv_sec as u64, vblank.tv_usec * 1000),
+          });
+        }
+      }
+    }
+
+    true
+  }
+
+  fn presented(&mut self, frame: u64, sequence: u32, timestamp: Duration) -> PresentationFeedback {
+    let refresh = self.mode.refresh_interval();
+    self.tracker.presented(frame, sequence, timestamp, refresh)
+  }
+
+  /// Feedback for frames that reached the screen after their `swap_buffers` returned (Immediate
+  /// and Mailbox modes), oldest first. Only the last 64 are kept, drain it regularly when
+  /// using those modes.
+  pub fn take_feedback(&mut self) -> Vec<PresentationFeedback> {
+    std::mem::take(&mut self.feedback)
+  }
+
+  // No feedback when the vblank counter can't be read
+  fn modeset(&mut self, frame: Frame) -> Option<PresentationFeedback> {
+    // Whatever was in flight won't make it to the screen
+    self.handle_events(false);
+    let pending = self.pending.take();
+    self.retire(pending);
+    let queued = self.queued.take();
+    self.retire(queued);
+
+    let number = frame.number;
+    if drm::mode_set_crtc(
+      self.card.file(),
       self.crtc.crtc_id,
-      fb,
+      frame.fb,
       0,
       0,
-      vec![self.connector_id],
-      &self.mode.raw,
-    );
+      &[self.connector_id],
+      Some(&self.mode.raw),
+    ) != 0
+    {
+      // E.g. master lost, the frame never shows and the next one tries again
+      println!("Error setting mode: {}", io::Error::last_os_error());
+      self.retire(Some(frame));
+      self.needs_modeset = true;
+      return None;
+    }
+
+    let previous = self.scanout.replace(frame);
+    self.retire(previous);
+    self.needs_modeset = false;
 
-    if !self.previous_bo.is_null() {
-      drm::mode_rm_fb(&self.device, self.previous_fb);
-      gbm::surface_release_buffer(self.gbm_surface, self.previous_bo);
+    match output::last_vblank(self.card.file(), self.pipe) {
+      Ok((sequence, timestamp)) => Some(self.presented(number, sequence, timestamp)),
+      Err(error) => {
+        println!("Error reading vblank counter: {}", error);
+        self.tracker.reset();
+        None
+      }
     }
-    self.previous_bo = bo;
-    self.previous_fb = fb;
+  }
+
+  // Hands the rendered frame over to GBM/KMS, `None` while the session is paused
+  fn lock_frame(&mut self) -> Option<Frame> {
+    self.dispatch_session();
+
+    let number = self.frame_count;
+    self.frame_count += 1;
+
+    egl::swap_buffers(self.egl_display, self.egl_surface);
+    let buffer = self
+      .gbm_surface
+      .lock_front_buffer()
+      .expect("Couldn't lock front buffer");
+
+    if !self.active {
+      // Keep the surface going (the buffer goes right back), but stay away from the device until
+      // we get it back
+      self.needs_modeset = true;
+      return None;
+    }
+
+    let (fb, handles, copied) = match self.prime.as_mut() {
+      None => {
+        let fb = drm::mode_add_fb(
+          self.card.file(),
+          self.mode.hdisplay as u32,
+          self.mode.vdisplay as u32,
+          24,
+          32,
+          buffer.stride(),
+          buffer.handle(),
+        )
+        .expect("Couldn't add framebuffer");
+        (fb, Vec::new(), false)
+      }
+      Some(prime) => {
+        let in_use: Vec<u32> = [&self.scanout, &self.pending, &self.queued]
+          .iter()
+          .filter_map(|frame| frame.as_ref().map(|frame| frame.fb))
+          .collect();
+        let frame = prime
+          .import(self.card.file(), &buffer, &in_use)
+          .expect("Couldn't hand frame to the card");
+        (frame.fb, frame.handles, frame.copied)
+      }
+    };
+
+    // Kept next to the surface it comes from, see the field order
+    let buffer = unsafe { FrontBuffer::from_raw(self.gbm_surface.as_raw(), buffer.into_raw()) };
+    Some(Frame {
+      buffer: Some(buffer),
+      fb,
+      number,
+      handles,
+      copied,
+    })
+  }
+
+  // Flips right away if nothing is in flight, replaces the queued frame otherwise
+  fn submit(&mut self, frame: Frame) {
+    self.handle_events(false);
+    if self.pending.is_none() {
+      self.flip(frame);
+    } else {
+      let replaced = self.queued.replace(frame);
+      self.retire(replaced);
+    }
+  }
+
+  /// Returns the presentation feedback of this frame when it was already shown by the time this
+  /// returns (Fifo mode, or the first frame), `take_feedback` delivers it otherwise.
+  pub fn swap_buffers(&mut self) -> Option<PresentationFeedback> {
+    let frame = self.lock_frame()?;
+    let number = frame.number;
+
+    if self.needs_modeset {
+      return self.modeset(frame);
+    }
+
+    match self.present_mode {
+      PresentMode::Fifo | PresentMode::Immediate => {
+        // One flip in flight at a time
+        while self.pending.is_some() {
+          self.handle_events(true);
+        }
+        self.flip(frame);
+        if self.present_mode == PresentMode::Fifo {
+          while self.pending.is_some() {
+            self.handle_events(true);
+          }
+          let shown = self.feedback.iter().position(|f| f.frame == number);
+          return shown.map(|i| self.feedback.remove(i));
+        }
+      }
+      PresentMode::Mailbox => self.submit(frame),
+    }
+
+    None
+  }
+
+  /// Non-blocking `swap_buffers` for event loops: the frame is flipped when the previous flip
+  /// completes, replacing any frame still waiting for it. Its feedback comes out of
+  /// `dispatch_events` once the device fd (see `AsRawFd`) becomes readable, or is returned right
+  /// away when the frame was shown by a modeset (first frame, or after the session resumed).
+  /// Nothing is presented while the session is paused.
+  pub fn queue_frame(&mut self) -> Option<PresentationFeedback> {
+    let frame = self.lock_frame()?;
+
+    if self.needs_modeset {
+      return self.modeset(frame);
+    }
+    self.submit(frame);
+
+    None
+  }
+
+  /// Whether a frame is flipping or waiting for a flip, i.e. the next `queue_frame` would
+  /// replace a frame that never made it to the screen.
+  pub fn frame_pending(&self) -> bool {
+    self.pending.is_some() || self.queued.is_some()
+  }
+
+  /// Processes page flip completions without blocking and returns the feedback of the frames
+  /// that reached the screen, oldest first. Call when the device fd is readable.
+  pub fn dispatch_events(&mut self) -> Vec<PresentationFeedback> {
+    self.process_events();
+
+    self.take_feedback()
+  }
+
+  /// Readable when the session has news (e.g. a VT switch), call `dispatch_events` then, as for
+  /// the device fd. `None` for sessions without notifications.
+  pub fn session_fd(&self) -> Option<RawFd> {
+    self.session.backend().fd()
+  }
+
+  // Same as `dispatch_events`, leaving feedback and vblanks for the caller to take
+  pub(crate) fn process_events(&mut self) -> bool {
+    self.dispatch_session();
+    self.handle_events(false)
+  }
+
+  /// Asks for an event at the next vblank, it shows up in `take_vblanks` once the device fd
+  /// becomes readable and `dispatch_events` ran. Does nothing if one is already on its way.
+  pub fn request_vblank(&mut self) -> io::Result<()> {
+    if self.vblank_requested {
+      return Ok(());
+    }
+    if drm::wait_vblank_event(self.card.file(), self.pipe, 0) != 0 {
+      return Err(io::Error::last_os_error());
+    }
+    self.vblank_requested = true;
+    Ok(())
+  }
+
+  /// Vblanks received since the last call, oldest first.
+  pub fn take_vblanks(&mut self) -> Vec<VblankInfo> {
+    std::mem::take(&mut self.vblanks)
+  }
+}
+
+/// The DRM device, readable when page flips complete.
+impl AsRawFd for Context {
+  fn as_raw_fd(&self) -> RawFd {
+    self.card.as_raw_fd()
+  }
+}
+
+impl AsFd for Context {
+  fn as_fd(&self) -> BorrowedFd<'_> {
+    self.card.as_fd()
   }
 }
 
 impl Drop for Context {
   fn drop(&mut self) {
+    self.restore_guard = None;
+
+    // Let the last flip land before pulling buffers from under it
+    while self.pending.is_some() && self.active {
+      self.handle_events(true);
+    }
+
     drm::mode_set_crtc(
-      &self.device,
+      self.card.file(),
       self.crtc.crtc_id,
       self.crtc.buffer_id,
       self.crtc.x,
       self.crtc.y,
-      vec![self.connector_id],
-      unsafe { &(*self.crtc.raw).mode },
+      &[self.connector_id],
+      Some(&self.crtc.mode.raw),
     );
-    drm::mode_free_crtc(&mut self.crtc);
 
-    if !(*self).previous_bo.is_null() {
-      drm::mode_rm_fb(&self.device, self.previous_fb);
-      gbm::surface_release_buffer(self.gbm_surface, self.previous_bo);
+    let frames = vec![self.scanout.take(), self.pending.take(), self.queued.take()];
+    for frame in frames {
+      self.retire(frame);
+    }
+    if let Some(prime) = self.prime.as_mut() {
+      prime.destroy(self.card.file());
+    }
+    // Imported buffers the caller didn't get to remove
+    for (fb, handles) in std::mem::take(&mut self.scanout_buffers) {
+      self.release_scanout_buffer(fb, &handles);
     }
 
-    gbm::surface_destroy(self.gbm_surface);
+    // The GBM surface and device go with the fields
     egl::destroy_context(self.egl_display, self.egl_context);
-    gbm::device_destroy(self.gbm_device);
+
+    self
+      .session
+      .close_device(Path::new(CARD_PATH), self.card.file());
   }
 }